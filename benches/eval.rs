@@ -0,0 +1,71 @@
+// Times one formula over many rows with each way of evaluating it, run with "cargo bench".
+use std::time::{Duration, Instant};
+
+use f_ops::{BatchVm, Engine, EvalOutput, Object, Vm};
+
+
+const ROWS: usize = 200_000;
+const FORMULAS: [&str; 2] = ["a*x^2 + b*x + sqrt(abs(x)) - max(x, 0)/3", "a*x*x + b"];
+
+
+fn report(name: &str, elapsed: Duration, checksum: f64) {
+    let per_row = elapsed.as_nanos() as f64 / ROWS as f64;
+    println!("{name:<10} {elapsed:>12.2?} {per_row:>10.1} ns/row   checksum {checksum:.6}");
+}
+
+fn rows() -> impl Iterator<Item = f64> {
+    (0..ROWS).map(|i| i as f64 / 1000.0 - 100.0)
+}
+
+fn bench(engine: &mut Engine, formula: &str) {
+    println!("{formula}");
+    let compiled = engine.compile(formula).unwrap();
+    let start = Instant::now();
+    let mut tree_sum = 0.0;
+    for x in rows() {
+        engine.set_var("x", x);
+        match engine.run(&compiled) {
+            Ok(EvalOutput::Value(Object::Scalar(y))) => tree_sum += y,
+            other => panic!("Expected a scalar, got {other:?}"),
+        }
+    }
+    report("evaluate", start.elapsed(), tree_sum);
+
+    let slots = engine.compile_slots(formula, &["x"]).unwrap();
+    let start = Instant::now();
+    let slot_sum: f64 = rows().map(|x| slots.eval(&[x]).unwrap()).sum();
+    report("slots", start.elapsed(), slot_sum);
+
+    let program = engine.compile_program(formula, &["x"]).unwrap();
+    let mut vm = Vm::new();
+    let start = Instant::now();
+    let vm_sum: f64 = rows().map(|x| vm.run(&program, &mut [x]).unwrap()).sum();
+    report("vm", start.elapsed(), vm_sum);
+
+    let column: Vec<f64> = rows().collect();
+    let mut out = vec![0.0; ROWS];
+    let mut batch = BatchVm::new();
+    let start = Instant::now();
+    batch.run(&program, &[&column], &mut out).unwrap();
+    let elapsed = start.elapsed();
+    let batch_sum: f64 = out.iter().sum();
+    report("batch", elapsed, batch_sum);
+
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let start = Instant::now();
+    program.eval_columns_parallel(&[&column], &mut out, threads).unwrap();
+    let elapsed = start.elapsed();
+    let parallel_sum: f64 = out.iter().sum();
+    report("parallel", elapsed, parallel_sum);
+
+    assert!([slot_sum, vm_sum, batch_sum, parallel_sum].iter().all(|&sum| sum == tree_sum), "Checksums differ");
+}
+
+fn main() {
+    let mut engine: Engine = Engine::new();
+    engine.set_var("a", 1.5);
+    engine.set_var("b", -2.0);
+    for formula in FORMULAS {
+        bench(&mut engine, formula);
+    }
+}
@@ -1,37 +1,109 @@
-use std::collections::HashMap;
-use core::f32::consts;
+use std::{collections::HashMap, fmt::Display};
 
-pub struct Context {
-    vars: HashMap<String,f32>,
+use crate::{ast::Expr, evaluator::EvalError, function::Registry, number::{Number, Settings}, object::Object};
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Arity {
+    Exact(usize),
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Self::Exact(n) => count == n,
+            Self::Range(min, max) => (min..=max).contains(&count),
+            Self::AtLeast(min) => count >= min,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact(n) => write!(f, "{n}"),
+            Self::Range(min, max) => write!(f, "{min} to {max}"),
+            Self::AtLeast(min) => write!(f, "at least {min}"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct UserFunc {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+
+pub struct Context<N: Number = f64> {
+    vars: HashMap<String, Object<N>>,
+    funcs: HashMap<String, UserFunc>,
+    functions: Registry<N>,
+    // Turns off implicit multiplication, so "2x" and "x(2)" with x a variable are errors
+    strict: bool,
+    settings: Settings,
+}
+
+impl<N: Number> Default for Context<N> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl Context {
+impl<N: Number> Context<N> {
     pub fn new() -> Self {
-        let mut vars = HashMap::new();
-        vars.insert("pi".to_string(), consts::PI);
-        vars.insert("e".to_string(), consts::E);
+        Context{vars: HashMap::new(), funcs: HashMap::new(), functions: Registry::builtins(), strict: false, settings: Settings::default()}
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
 
-        Context{vars}
+    pub fn settings(&self) -> Settings {
+        self.settings
     }
 
-    pub fn var(&self, var_name: &str) -> Option<f32> {
-        self.vars.get(var_name).copied()
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    // Constants are built on lookup so they follow the current precision, but can be shadowed.
+    pub fn var(&self, var_name: &str) -> Option<Object<N>> {
+        match (self.vars.get(var_name), var_name) {
+            (Some(value), _) => Some(value.clone()),
+            (None, name) => N::constant(name).map(Object::Scalar),
+        }
     }
     
-    pub fn set_var(&mut self, var_name: &str, value: f32) {
+    pub fn set_var(&mut self, var_name: &str, value: Object<N>) {
 
         self.vars.insert(var_name.to_string(), value);
     }
 
-    pub fn call_func(&self, func_name: &str, arg: f32) -> Option<f32> {
-        match func_name {
-            "sqrt" => Some(arg.sqrt()),
-            "sin" => Some(arg.sin()),
-            "cos" => Some(arg.cos()),
-            "tan" => Some(arg.tan()),
-            "exp" => Some(arg.exp()),
-            "ln" => Some(arg.ln()),
-            _ => None,
-        }
+    pub fn user_func(&self, func_name: &str) -> Option<&UserFunc> {
+        self.funcs.get(func_name)
     }
-}
\ No newline at end of file
+
+    pub fn set_func(&mut self, func_name: &str, func: UserFunc) {
+        self.funcs.insert(func_name.to_string(), func);
+    }
+
+    pub fn functions(&self) -> &Registry<N> {
+        &self.functions
+    }
+
+    pub fn functions_mut(&mut self) -> &mut Registry<N> {
+        &mut self.functions
+    }
+
+    pub fn call_func(&self, func_name: &str, args: Vec<Object<N>>) -> Result<Object<N>, EvalError> {
+        self.functions.call(func_name, args)
+    }
+}
@@ -0,0 +1,375 @@
+use std::fmt::Display;
+use num_bigint::BigInt;
+
+use crate::{span::Span, tokens::{exact_f64, BinaryOp, PostfixOp, UnaryOp}};
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Literal(f64),
+    // Whole number an f64 cannot hold exactly
+    Integer(BigInt),
+    // Decimal literal an f64 cannot hold exactly, as written, every number type reads it itself
+    Decimal(String),
+    Var(String),
+    Unary(UnaryOp, Box<Expr>),
+    Postfix(PostfixOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    List(Vec<Expr>),
+    Index(Box<Expr>, Vec<Expr>),
+    // Values with their conditions, tried in order, and the value when none holds
+    Piecewise(Vec<(Expr, Expr)>, Option<Box<Expr>>),
+    Assign(String, Box<Expr>),
+    Define(String, Vec<String>, Box<Expr>),
+    // Where the wrapped expression came from in the input, used to locate errors
+    At(Span, Box<Expr>),
+}
+
+// Longest chain of left operands evaluated by recursing.
+pub(crate) const SHORT_CHAIN: usize = 16;
+
+// One operation of a chain of binary operations, with the span of the expression it completes.
+pub(crate) struct Link<'a> {
+    pub span: Option<Span>,
+    pub op: &'a BinaryOp,
+    pub rhs: &'a Expr,
+}
+
+impl Expr {
+    pub fn literal(x: f64) -> Self {
+        Self::Literal(x)
+    }
+
+    // A literal when the value is small enough to read back the same as an f64.
+    pub fn integer(x: BigInt) -> Self {
+        match exact_f64(&x) {
+            Some(x) => Self::Literal(x),
+            None => Self::Integer(x),
+        }
+    }
+
+    pub fn var(name: &str) -> Self {
+        Self::Var(name.to_string())
+    }
+
+    pub fn unary(op: UnaryOp, operand: Expr) -> Self {
+        Self::Unary(op, Box::new(operand))
+    }
+
+    pub fn postfix(op: PostfixOp, operand: Expr) -> Self {
+        Self::Postfix(op, Box::new(operand))
+    }
+
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        Self::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    pub fn call(name: &str, args: Vec<Expr>) -> Self {
+        Self::Call(name.to_string(), args)
+    }
+
+    pub fn list(items: Vec<Expr>) -> Self {
+        Self::List(items)
+    }
+
+    pub fn index(target: Expr, indices: Vec<Expr>) -> Self {
+        Self::Index(Box::new(target), indices)
+    }
+
+    pub fn piecewise(clauses: Vec<(Expr, Expr)>, otherwise: Option<Expr>) -> Self {
+        Self::Piecewise(clauses, otherwise.map(Box::new))
+    }
+
+    pub fn assign(name: &str, value: Expr) -> Self {
+        Self::Assign(name.to_string(), Box::new(value))
+    }
+
+    pub fn define(name: &str, params: &[&str], body: Expr) -> Self {
+        let params = params.iter().map(|param| param.to_string()).collect();
+        Self::Define(name.to_string(), params, Box::new(body))
+    }
+
+    pub fn at(span: Span, expr: Expr) -> Self {
+        Self::At(span, Box::new(expr))
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::At(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+
+    // The expression under any source positions.
+    pub fn inner(&self) -> &Expr {
+        match self {
+            Self::At(_, expr) => expr.inner(),
+            expr => expr,
+        }
+    }
+
+    // Follows the left operands of binary operations down from this one while "follow" accepts them. Returns the
+    // operand the chain starts with and its operations, innermost first: "1 + 2 - 3" is 1, then "+ 2" and "- 3".
+    // Long sums are walked with this in a loop, the parser does not count their left operands as nesting.
+    pub(crate) fn left_chain(&self, follow: impl Fn(&BinaryOp, &Expr) -> bool) -> (&Expr, Vec<Link<'_>>) {
+        let mut links = Vec::new();
+        let mut expr = self;
+        loop {
+            let (span, inner) = match expr {
+                Self::At(span, inner) => (Some(*span), inner.as_ref()),
+                expr => (None, expr),
+            };
+            let Self::Binary(op, lhs, rhs) = inner else {
+                break;
+            };
+            links.push(Link{span, op, rhs});
+            expr = lhs;
+            if !follow(op, lhs) {
+                break;
+            }
+        }
+        links.reverse();
+        (expr, links)
+    }
+
+    // Whether there are more than a few binary operations down the left operands, evaluation recurses into short
+    // chains as that is faster and only walks long ones with left_chain.
+    pub(crate) fn is_long_chain(&self) -> bool {
+        let mut expr = self;
+        for _ in 0..SHORT_CHAIN {
+            match expr.inner() {
+                Self::Binary(_, lhs, _) => expr = lhs,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    // Levels from here down to the deepest operand, spans not counted. Found without recursing, so it can guard
+    // the passes that still recurse into every operand.
+    pub fn height(&self) -> usize {
+        let mut height = 0;
+        let mut pending = vec![(self, 1)];
+        while let Some((expr, level)) = pending.pop() {
+            height = height.max(level);
+            let below = level + 1;
+            match expr {
+                Self::Literal(_) | Self::Integer(_) | Self::Decimal(_) | Self::Var(_) => (),
+                Self::Unary(_, operand) | Self::Postfix(_, operand) => pending.push((operand, below)),
+                Self::Binary(_, lhs, rhs) => pending.extend([(lhs.as_ref(), below), (rhs.as_ref(), below)]),
+                Self::Call(_, items) | Self::List(items) => pending.extend(items.iter().map(|item| (item, below))),
+                Self::Index(target, indices) => {
+                    pending.push((target, below));
+                    pending.extend(indices.iter().map(|index| (index, below)));
+                }
+                Self::Piecewise(clauses, otherwise) => {
+                    pending.extend(clauses.iter().flat_map(|(value, condition)| [(value, below), (condition, below)]));
+                    pending.extend(otherwise.iter().map(|otherwise| (otherwise.as_ref(), below)));
+                }
+                Self::Assign(_, value) | Self::Define(_, _, value) => pending.push((value, below)),
+                Self::At(_, expr) => pending.push((expr, level)),
+            }
+        }
+        height
+    }
+
+    // Drops source positions, for expressions kept beyond the line they were typed on.
+    pub fn without_spans(&self) -> Expr {
+        let strip = |exprs: &[Expr]| exprs.iter().map(Expr::without_spans).collect();
+        match self {
+            Self::Literal(_) | Self::Integer(_) | Self::Decimal(_) | Self::Var(_) => self.clone(),
+            Self::Unary(op, operand) => Self::unary(op.clone(), operand.without_spans()),
+            Self::Postfix(op, operand) => Self::postfix(op.clone(), operand.without_spans()),
+            Self::Binary(..) => {
+                let (first, links) = self.left_chain(|_, _| true);
+                links.into_iter().fold(first.without_spans(), |lhs, link| Self::binary(link.op.clone(), lhs, link.rhs.without_spans()))
+            }
+            Self::Call(name, args) => Self::Call(name.clone(), strip(args)),
+            Self::List(items) => Self::List(strip(items)),
+            Self::Index(target, indices) => Self::index(target.without_spans(), strip(indices)),
+            Self::Piecewise(clauses, otherwise) => {
+                map_clauses(clauses, otherwise, Expr::without_spans, Expr::without_spans)
+            }
+            Self::Assign(name, value) => Self::assign(name, value.without_spans()),
+            Self::Define(name, params, body) => Self::Define(name.clone(), params.clone(), Box::new(body.without_spans())),
+            Self::At(_, expr) => expr.without_spans(),
+        }
+    }
+
+    // Binding strength used when printing, higher binds tighter.
+    fn binding(&self) -> i32 {
+        match self {
+            Self::At(_, expr) => expr.binding(),
+            Self::Assign(..) | Self::Define(..) => -1,
+            Self::Binary(op, ..) => binary_binding(op),
+            Self::Unary(..) => 5,
+            Self::Literal(x) if x.is_sign_negative() => 5,
+            Self::Integer(x) if x.sign() == num_bigint::Sign::Minus => 5,
+            Self::Literal(_) | Self::Integer(_) | Self::Decimal(_) | Self::Var(_) | Self::Postfix(..) | Self::Call(..) | Self::List(_) | Self::Index(..) | Self::Piecewise(..) => 7,
+        }
+    }
+}
+
+
+// Rebuilds a piecewise expression with "value" applied to every value, including the last one, and "condition" to every condition.
+pub(crate) fn try_map_clauses<E>(
+    clauses: &[(Expr, Expr)],
+    otherwise: &Option<Box<Expr>>,
+    mut value: impl FnMut(&Expr) -> Result<Expr, E>,
+    mut condition: impl FnMut(&Expr) -> Result<Expr, E>,
+) -> Result<Expr, E> {
+    let clauses = clauses.iter()
+        .map(|(v, c)| Ok((value(v)?, condition(c)?)))
+        .collect::<Result<Vec<(Expr, Expr)>, E>>()?;
+    let otherwise = otherwise.as_ref().map(|otherwise| value(otherwise)).transpose()?;
+    Ok(Expr::piecewise(clauses, otherwise))
+}
+
+pub(crate) fn map_clauses(
+    clauses: &[(Expr, Expr)],
+    otherwise: &Option<Box<Expr>>,
+    mut value: impl FnMut(&Expr) -> Expr,
+    mut condition: impl FnMut(&Expr) -> Expr,
+) -> Expr {
+    let result: Result<Expr, std::convert::Infallible> = try_map_clauses(clauses, otherwise, |v| Ok(value(v)), |c| Ok(condition(c)));
+    result.unwrap_or_else(|never| match never {})
+}
+
+
+fn binary_binding(op: &BinaryOp) -> i32 {
+    match op {
+        BinaryOp::Or => 0,
+        BinaryOp::And => 1,
+        op if op.is_comparison() => 2,
+        BinaryOp::Add | BinaryOp::Sub => 3,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::FloorDiv => 4,
+        _ => 6,
+    }
+}
+
+// "^" groups to the right, everything else to the left
+fn left_parenthesized(op: &BinaryOp, lhs: &Expr) -> bool {
+    match op {
+        BinaryOp::Pow => lhs.binding() <= binary_binding(op),
+        _ => lhs.binding() < binary_binding(op),
+    }
+}
+
+fn right_parenthesized(op: &BinaryOp, rhs: &Expr) -> bool {
+    match op {
+        BinaryOp::Pow => rhs.binding() < binary_binding(op),
+        _ => rhs.binding() <= binary_binding(op),
+    }
+}
+
+fn write_list(f: &mut std::fmt::Formatter<'_>, items: &[Expr]) -> std::fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
+fn write_operand(f: &mut std::fmt::Formatter<'_>, expr: &Expr, parenthesize: bool) -> std::fmt::Result {
+    if parenthesize {
+        write!(f, "({expr})")
+    }
+    else {
+        write!(f, "{expr}")
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Literal(x) => write!(f, "{x}"),
+            Self::Integer(x) => write!(f, "{x}"),
+            Self::Decimal(text) => write!(f, "{text}"),
+            Self::Var(name) => write!(f, "{name}"),
+            Self::Unary(op, operand) => {
+                write!(f, "{}", match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                })?;
+                write_operand(f, operand, operand.binding() < 5)
+            }
+            Self::Postfix(op, operand) => {
+                write_operand(f, operand, operand.binding() < 7)?;
+                write!(f, "{}", op.symbol())
+            }
+            // Left operands written without brackets are followed in a loop, a long sum does not recurse per term
+            Self::Binary(..) => {
+                let (first, links) = self.left_chain(|op, lhs| !left_parenthesized(op, lhs));
+                write_operand(f, first, left_parenthesized(links[0].op, first))?;
+                for Link{op, rhs, ..} in links {
+                    // Operators looser than "*" and words are spaced out
+                    match binary_binding(op) < 4 || op.symbol().starts_with(char::is_alphabetic) {
+                        true => write!(f, " {} ", op.symbol())?,
+                        false => write!(f, "{}", op.symbol())?,
+                    }
+                    write_operand(f, rhs, right_parenthesized(op, rhs))?;
+                }
+                Ok(())
+            }
+            Self::Call(name, args) => {
+                write!(f, "{name}(")?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Self::List(items) => {
+                write!(f, "[")?;
+                write_list(f, items)?;
+                write!(f, "]")
+            }
+            Self::Index(target, indices) => {
+                write_operand(f, target, target.binding() < 7)?;
+                write!(f, "[")?;
+                write_list(f, indices)?;
+                write!(f, "]")
+            }
+            Self::Piecewise(clauses, otherwise) => {
+                write!(f, "{{ ")?;
+                for (i, (value, condition)) in clauses.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{value} if {condition}")?;
+                }
+                if let Some(otherwise) = otherwise {
+                    if !clauses.is_empty() {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{otherwise} otherwise")?;
+                }
+                write!(f, " }}")
+            }
+            Self::Assign(name, value) => write!(f, "{name} = {value}"),
+            Self::Define(name, params, body) => write!(f, "{name}({}) = {body}", params.join(", ")),
+            Self::At(_, expr) => expr.fmt(f),
+        }
+    }
+}
+
+
+#[test]
+fn test_display_0() {
+    let expr = Expr::binary(
+        BinaryOp::Mul,
+        Expr::binary(BinaryOp::Add, Expr::var("a"), Expr::literal(1.0)),
+        Expr::unary(UnaryOp::Neg, Expr::call("sin", vec![Expr::var("x")])),
+    );
+    assert!(expr.to_string() == "(a + 1)*-sin(x)", "Got {expr}, expected (a + 1)*-sin(x)");
+}
+
+#[test]
+fn test_display_1() {
+    let expr = Expr::binary(
+        BinaryOp::Sub,
+        Expr::var("a"),
+        Expr::binary(BinaryOp::Sub, Expr::var("b"), Expr::binary(BinaryOp::Pow, Expr::var("c"), Expr::literal(2.0))),
+    );
+    assert!(expr.to_string() == "a - (b - c^2)", "Got {expr}, expected a - (b - c^2)");
+}
\ No newline at end of file
@@ -0,0 +1,237 @@
+use crate::{
+    evaluator::EvalError,
+    number::Number,
+    tokens::BinaryOp,
+    vm::{Instr, Program, Vm},
+};
+
+
+// Rows evaluated together, each instruction runs over a whole chunk before the next one.
+pub const CHUNK: usize = 256;
+
+
+fn zip_in_place<N: Number>(a: &mut [N], b: &[N], f: impl Fn(N, N) -> N) {
+    for (a, b) in a.iter_mut().zip(b) {
+        *a = f(a.clone(), b.clone());
+    }
+}
+
+fn try_zip_in_place<N: Number>(a: &mut [N], b: &[N], f: impl Fn(N, N) -> Result<N, EvalError>) -> Result<(), EvalError> {
+    for (a, b) in a.iter_mut().zip(b) {
+        *a = f(a.clone(), b.clone())?;
+    }
+    Ok(())
+}
+
+
+// Runs a program over columns of input, one column per slot, with every stack entry holding a chunk of rows.
+pub struct BatchVm<N: Number = f64> {
+    stack: Vec<Vec<N>>,
+    args: Vec<N>,
+    // Programs with jumps run row by row, since rows may take different branches
+    vm: Vm<N>,
+}
+
+impl<N: Number> Default for BatchVm<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: Number> BatchVm<N> {
+    pub fn new() -> Self {
+        BatchVm{stack: Vec::new(), args: Vec::new(), vm: Vm::new()}
+    }
+
+    // Columns are given in slot order and must all be as long as the output, anything else panics.
+    // Assignments only produce the output, the input columns are never written to.
+    pub fn run(&mut self, program: &Program<N>, columns: &[&[N]], out: &mut [N]) -> Result<(), EvalError> {
+        N::scoped(&program.settings, || self.execute(program, columns, out))
+    }
+
+    fn execute(&mut self, program: &Program<N>, columns: &[&[N]], out: &mut [N]) -> Result<(), EvalError> {
+        assert!(columns.len() >= program.slots().len(), "Expected {} columns, got {}", program.slots().len(), columns.len());
+        assert!(columns.iter().all(|column| column.len() == out.len()), "Columns and output differ in length");
+
+        if program.branches {
+            for (row, out) in out.iter_mut().enumerate() {
+                self.args.clear();
+                self.args.extend(columns.iter().map(|column| column[row].clone()));
+                *out = self.vm.run(program, &mut self.args)?;
+            }
+            return Ok(());
+        }
+
+        while self.stack.len() < program.stack_size {
+            self.stack.push(vec![N::from_f64(0.0); CHUNK]);
+        }
+
+        for start in (0..out.len()).step_by(CHUNK) {
+            let end = (start + CHUNK).min(out.len());
+            self.run_chunk(program, columns, start..end)?;
+            out[start..end].clone_from_slice(&self.stack[0][..end - start]);
+        }
+        Ok(())
+    }
+
+    fn run_chunk(&mut self, program: &Program<N>, columns: &[&[N]], rows: std::ops::Range<usize>) -> Result<(), EvalError> {
+        let len = rows.len();
+        let mut top = 0;
+
+        for instr in &program.code {
+            match instr {
+                Instr::Push(x) => {
+                    self.stack[top][..len].fill(x.clone());
+                    top += 1;
+                }
+                Instr::Load(i) => {
+                    self.stack[top][..len].clone_from_slice(&columns[*i][rows.clone()]);
+                    top += 1;
+                }
+                Instr::Store(_) => {}
+                Instr::Neg => {
+                    for x in &mut self.stack[top - 1][..len] {
+                        *x = -x.clone();
+                    }
+                }
+                Instr::Factorial => {
+                    for x in &mut self.stack[top - 1][..len] {
+                        *x = x.clone().factorial();
+                    }
+                }
+                Instr::Percent => {
+                    let hundred = N::from_f64(100.0);
+                    for x in &mut self.stack[top - 1][..len] {
+                        *x = x.clone() / hundred.clone();
+                    }
+                }
+                Instr::Not | Instr::Truth => {
+                    let truth = matches!(instr, Instr::Truth);
+                    for x in &mut self.stack[top - 1][..len] {
+                        *x = N::from_bool(x.is_true() == truth);
+                    }
+                }
+                Instr::Add | Instr::Sub | Instr::Mul | Instr::Div | Instr::Pow | Instr::Eq | Instr::Ne => {
+                    let (lower, upper) = self.stack.split_at_mut(top - 1);
+                    let (a, b) = (&mut lower[top - 2][..len], &upper[0][..len]);
+                    // Matching outside the loop keeps each loop body a single operation
+                    match instr {
+                        Instr::Add => zip_in_place(a, b, |a, b| a + b),
+                        Instr::Sub => zip_in_place(a, b, |a, b| a - b),
+                        Instr::Mul => zip_in_place(a, b, |a, b| a * b),
+                        Instr::Div => zip_in_place(a, b, |a, b| a / b),
+                        Instr::Pow => zip_in_place(a, b, N::pow),
+                        Instr::Eq => zip_in_place(a, b, |a, b| N::from_bool(a == b)),
+                        _ => zip_in_place(a, b, |a, b| N::from_bool(a != b)),
+                    }
+                    top -= 1;
+                }
+                // These can fail, on a zero divisor or a value that is not real
+                Instr::Mod | Instr::FloorDiv | Instr::Lt | Instr::Le | Instr::Gt | Instr::Ge => {
+                    let (lower, upper) = self.stack.split_at_mut(top - 1);
+                    let (a, b) = (&mut lower[top - 2][..len], &upper[0][..len]);
+                    let op = match instr {
+                        Instr::Mod => BinaryOp::Mod,
+                        Instr::FloorDiv => BinaryOp::FloorDiv,
+                        Instr::Lt => BinaryOp::Lt,
+                        Instr::Le => BinaryOp::Le,
+                        Instr::Gt => BinaryOp::Gt,
+                        _ => BinaryOp::Ge,
+                    };
+                    try_zip_in_place(a, b, |a, b| op.try_apply(a, b))?;
+                    top -= 1;
+                }
+                Instr::Call(func, count) => {
+                    let base = top - count;
+                    for row in 0..len {
+                        self.args.clear();
+                        self.args.extend(self.stack[base..top].iter().map(|arg| arg[row].clone()));
+                        let result = program.funcs[*func].call_scalar(&self.args)?;
+                        self.stack[base][row] = result;
+                    }
+                    top = base + 1;
+                }
+                Instr::NoMatch(expr) => return Err(EvalError::NoMatchingClause(expr.clone())),
+                Instr::JumpIfFalse(_) | Instr::Jump(_) => unreachable!("Programs with jumps run row by row"),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<N: Number> Program<N> {
+    // Fills "out" with the value of every row, see BatchVm::run.
+    pub fn eval_columns(&self, columns: &[&[N]], out: &mut [N]) -> Result<(), EvalError> {
+        BatchVm::new().run(self, columns, out)
+    }
+
+    // Splits the rows between scoped threads, each running its own BatchVm, and returns the first error.
+    pub fn eval_columns_parallel(&self, columns: &[&[N]], out: &mut [N], threads: usize) -> Result<(), EvalError> {
+        assert!(columns.iter().all(|column| column.len() == out.len()), "Columns and output differ in length");
+        let piece = out.len().div_ceil(threads.max(1)).div_ceil(CHUNK).max(1) * CHUNK;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = out.chunks_mut(piece).enumerate()
+                .map(|(i, out)| {
+                    let rows = i * piece..i * piece + out.len();
+                    let columns: Vec<&[N]> = columns.iter().map(|column| &column[rows.clone()]).collect();
+                    scope.spawn(move || BatchVm::new().run(self, &columns, out))
+                })
+                .collect();
+
+            handles.into_iter()
+                .try_for_each(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+        })
+    }
+}
+
+
+#[test]
+fn test_batch_0() {
+    use crate::{engine::Engine, vm::Vm};
+
+    let mut engine: Engine = Engine::new();
+    engine.set_var("a", 1.5);
+    let program = engine.compile_program("a*x^2 + -y/(1 + max(x, y, 0, 1)) + hypot(x, 2)", &["x", "y"]).unwrap();
+
+    let rows = 2 * CHUNK + 17;
+    let x: Vec<f64> = (0..rows).map(|i| i as f64 * 0.25 - 40.0).collect();
+    let y: Vec<f64> = (0..rows).map(|i| (i % 7) as f64).collect();
+    let mut out = vec![0.0; rows];
+    program.eval_columns(&[&x, &y], &mut out).unwrap();
+
+    let mut vm = Vm::new();
+    for row in 0..rows {
+        assert!(vm.run(&program, &mut [x[row], y[row]]).is_ok_and(|expected| expected == out[row]), "Wrong result in row {row}");
+    }
+
+    let program = engine.compile_program("7", &[]).unwrap();
+    let mut out = vec![0.0; 3];
+    program.eval_columns(&[], &mut out).unwrap();
+    assert!(out == [7.0; 3]);
+}
+
+#[test]
+fn test_batch_1() {
+    use crate::{decimal::Decimal, engine::Engine, number::Settings};
+
+    let engine: Engine = Engine::new();
+    let program = engine.compile_program("x*y + sqrt(abs(x))", &["x", "y"]).unwrap();
+    let rows = 10 * CHUNK + 3;
+    let x: Vec<f64> = (0..rows).map(|i| i as f64 - 1000.0).collect();
+    let y: Vec<f64> = (0..rows).map(|i| (i % 13) as f64 / 4.0).collect();
+
+    let (mut serial, mut parallel) = (vec![0.0; rows], vec![0.0; rows]);
+    program.eval_columns(&[&x, &y], &mut serial).unwrap();
+    for threads in [1, 3, 4, 64] {
+        program.eval_columns_parallel(&[&x, &y], &mut parallel, threads).unwrap();
+        assert!(parallel == serial, "Results differ with {threads} threads");
+    }
+
+    let mut engine: Engine<Decimal> = Engine::new();
+    engine.set_settings(Settings{precision: 50, ..Settings::default()});
+    let program = engine.compile_program("1/x", &["x"]).unwrap();
+    let x = vec![Decimal::from_f64(3.0); 2];
+    let mut out = vec![Decimal::from_f64(0.0); 2];
+    program.eval_columns_parallel(&[&x], &mut out, 2).unwrap();
+    assert!(engine.display(&out[1]).len() > 40, "Got {}", out[1]);
+}
@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use crate::{
+    app_context::{Arity, Context},
+    ast::{Expr, SHORT_CHAIN},
+    evaluator::EvalError,
+    function::Function,
+    number::{Number, Settings},
+    tokens::{BinaryOp, PostfixOp, UnaryOp},
+};
+
+
+#[derive(Clone)]
+pub(crate) enum Node<N: Number> {
+    Const(N),
+    Slot(usize),
+    Neg(Box<Node<N>>),
+    Not(Box<Node<N>>),
+    Postfix(PostfixOp, Box<Node<N>>),
+    Binary(BinaryOp, Box<Node<N>>, Box<Node<N>>),
+    // The right side only runs when the left one does not decide the result
+    And(Box<Node<N>>, Box<Node<N>>),
+    Or(Box<Node<N>>, Box<Node<N>>),
+    If(Box<Node<N>>, Box<Node<N>>, Box<Node<N>>),
+    // Reached when no clause of a piecewise expression holds, keeps the expression for the error
+    NoMatch(String),
+    Call(Arc<dyn Function<N>>, Vec<Node<N>>),
+}
+
+impl<N: Number> Node<N> {
+    fn constant(&self) -> Option<&N> {
+        match self {
+            Self::Const(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    // The nodes down a chain of left operands, outermost first, and the operand the chain starts with.
+    pub(crate) fn left_chain(&self) -> (&Self, Vec<&Self>) {
+        let mut chain = Vec::new();
+        let mut node = self;
+        while let Self::Binary(_, lhs, _) | Self::And(lhs, _) | Self::Or(lhs, _) = node {
+            chain.push(node);
+            node = lhs;
+        }
+        (node, chain)
+    }
+
+    fn is_long_chain(&self) -> bool {
+        let mut node = self;
+        for _ in 0..SHORT_CHAIN {
+            match node {
+                Self::Binary(_, lhs, _) | Self::And(lhs, _) | Self::Or(lhs, _) => node = lhs,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    // Long sums are evaluated in a loop over their left operands, like in the interpreter.
+    fn eval_chain(&self, bindings: &[N]) -> Result<N, EvalError> {
+        let (first, chain) = self.left_chain();
+        let mut value = first.eval(bindings)?;
+        for node in chain.into_iter().rev() {
+            value = match node {
+                Self::Binary(op, _, rhs) => op.try_apply(value, rhs.eval(bindings)?)?,
+                Self::And(_, rhs) => N::from_bool(value.is_true() && rhs.eval(bindings)?.is_true()),
+                Self::Or(_, rhs) => N::from_bool(value.is_true() || rhs.eval(bindings)?.is_true()),
+                _ => unreachable!(),
+            };
+        }
+        Ok(value)
+    }
+
+    fn eval(&self, bindings: &[N]) -> Result<N, EvalError> {
+        match self {
+            Self::Const(x) => Ok(x.clone()),
+            Self::Slot(i) => Ok(bindings[*i].clone()),
+            Self::Neg(operand) => Ok(-operand.eval(bindings)?),
+            Self::Not(operand) => Ok(N::from_bool(!operand.eval(bindings)?.is_true())),
+            Self::Postfix(op, operand) => Ok(op.apply(operand.eval(bindings)?)),
+            Self::Binary(..) | Self::And(..) | Self::Or(..) if self.is_long_chain() => self.eval_chain(bindings),
+            Self::Binary(op, lhs, rhs) => op.try_apply(lhs.eval(bindings)?, rhs.eval(bindings)?),
+            Self::And(lhs, rhs) => Ok(N::from_bool(lhs.eval(bindings)?.is_true() && rhs.eval(bindings)?.is_true())),
+            Self::Or(lhs, rhs) => Ok(N::from_bool(lhs.eval(bindings)?.is_true() || rhs.eval(bindings)?.is_true())),
+            Self::If(condition, then, otherwise) => match condition.eval(bindings)?.is_true() {
+                true => then.eval(bindings),
+                false => otherwise.eval(bindings),
+            },
+            Self::NoMatch(expr) => Err(EvalError::NoMatchingClause(expr.clone())),
+            // Arguments live on the stack, only variadic calls with more than three of them allocate
+            Self::Call(func, args) => match args.as_slice() {
+                [] => func.call_scalar(&[]),
+                [a] => func.call_scalar(&[a.eval(bindings)?]),
+                [a, b] => func.call_scalar(&[a.eval(bindings)?, b.eval(bindings)?]),
+                [a, b, c] => func.call_scalar(&[a.eval(bindings)?, b.eval(bindings)?, c.eval(bindings)?]),
+                _ => {
+                    let args = args.iter().map(|arg| arg.eval(bindings)).collect::<Result<Vec<N>, EvalError>>()?;
+                    func.call_scalar(&args)
+                }
+            },
+        }
+    }
+}
+
+
+// Scalar expression with its variables resolved to slots, for evaluating one formula over many bindings.
+// User functions are expanded and variables that are not slots are read from the context when compiling.
+#[derive(Clone)]
+pub struct SlotExpr<N: Number = f64> {
+    slots: Vec<String>,
+    root: Node<N>,
+    settings: Settings,
+}
+
+impl<N: Number> SlotExpr<N> {
+    pub fn compile(expr: &Expr, slots: &[&str], context: &Context<N>) -> Result<Self, EvalError> {
+        let slots: Vec<String> = slots.iter().map(|slot| slot.to_string()).collect();
+        let settings = context.settings();
+        let root = N::scoped(&settings, || Self::node(&expr.inline(context, 0)?, &slots, context))?;
+        Ok(SlotExpr{slots, root, settings})
+    }
+
+    // Constant parts, including calls to pure functions with constant arguments, are folded here.
+    fn node(expr: &Expr, slots: &[String], context: &Context<N>) -> Result<Node<N>, EvalError> {
+        match expr {
+            Expr::At(_, expr) => Self::node(expr, slots, context),
+            Expr::Literal(x) => Ok(Node::Const(N::from_f64(*x))),
+            Expr::Integer(x) => Ok(Node::Const(N::from_integer(x))),
+            Expr::Decimal(text) => Ok(Node::Const(N::from_literal(text))),
+            Expr::Var(name) => match slots.iter().position(|slot| slot == name) {
+                Some(i) => Ok(Node::Slot(i)),
+                None => {
+                    let value = context.var(name).ok_or_else(|| EvalError::UndefinedVariable(name.clone()))?;
+                    Ok(Node::Const(value.scalar()?))
+                }
+            },
+            Expr::Unary(UnaryOp::Neg, operand) => match Self::node(operand, slots, context)? {
+                Node::Const(x) => Ok(Node::Const(-x)),
+                operand => Ok(Node::Neg(Box::new(operand))),
+            },
+            Expr::Unary(UnaryOp::Not, operand) => match Self::node(operand, slots, context)? {
+                Node::Const(x) => Ok(Node::Const(N::from_bool(!x.is_true()))),
+                operand => Ok(Node::Not(Box::new(operand))),
+            },
+            Expr::Postfix(op, operand) => match Self::node(operand, slots, context)? {
+                Node::Const(x) => Ok(Node::Const(op.apply(x))),
+                operand => Ok(Node::Postfix(op.clone(), Box::new(operand))),
+            },
+            // Built in a loop over the left operands, so a long sum does not recurse once per term
+            Expr::Binary(..) => {
+                let (first, links) = expr.left_chain(|_, _| true);
+                links.into_iter().try_fold(Self::node(first, slots, context)?, |lhs, link| {
+                    let op = link.op;
+                    Ok(match (lhs, Self::node(link.rhs, slots, context)?) {
+                        // Like a failing call, a division by a constant zero is left for run time
+                        (Node::Const(a), Node::Const(b)) => match op.try_apply(a.clone(), b.clone()) {
+                            Ok(x) => Node::Const(x),
+                            Err(_) => Node::Binary(op.clone(), Box::new(Node::Const(a)), Box::new(Node::Const(b))),
+                        },
+                        (lhs, rhs) => match op {
+                            BinaryOp::And => Node::And(Box::new(lhs), Box::new(rhs)),
+                            BinaryOp::Or => Node::Or(Box::new(lhs), Box::new(rhs)),
+                            op => Node::Binary(op.clone(), Box::new(lhs), Box::new(rhs)),
+                        },
+                    })
+                })
+            }
+            Expr::Call(name, args) if name == "if" => match args.as_slice() {
+                [condition, then, otherwise] => {
+                    let condition = Self::node(condition, slots, context)?;
+                    let (then, otherwise) = (Self::node(then, slots, context)?, Self::node(otherwise, slots, context)?);
+                    match condition {
+                        Node::Const(x) if x.is_true() => Ok(then),
+                        Node::Const(_) => Ok(otherwise),
+                        condition => Ok(Node::If(Box::new(condition), Box::new(then), Box::new(otherwise))),
+                    }
+                }
+                _ => Err(EvalError::ArgumentCount(name.clone(), Arity::Exact(3), args.len())),
+            },
+            // Read as a product when there is no such function, like the interpreter does
+            Expr::Call(name, args) if !context.strict() && args.len() == 1 && context.functions().get(name).is_none()
+                && (slots.contains(name) || context.var(name).is_some()) => {
+                Self::node(&Expr::binary(BinaryOp::Mul, Expr::Var(name.clone()), args[0].clone()), slots, context)
+            }
+            Expr::Call(name, args) => {
+                let func = context.functions().handle(name).ok_or_else(|| EvalError::UndfinedFunction(name.clone()))?;
+                if !func.arity().accepts(args.len()) {
+                    return Err(EvalError::ArgumentCount(name.clone(), func.arity(), args.len()));
+                }
+                let args = args.iter()
+                    .map(|arg| Self::node(arg, slots, context))
+                    .collect::<Result<Vec<Node<N>>, EvalError>>()?;
+
+                if func.is_pure() {
+                    if let Some(values) = args.iter().map(|arg| arg.constant().cloned()).collect::<Option<Vec<N>>>() {
+                        // A failing call is left for run time, it may sit in a branch that is never taken
+                        if let Ok(x) = func.call_scalar(&values) {
+                            return Ok(Node::Const(x));
+                        }
+                    }
+                }
+                Ok(Node::Call(func, args))
+            }
+            // Becomes a chain of conditionals, clauses whose condition is constant are decided here
+            Expr::Piecewise(clauses, otherwise) => {
+                let mut node = match otherwise {
+                    Some(otherwise) => Self::node(otherwise, slots, context)?,
+                    None => Node::NoMatch(expr.to_string()),
+                };
+                for (value, condition) in clauses.iter().rev() {
+                    let value = Self::node(value, slots, context)?;
+                    node = match Self::node(condition, slots, context)? {
+                        Node::Const(x) if x.is_true() => value,
+                        Node::Const(_) => node,
+                        condition => Node::If(Box::new(condition), Box::new(value), Box::new(node)),
+                    };
+                }
+                Ok(node)
+            }
+            Expr::List(_) | Expr::Index(..) => Err(EvalError::NotCompilable(expr.to_string())),
+            Expr::Assign(..) | Expr::Define(..) => Err(EvalError::IncorrectAssignment(expr.to_string())),
+        }
+    }
+
+    pub(crate) fn root(&self) -> &Node<N> {
+        &self.root
+    }
+
+    pub(crate) fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    pub fn slots(&self) -> &[String] {
+        &self.slots
+    }
+
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.iter().position(|slot| slot == name)
+    }
+
+    // Bindings are given in slot order, passing fewer than there are slots is a bug in the caller and panics.
+    pub fn eval(&self, bindings: &[N]) -> Result<N, EvalError> {
+        assert!(bindings.len() >= self.slots.len(), "Expected {} bindings, got {}", self.slots.len(), bindings.len());
+        N::scoped(&self.settings, || self.root.eval(bindings))
+    }
+}
+
+
+#[test]
+fn test_compile_0() {
+    use crate::engine::Engine;
+
+    let mut engine: Engine = Engine::new();
+    engine.eval("f(t) = t^2 + 1").unwrap();
+    engine.eval("k = 3").unwrap();
+
+    let compiled = engine.compile_slots("k*f(x) + max(x, y, 0, 1) - y/2", &["x", "y"]).unwrap();
+    assert!(compiled.slot("y") == Some(1));
+    for (x, y) in [(0.0, 0.0), (1.5, -2.0), (-3.0, 4.0)] {
+        let expected = 3.0 * (x * x + 1.0) - y / 2.0 + f64::max(f64::max(x, y), 1.0);
+        assert!(compiled.eval(&[x, y]).is_ok_and(|result| (result - expected).abs() < 1e-12), "Wrong result at {x}, {y}");
+    }
+
+    let folded = engine.compile_slots("sqrt(16) + sin(0)*x", &["x"]).unwrap();
+    assert!(matches!(folded.root, Node::Binary(BinaryOp::Add, ref lhs, _) if lhs.constant() == Some(&4.0)));
+
+    assert!(matches!(engine.compile_slots("z + 1", &["x"]).map_err(|e| e.code()), Err("E0213")));
+    assert!(matches!(engine.compile_slots("[x, 1]", &["x"]).map_err(|e| e.code()), Err("E0215")));
+    assert!(matches!(engine.compile_slots("sin(x, 1)", &["x"]).map_err(|e| e.code()), Err("E0201")));
+
+    // A variable before brackets multiplies, both as a slot and as a known variable
+    let product = engine.compile_slots("x(x + 1) + k(2) + f(x)(2)", &["x"]).unwrap();
+    assert!(product.eval(&[2.0]).is_ok_and(|result| result == 22.0));
+    assert!(matches!(engine.compile_slots("z(x)", &["x"]).map_err(|e| e.code()), Err("E0214")));
+}
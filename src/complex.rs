@@ -0,0 +1,335 @@
+use std::{cell::Cell, cmp::Ordering, fmt::Display, ops::{Add, Div, Mul, Neg, Sub}};
+
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
+
+use crate::number::{Number, Settings};
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ComplexFormat {
+    Rectangular,
+    Polar,
+}
+
+thread_local! {
+    static FORMAT: Cell<ComplexFormat> = const { Cell::new(ComplexFormat::Rectangular) };
+}
+
+// Format complex results are printed in, installed by the engine running on this thread.
+fn format() -> ComplexFormat {
+    FORMAT.with(|format| format.get())
+}
+
+// Runs f with the given format, the previous one comes back afterwards even if f panics.
+fn with_format<R>(format: ComplexFormat, f: impl FnOnce() -> R) -> R {
+    struct Restore(ComplexFormat);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            FORMAT.with(|format| format.set(self.0));
+        }
+    }
+
+    let _restore = Restore(FORMAT.with(|current| current.replace(format)));
+    f()
+}
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const I: Complex = Complex{re: 0.0, im: 1.0};
+
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex{re, im}
+    }
+
+    pub fn from_polar(r: f64, theta: f64) -> Self {
+        Complex{re: r * theta.cos(), im: r * theta.sin()}
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn angle(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    fn powi(self, mut exponent: u64) -> Self {
+        let mut base = self;
+        let mut result = Self::new(1.0, 0.0);
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            exponent /= 2;
+        }
+        result
+    }
+}
+
+
+// Parts far from 1 are printed in scientific notation, e.g. the rounding error in e^(i*pi)
+fn part(x: f64) -> String {
+    match x.abs() {
+        a if a != 0.0 && !(1e-4..1e16).contains(&a) => format!("{x:e}"),
+        _ => format!("{x}"),
+    }
+}
+
+fn write_rectangular(f: &mut std::fmt::Formatter<'_>, re: f64, im: f64) -> std::fmt::Result {
+    let imaginary = |im: f64| match im {
+        1.0 => "i".to_string(),
+        _ => format!("{}i", part(im)),
+    };
+
+    match (re, im) {
+        (re, 0.0) => write!(f, "{}", part(re)),
+        (0.0, -1.0) => write!(f, "-i"),
+        (0.0, im) => write!(f, "{}", imaginary(im)),
+        (re, im) if im < 0.0 => write!(f, "{} - {}", part(re), imaginary(-im)),
+        (re, im) => write!(f, "{} + {}", part(re), imaginary(im)),
+    }
+}
+
+impl Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match format() {
+            ComplexFormat::Rectangular => write_rectangular(f, self.re, self.im),
+            // Printed so the result can be typed back in
+            ComplexFormat::Polar => write!(f, "{}*e^({}*i)", self.norm(), self.angle()),
+        }
+    }
+}
+
+// Only real values are ordered.
+impl PartialOrd for Complex {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.is_real() && other.is_real() {
+            true => self.re.partial_cmp(&other.re),
+            false => None,
+        }
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        if other.is_real() {
+            return Self::new(self.re / other.re, self.im / other.re);
+        }
+        let denom = other.re * other.re + other.im * other.im;
+        Self::new(
+            (self.re * other.re + self.im * other.im) / denom,
+            (self.im * other.re - self.re * other.im) / denom,
+        )
+    }
+}
+
+impl Neg for Complex {
+    type Output = Self;
+
+    // Subtracting from zero keeps a zero part positive, so ln(-2) lands on +pi and not -pi
+    fn neg(self) -> Self {
+        Self::new(0.0 - self.re, 0.0 - self.im)
+    }
+}
+
+
+impl Number for Complex {
+    fn from_f64(x: f64) -> Self {
+        Self::new(x, 0.0)
+    }
+
+    // Real part
+    fn to_f64(&self) -> f64 {
+        self.re
+    }
+
+    fn pi() -> Self {
+        Self::from_f64(std::f64::consts::PI)
+    }
+
+    fn e() -> Self {
+        Self::from_f64(std::f64::consts::E)
+    }
+
+    fn constant(name: &str) -> Option<Self> {
+        match name {
+            "pi" => Some(Self::pi()),
+            "e" => Some(Self::e()),
+            "i" => Some(Self::I),
+            _ => None,
+        }
+    }
+
+    fn pow(self, exponent: Self) -> Self {
+        match (self, exponent) {
+            (base, exponent) if base.is_real() && exponent.is_real() && base.re >= 0.0 => {
+                Self::from_f64(base.re.powf(exponent.re))
+            }
+            // Whole powers are multiplied out, so (1 + i)^2 is exactly 2i
+            (base, exponent) if exponent.is_real() && exponent.re.fract() == 0.0 && exponent.re.abs() <= 1024.0 => {
+                let result = base.powi(exponent.re.abs() as u64);
+                if exponent.re < 0.0 { Self::from_f64(1.0) / result } else { result }
+            }
+            (base, _) if base == Self::from_f64(0.0) => Self::from_f64(0.0),
+            (base, exponent) => (exponent * base.ln()).exp(),
+        }
+    }
+
+    fn sqrt(self) -> Self {
+        if self.is_real() && self.re >= 0.0 {
+            return Self::from_f64(self.re.sqrt());
+        }
+        let r = self.norm();
+        let re = ((r + self.re) / 2.0).sqrt();
+        let im = ((r - self.re) / 2.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(self) -> Self {
+        Self::from_polar(self.re.exp(), self.im)
+    }
+
+    fn ln(self) -> Self {
+        Self::new(self.norm().ln(), self.angle())
+    }
+
+    fn sin(self) -> Self {
+        Self::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+
+    fn cos(self) -> Self {
+        Self::new(self.re.cos() * self.im.cosh(), -self.re.sin() * self.im.sinh())
+    }
+
+    fn abs(self) -> Self {
+        Self::from_f64(self.norm())
+    }
+
+    fn arg(self) -> Self {
+        Self::from_f64(self.angle())
+    }
+
+    fn is_real(&self) -> bool {
+        self.im == 0.0
+    }
+
+    fn re(self) -> Self {
+        Self::from_f64(self.re)
+    }
+
+    fn im(self) -> Self {
+        Self::from_f64(self.im)
+    }
+
+    fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    fn scoped<R>(settings: &Settings, f: impl FnOnce() -> R) -> R {
+        with_format(settings.format, f)
+    }
+
+    fn to_integer(&self) -> Option<BigInt> {
+        match self.is_real() && self.re.fract() == 0.0 {
+            true => BigInt::from_f64(self.re),
+            false => None,
+        }
+    }
+
+    fn floor(self) -> Self {
+        Self::new(self.re.floor(), self.im.floor())
+    }
+
+    // Only defined on the real line here
+    fn factorial(self) -> Self {
+        match self.is_real() {
+            true => Self::new(self.re.factorial(), 0.0),
+            false => Self::new(f64::NAN, f64::NAN),
+        }
+    }
+}
+
+
+#[test]
+fn test_complex_0() {
+    assert!(Complex::from_f64(-1.0).sqrt() == Complex::I);
+    assert!(Complex::new(1.0, 1.0).pow(Complex::from_f64(2.0)) == Complex::new(0.0, 2.0));
+    assert!(Complex::new(3.0, -4.0).abs() == Complex::from_f64(5.0));
+
+    let z = Complex::e().pow(Complex::I * Complex::pi());
+    assert!((z.re + 1.0).abs() < 1e-15 && z.im.abs() < 1e-15, "e^(i*pi) = {z}");
+
+    let z = (-Complex::from_f64(2.0)).ln();
+    assert!(z.re == 2f64.ln() && z.im == std::f64::consts::PI, "ln(-2) = {z}");
+}
+
+#[test]
+fn test_complex_1() {
+    assert!(Complex::new(1.0, -2.0).to_string() == "1 - 2i");
+    assert!(Complex::new(0.0, -1.0).to_string() == "-i");
+    assert!(Complex::new(2.5, 0.0).to_string() == "2.5");
+
+    let polar = Settings{format: ComplexFormat::Polar, ..Settings::default()};
+    let z = Complex::scoped(&polar, || Complex::new(0.0, 2.0).to_string());
+    assert!(z == format!("2*e^({}*i)", std::f64::consts::FRAC_PI_2));
+    assert!(Complex::new(0.0, 2.0).to_string() == "2i", "The format is restored after the scope");
+}
+
+#[test]
+fn test_complex_2() {
+    use crate::{engine::Engine, error::Error, evaluator::EvalError};
+
+    let engine: Engine<Complex> = Engine::new();
+    let eval = |input: &str| match engine.value(input) {
+        Ok(value) => Ok(value.to_string()),
+        Err(Error::Eval(e)) => Err(e.without_span()),
+        Err(e) => panic!("{input} failed with {e}"),
+    };
+
+    // Only real values are ordered, whatever order the arguments come in
+    for input in ["max(i, 1)", "max(1, i)", "min([2, i])", "clamp(i, 0, 1)", "i < 1", "1 >= 2i", "[1, i] > 0"] {
+        assert!(matches!(eval(input), Err(EvalError::InvalidArgument(..))), "{input} did not fail");
+    }
+    for input in ["i > 0", "max(1, 2i)"] {
+        assert!(matches!(engine.compile_program(input, &[]).unwrap().eval(&mut []), Err(EvalError::InvalidArgument(..))));
+    }
+    assert!(eval("max(1, (1 + i)*(1 - i), -3)").unwrap() == "2" && eval("i^2 < 0").unwrap() == "1");
+    assert!(eval("i == i && 1 != i").unwrap() == "1");
+}
@@ -0,0 +1,623 @@
+use std::{cell::Cell, cmp::Ordering, fmt::Display, ops::{Add, Div, Mul, Neg, Sub}};
+
+use num_bigint::{BigInt, Sign};
+use num_integer::Integer;
+use num_traits::{pow, One, Signed, ToPrimitive, Zero};
+
+use crate::number::{literal_parts, Number, Settings};
+
+
+pub const DEFAULT_PRECISION: usize = 32;
+// Extra digits carried through every operation so the printed digits stay correct
+const GUARD_DIGITS: usize = 10;
+
+thread_local! {
+    static PRECISION: Cell<usize> = const { Cell::new(DEFAULT_PRECISION) };
+}
+
+// Number of significant digits results are printed with, installed by the engine running on this thread.
+fn precision() -> usize {
+    PRECISION.with(|precision| precision.get())
+}
+
+// Runs f with the given precision, the previous one comes back afterwards even if f panics.
+fn with_precision<R>(digits: usize, f: impl FnOnce() -> R) -> R {
+    struct Restore(usize);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            PRECISION.with(|precision| precision.set(self.0));
+        }
+    }
+
+    let _restore = Restore(PRECISION.with(|precision| precision.replace(digits.max(1))));
+    f()
+}
+
+fn working_digits() -> usize {
+    precision() + GUARD_DIGITS
+}
+
+
+fn pow10(n: usize) -> BigInt {
+    pow(BigInt::from(10), n)
+}
+
+fn digit_count(x: &BigInt) -> usize {
+    if x.is_zero() { 1 } else { x.abs().to_string().len() }
+}
+
+// Rounds x half away from zero to at most `digits` digits, returning the new value and the powers of ten removed.
+fn round_digits(x: &BigInt, digits: usize) -> (BigInt, i64) {
+    let len = digit_count(x);
+    if len <= digits {
+        return (x.clone(), 0);
+    }
+
+    let divisor = pow10(len - digits);
+    let (quotient, remainder) = x.abs().div_rem(&divisor);
+    let quotient = if remainder * 2 >= divisor { quotient + 1 } else { quotient };
+    let quotient = if x.is_negative() { -quotient } else { quotient };
+    (quotient, (len - digits) as i64)
+}
+
+// Fixed point arctan(1/n) scaled by 10^scale.
+fn atan_inv(n: u32, scale: usize) -> BigInt {
+    let n = BigInt::from(n);
+    let n_squared = &n * &n;
+    let mut power = pow10(scale) / &n;
+    let mut sum = power.clone();
+    let mut k = 1u32;
+    while !power.is_zero() {
+        power /= &n_squared;
+        let term = &power / (2 * k + 1);
+        if k % 2 == 1 { sum -= term } else { sum += term }
+        k += 1;
+    }
+    sum
+}
+
+// Fixed point pi scaled by 10^scale, from Machin's formula.
+fn pi_fixed(scale: usize) -> BigInt {
+    let guarded = scale + 5;
+    let pi = (atan_inv(5, guarded) * 4 - atan_inv(239, guarded)) * 4;
+    pi / pow10(5)
+}
+
+// Fixed point ln(y) scaled by 10^scale, for a fixed point y in (0, 10].
+fn ln_fixed(y: BigInt, scale: usize) -> BigInt {
+    // Taking square roots brings y close to 1, where the atanh series converges quickly
+    const REDUCTIONS: u32 = 10;
+    let guarded = scale + 5;
+    let one = pow10(guarded);
+    let mut y = y * pow10(5);
+    for _ in 0..REDUCTIONS {
+        y = (y * &one).sqrt();
+    }
+
+    let z = ((&y - &one) * &one) / (&y + &one);
+    let z_squared = &z * &z / &one;
+    let mut power = z.clone();
+    let mut sum = z;
+    let mut k = 1u32;
+    while !power.is_zero() {
+        power = power * &z_squared / &one;
+        sum += &power / (2 * k + 1);
+        k += 1;
+    }
+
+    (sum * 2 * (1 << REDUCTIONS)) / pow10(5)
+}
+
+
+// Arbitrary precision decimal, mantissa * 10^exponent, with a float fallback for NaN and infinities.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Decimal {
+    Finite(BigInt, i64),
+    NonFinite(f64),
+}
+
+impl Decimal {
+    fn zero() -> Self {
+        Self::Finite(BigInt::zero(), 0)
+    }
+
+    fn one() -> Self {
+        Self::Finite(BigInt::one(), 0)
+    }
+
+    // Rounds to `digits` significant digits and strips trailing zeros, so equal values compare equal.
+    fn rounded(mantissa: BigInt, exponent: i64, digits: usize) -> Self {
+        if mantissa.is_zero() {
+            return Self::zero();
+        }
+
+        let (mut mantissa, shift) = round_digits(&mantissa, digits);
+        let mut exponent = exponent + shift;
+        while (&mantissa % 10u32).is_zero() {
+            mantissa /= 10u32;
+            exponent += 1;
+        }
+        Self::Finite(mantissa, exponent)
+    }
+
+    fn round_to(self, digits: usize) -> Self {
+        match self {
+            Self::Finite(mantissa, exponent) => Self::rounded(mantissa, exponent, digits),
+            x => x,
+        }
+    }
+
+    fn new(mantissa: BigInt, exponent: i64) -> Self {
+        Self::rounded(mantissa, exponent, working_digits())
+    }
+
+    fn from_fixed(x: BigInt, scale: usize, digits: usize) -> Self {
+        Self::rounded(x, -(scale as i64), digits)
+    }
+
+    fn to_fixed(&self, scale: usize) -> BigInt {
+        match self {
+            Self::Finite(mantissa, exponent) => {
+                let shift = exponent + scale as i64;
+                if shift >= 0 {
+                    mantissa * pow10(shift as usize)
+                }
+                else {
+                    mantissa / pow10(shift.unsigned_abs() as usize)
+                }
+            }
+            Self::NonFinite(_) => BigInt::zero(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Self::Finite(mantissa, _) if mantissa.is_zero())
+    }
+
+    fn is_negative(&self) -> bool {
+        match self {
+            Self::Finite(mantissa, _) => mantissa.is_negative(),
+            Self::NonFinite(x) => *x < 0.0,
+        }
+    }
+
+    // Number of digits before the decimal point, negative for values below 0.1
+    fn magnitude(&self) -> i64 {
+        match self {
+            Self::Finite(mantissa, exponent) => digit_count(mantissa) as i64 + exponent,
+            Self::NonFinite(_) => 0,
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, Self::Finite(_, exponent) if *exponent >= 0)
+    }
+
+    fn fallback(&self, other: &Self, op: impl FnOnce(f64, f64) -> f64) -> Option<Self> {
+        match (self, other) {
+            (Self::Finite(..), Self::Finite(..)) => None,
+            (a, b) => Some(Self::from_f64(op(a.to_f64(), b.to_f64()))),
+        }
+    }
+
+    fn mul_digits(&self, other: &Self, digits: usize) -> Self {
+        if let Some(result) = self.fallback(other, |a, b| a * b) {
+            return result;
+        }
+        let (Self::Finite(a, ea), Self::Finite(b, eb)) = (self, other) else { unreachable!() };
+        Self::rounded(a * b, ea + eb, digits)
+    }
+
+    fn div_digits(&self, other: &Self, digits: usize) -> Self {
+        if let Some(result) = self.fallback(other, |a, b| a / b) {
+            return result;
+        }
+        if other.is_zero() {
+            return Self::NonFinite(self.to_f64() / 0.0);
+        }
+        let (Self::Finite(a, ea), Self::Finite(b, eb)) = (self, other) else { unreachable!() };
+        let shift = (digits + 1 + digit_count(b)).saturating_sub(digit_count(a));
+        Self::rounded(a * pow10(shift) / b, ea - eb - shift as i64, digits)
+    }
+
+    fn powi_digits(&self, mut exponent: u64, digits: usize) -> Self {
+        let mut base = self.clone();
+        let mut result = Self::one();
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result.mul_digits(&base, digits);
+            }
+            base = base.mul_digits(&base, digits);
+            exponent /= 2;
+        }
+        result
+    }
+
+    fn exp_digits(&self, digits: usize) -> Self {
+        match self {
+            Self::NonFinite(x) => return Self::from_f64(x.exp()),
+            x if x.is_zero() => return Self::one(),
+            x if x.is_negative() => return Self::one().div_digits(&(-x.clone()).exp_digits(digits), digits),
+            // The result would not fit in the exponent
+            x if x.magnitude() > 15 => return Self::NonFinite(f64::INFINITY),
+            _ => (),
+        }
+
+        // exp(x) = exp(x / 2^k)^(2^k), with k chosen so the series argument is below 1/16
+        let halvings = self.to_fixed(0).bits() as usize + 4;
+        let digits = digits + halvings / 3 + 5;
+        let scale = digits + 5;
+        let one = pow10(scale);
+        let x = self.to_fixed(scale) >> halvings;
+
+        let mut term = one.clone();
+        let mut sum = one.clone();
+        let mut n = 1u32;
+        while !term.is_zero() {
+            term = term * &x / &one / n;
+            sum += &term;
+            n += 1;
+        }
+
+        let mut result = Self::from_fixed(sum, scale, digits);
+        for _ in 0..halvings {
+            result = result.mul_digits(&result, digits);
+        }
+        result
+    }
+
+    fn ln_digits(&self, digits: usize) -> Self {
+        match self {
+            Self::NonFinite(x) => return Self::NonFinite(x.ln()),
+            x if x.is_zero() => return Self::NonFinite(f64::NEG_INFINITY),
+            x if x.is_negative() => return Self::NonFinite(f64::NAN),
+            _ => (),
+        }
+
+        // x = y * 10^n with y in [1, 10), so ln(x) = ln(y) + n*ln(10)
+        let n = self.magnitude() - 1;
+        let scale = digits + 5;
+        let y = match self {
+            Self::Finite(mantissa, exponent) => Self::Finite(mantissa.clone(), exponent - n),
+            Self::NonFinite(_) => unreachable!(),
+        };
+        let sum = ln_fixed(y.to_fixed(scale), scale) + ln_fixed(pow10(scale) * 10, scale) * n;
+        Self::from_fixed(sum, scale, digits)
+    }
+
+    // Fixed point sin and cos of x, reduced into [-pi, pi] first.
+    fn sin_cos_fixed(&self, scale: usize) -> (BigInt, BigInt) {
+        let extra = self.magnitude().max(0) as usize;
+        let wide_scale = scale + extra;
+        let two_pi = pi_fixed(wide_scale) * 2u32;
+        let x = self.to_fixed(wide_scale);
+        let turns = (&x * 2u32 + &two_pi).div_floor(&(&two_pi * 2u32));
+        let x = (x - turns * two_pi) / pow10(extra);
+
+        let one = pow10(scale);
+        let x_squared = &x * &x / &one;
+        let (mut sin, mut cos) = (x.clone(), one.clone());
+        let (mut sin_term, mut cos_term) = (x, one.clone());
+        let mut n = 1u32;
+        while !sin_term.is_zero() || !cos_term.is_zero() {
+            cos_term = -cos_term * &x_squared / &one / ((2 * n - 1) * (2 * n));
+            sin_term = -sin_term * &x_squared / &one / ((2 * n) * (2 * n + 1));
+            sin += &sin_term;
+            cos += &cos_term;
+            n += 1;
+        }
+        (sin, cos)
+    }
+}
+
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (mantissa, exponent) = match self.clone().round_to(precision()) {
+            Self::Finite(mantissa, exponent) => (mantissa, exponent),
+            Self::NonFinite(x) => return write!(f, "{x}"),
+        };
+
+        let sign = if mantissa.is_negative() { "-" } else { "" };
+        let digits = mantissa.abs().to_string();
+        // Position of the decimal point relative to the first digit
+        let point = digits.len() as i64 + exponent;
+
+        if exponent >= 0 && point <= 40 {
+            write!(f, "{sign}{digits}{}", "0".repeat(exponent as usize))
+        }
+        else if point > 0 && exponent < 0 {
+            let (whole, fraction) = digits.split_at(point as usize);
+            write!(f, "{sign}{whole}.{fraction}")
+        }
+        else if point > -10 && exponent < 0 {
+            write!(f, "{sign}0.{}{digits}", "0".repeat(point.unsigned_abs() as usize))
+        }
+        else {
+            let (first, rest) = digits.split_at(1);
+            write!(f, "{sign}{first}{}{rest}e{}", if rest.is_empty() { "" } else { "." }, point - 1)
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (Self::Finite(a, ea), Self::Finite(b, eb)) = (self, other) else {
+            return self.to_f64().partial_cmp(&other.to_f64());
+        };
+
+        if a.sign() != b.sign() || a.is_zero() {
+            return a.sign().partial_cmp(&b.sign());
+        }
+
+        let order = match self.magnitude().cmp(&other.magnitude()) {
+            Ordering::Equal => {
+                let exponent = *ea.min(eb);
+                let a = a * pow10((ea - exponent) as usize);
+                let b = b * pow10((eb - exponent) as usize);
+                return a.partial_cmp(&b);
+            }
+            order => order,
+        };
+        Some(if a.sign() == Sign::Minus { order.reverse() } else { order })
+    }
+}
+
+impl Add for Decimal {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        if let Some(result) = self.fallback(&other, |a, b| a + b) {
+            return result;
+        }
+
+        // Terms too small to change any working digit are dropped
+        let digits = working_digits() as i64 + 1;
+        match (&self, &other) {
+            (a, b) if b.is_zero() || (!a.is_zero() && a.magnitude() - b.magnitude() > digits) => self,
+            (a, b) if a.is_zero() || b.magnitude() - a.magnitude() > digits => other,
+            (Self::Finite(a, ea), Self::Finite(b, eb)) => {
+                let exponent = *ea.min(eb);
+                let sum = a * pow10((ea - exponent) as usize) + b * pow10((eb - exponent) as usize);
+                Self::new(sum, exponent)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self + -other
+    }
+}
+
+impl Mul for Decimal {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        self.mul_digits(&other, working_digits())
+    }
+}
+
+impl Div for Decimal {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        self.div_digits(&other, working_digits())
+    }
+}
+
+impl Neg for Decimal {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self {
+            Self::Finite(mantissa, exponent) => Self::Finite(-mantissa, exponent),
+            Self::NonFinite(x) => Self::NonFinite(-x),
+        }
+    }
+}
+
+
+impl Number for Decimal {
+    // Goes through the shortest decimal form of x, so a literal like 0.1 stays exactly 0.1
+    fn from_f64(x: f64) -> Self {
+        if !x.is_finite() {
+            return Self::NonFinite(x);
+        }
+
+        Self::from_literal(&format!("{x:e}"))
+    }
+
+    fn from_literal(text: &str) -> Self {
+        match literal_parts(text) {
+            Some((mantissa, exponent)) => Self::new(mantissa, exponent),
+            None => Self::from_f64(text.parse().unwrap_or(f64::NAN)),
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        match self {
+            Self::Finite(mantissa, exponent) => format!("{mantissa}e{exponent}").parse().unwrap_or(f64::NAN),
+            Self::NonFinite(x) => *x,
+        }
+    }
+
+    fn pi() -> Self {
+        let scale = working_digits() + 2;
+        Self::from_fixed(pi_fixed(scale), scale, working_digits())
+    }
+
+    fn e() -> Self {
+        Self::one().exp()
+    }
+
+    fn from_integer(x: &BigInt) -> Self {
+        Self::new(x.clone(), 0)
+    }
+
+    fn to_integer(&self) -> Option<BigInt> {
+        match self {
+            Self::Finite(mantissa, exponent) if *exponent >= 0 => Some(mantissa * pow10(*exponent as usize)),
+            _ => None,
+        }
+    }
+
+    fn floor(self) -> Self {
+        match self {
+            Self::Finite(mantissa, exponent) if exponent < 0 => {
+                Self::new(mantissa.div_floor(&pow10(exponent.unsigned_abs() as usize)), 0)
+            }
+            x => x,
+        }
+    }
+
+    fn pow(self, exponent: Self) -> Self {
+        if let Some(result) = self.fallback(&exponent, f64::powf) {
+            return result;
+        }
+
+        let digits = working_digits();
+        if exponent.is_integer() {
+            if let Some(n) = exponent.to_fixed(0).abs().to_u64() {
+                let digits = digits + digit_count(&BigInt::from(n)) + 2;
+                let result = self.powi_digits(n, digits);
+                let result = if exponent.is_negative() { Self::one().div_digits(&result, digits) } else { result };
+                return result.round_to(working_digits());
+            }
+        }
+
+        match &self {
+            x if x.is_zero() && exponent.is_negative() => Self::NonFinite(f64::INFINITY),
+            x if x.is_zero() => Self::zero(),
+            x if x.is_negative() => Self::NonFinite(f64::NAN),
+            x => {
+                // x^y = exp(y*ln(x)), with extra digits to cover the size of y*ln(x)
+                let power = exponent.mul_digits(&x.ln_digits(digits + 5), digits + 5);
+                let digits = digits + power.magnitude().max(0) as usize;
+                let power = exponent.mul_digits(&x.ln_digits(digits + 5), digits + 5);
+                let result = power.exp_digits(digits);
+                result.round_to(working_digits())
+            }
+        }
+    }
+
+    fn sqrt(self) -> Self {
+        let (mantissa, exponent) = match &self {
+            Self::NonFinite(x) => return Self::from_f64(x.sqrt()),
+            x if x.is_zero() => return Self::zero(),
+            x if x.is_negative() => return Self::NonFinite(f64::NAN),
+            Self::Finite(mantissa, exponent) => (mantissa, *exponent),
+        };
+
+        // Scale the mantissa to twice the working digits, with an even exponent
+        let digits = working_digits();
+        let shift = (2 * digits + 2).saturating_sub(digit_count(mantissa));
+        let mut mantissa = mantissa * pow10(shift);
+        let mut exponent = exponent - shift as i64;
+        if exponent % 2 != 0 {
+            mantissa *= 10;
+            exponent -= 1;
+        }
+        Self::new(mantissa.sqrt(), exponent / 2)
+    }
+
+    fn exp(self) -> Self {
+        let result = self.exp_digits(working_digits());
+        result.round_to(working_digits())
+    }
+
+    fn ln(self) -> Self {
+        self.ln_digits(working_digits())
+    }
+
+    fn sin(self) -> Self {
+        if let Self::NonFinite(x) = self {
+            return Self::NonFinite(x.sin());
+        }
+        let scale = working_digits() + 5;
+        Self::from_fixed(self.sin_cos_fixed(scale).0, scale, working_digits())
+    }
+
+    fn cos(self) -> Self {
+        if let Self::NonFinite(x) = self {
+            return Self::NonFinite(x.cos());
+        }
+        let scale = working_digits() + 5;
+        Self::from_fixed(self.sin_cos_fixed(scale).1, scale, working_digits())
+    }
+
+    fn tan(self) -> Self {
+        if let Self::NonFinite(x) = self {
+            return Self::NonFinite(x.tan());
+        }
+        let scale = working_digits() + 5;
+        let (sin, cos) = self.sin_cos_fixed(scale);
+        Self::from_fixed(sin, scale, working_digits()).div_digits(&Self::from_fixed(cos, scale, working_digits()), working_digits())
+    }
+
+    fn scoped<R>(settings: &Settings, f: impl FnOnce() -> R) -> R {
+        with_precision(settings.precision, f)
+    }
+}
+
+
+#[test]
+fn test_decimal_0() {
+    assert!(Decimal::pi().to_string() == "3.1415926535897932384626433832795");
+    assert!((Decimal::from_f64(1.0) / Decimal::from_f64(3.0)).to_string() == "0.33333333333333333333333333333333");
+    assert!((Decimal::from_f64(0.1) + Decimal::from_f64(0.2)).to_string() == "0.3");
+    assert!(Decimal::from_f64(2.0).pow(Decimal::from_f64(100.0)).to_string() == "1267650600228229401496703205376");
+    assert!(Decimal::from_f64(-2.0).sqrt().to_f64().is_nan());
+}
+
+#[test]
+fn test_decimal_1() {
+    Decimal::scoped(&Settings{precision: 50, ..Settings::default()}, || {
+        assert!(Decimal::pi().to_string() == "3.1415926535897932384626433832795028841971693993751");
+        assert!(Decimal::e().to_string() == "2.7182818284590452353602874713526624977572470937");
+        assert!(Decimal::from_f64(2.0).sqrt().to_string() == "1.4142135623730950488016887242096980785696718753769");
+        assert!(Decimal::from_f64(2.0).ln().to_string() == "0.69314718055994530941723212145817656807550013436026");
+        assert!(Decimal::from_f64(1.0).sin().to_string() == "0.84147098480789650665250232163029899962256306079837");
+        assert!(Decimal::from_f64(1.0).cos().to_string() == "0.54030230586813971740093660744297660373231042061792");
+        assert!(Decimal::from_f64(1.0).tan().to_string() == "1.5574077246549022305069748074583601730872507723815");
+        assert!(Decimal::from_f64(-50.0).exp().to_string() == "1.9287498479639177830173428165270125747528326512303e-22");
+    });
+}
+
+#[test]
+fn test_decimal_2() {
+    use crate::engine::Engine;
+
+    let mut engine: Engine<Decimal> = Engine::new();
+    engine.set_settings(Settings{precision: 50, ..Settings::default()});
+    let value = |input: &str| engine.value(input).unwrap().scalar().unwrap();
+    // Literals keep every digit written instead of going through an f64
+    let difference = value("3.14159265358979323846264338327950288 - pi");
+    assert!(difference.clone().abs() < Decimal::from_literal("1e-35"), "Got {difference}");
+    assert!(value("0.1234567890123456789012345e25").to_string() == "1234567890123456789012345");
+    assert!(value("1e30 + 0.1").to_string() == "1000000000000000000000000000000.1");
+    assert!(engine.compile_slots("x + 0.10000000000000000000001", &["x"]).unwrap().eval(&[Decimal::from_f64(1.0)]).unwrap().to_string() == "1.10000000000000000000001");
+}
+
+#[test]
+fn test_decimal_3() {
+    use crate::engine::Engine;
+
+    // Each engine keeps its own precision, on any thread it is used from
+    let mut precise: Engine<Decimal> = Engine::new();
+    precise.set_settings(Settings{precision: 60, ..Settings::default()});
+    let coarse: Engine<Decimal> = Engine::new();
+    let third = precise.compile("1/3").unwrap();
+
+    let digits = |engine: &Engine<Decimal>| engine.display(&engine.value_of(&third).unwrap()).len() - 2;
+    assert!(digits(&precise) == 60 && digits(&coarse) == DEFAULT_PRECISION);
+    let precise = &precise;
+    std::thread::scope(|scope| {
+        assert!(scope.spawn(|| digits(precise)).join().unwrap() == 60);
+    });
+    assert!(precise.var("pi").is_some_and(|pi| precise.display(&pi).len() == 61));
+}
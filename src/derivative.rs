@@ -0,0 +1,332 @@
+use crate::{app_context::{Arity, Context}, ast::{map_clauses, try_map_clauses, Expr}, evaluator::{EvalError, MAX_CALL_DEPTH}, number::Number, parser::MAX_DEPTH, tokens::{BinaryOp, PostfixOp, UnaryOp}};
+
+
+// Constructors that skip the trivial terms the differentiation rules produce, e.g. "x*1" or "0 + x".
+fn is_literal(expr: &Expr, value: f64) -> bool {
+    matches!(expr, Expr::Literal(x) if *x == value)
+}
+
+fn sum(lhs: Expr, rhs: Expr) -> Expr {
+    match (lhs, rhs) {
+        (Expr::Literal(a), Expr::Literal(b)) => Expr::literal(a + b),
+        (lhs, rhs) if is_literal(&lhs, 0.0) => rhs,
+        (lhs, rhs) if is_literal(&rhs, 0.0) => lhs,
+        (lhs, Expr::Unary(UnaryOp::Neg, rhs)) => Expr::Binary(BinaryOp::Sub, Box::new(lhs), rhs),
+        (lhs, rhs) => Expr::binary(BinaryOp::Add, lhs, rhs),
+    }
+}
+
+fn difference(lhs: Expr, rhs: Expr) -> Expr {
+    match (lhs, rhs) {
+        (Expr::Literal(a), Expr::Literal(b)) => Expr::literal(a - b),
+        (lhs, rhs) if is_literal(&rhs, 0.0) => lhs,
+        (lhs, rhs) if is_literal(&lhs, 0.0) => negate(rhs),
+        (lhs, Expr::Unary(UnaryOp::Neg, rhs)) => sum(lhs, *rhs),
+        (lhs, rhs) => Expr::binary(BinaryOp::Sub, lhs, rhs),
+    }
+}
+
+fn product(lhs: Expr, rhs: Expr) -> Expr {
+    match (lhs, rhs) {
+        (Expr::Literal(a), Expr::Literal(b)) => Expr::literal(a * b),
+        (lhs, rhs) if is_literal(&lhs, 0.0) || is_literal(&rhs, 0.0) => Expr::literal(0.0),
+        (lhs, rhs) if is_literal(&lhs, 1.0) => rhs,
+        (lhs, rhs) if is_literal(&rhs, 1.0) => lhs,
+        (Expr::Unary(UnaryOp::Neg, lhs), rhs) => negate(product(*lhs, rhs)),
+        (lhs, Expr::Unary(UnaryOp::Neg, rhs)) => negate(product(lhs, *rhs)),
+        // Constants go in front, "2*x" rather than "x*2"
+        (lhs, rhs @ (Expr::Literal(_) | Expr::Integer(_) | Expr::Decimal(_))) => Expr::binary(BinaryOp::Mul, rhs, lhs),
+        (lhs, rhs) => Expr::binary(BinaryOp::Mul, lhs, rhs),
+    }
+}
+
+fn quotient(lhs: Expr, rhs: Expr) -> Expr {
+    match (lhs, rhs) {
+        (lhs, _) if is_literal(&lhs, 0.0) => Expr::literal(0.0),
+        (lhs, rhs) if is_literal(&rhs, 1.0) => lhs,
+        (Expr::Unary(UnaryOp::Neg, lhs), rhs) => negate(quotient(*lhs, rhs)),
+        (lhs, rhs) => Expr::binary(BinaryOp::Div, lhs, rhs),
+    }
+}
+
+fn power(base: Expr, exponent: Expr) -> Expr {
+    match (base, exponent) {
+        (_, exponent) if is_literal(&exponent, 0.0) => Expr::literal(1.0),
+        (base, exponent) if is_literal(&exponent, 1.0) => base,
+        (base, exponent) => Expr::binary(BinaryOp::Pow, base, exponent),
+    }
+}
+
+fn negate(operand: Expr) -> Expr {
+    match operand {
+        Expr::Literal(x) => Expr::literal(-x),
+        Expr::Unary(UnaryOp::Neg, operand) => *operand,
+        operand => Expr::unary(UnaryOp::Neg, operand),
+    }
+}
+
+
+// Splits the arguments of "diff(expr, x)" and "diff(expr, x, point)".
+pub fn diff_args(args: &[Expr]) -> Result<(&Expr, &str, Option<&Expr>), EvalError> {
+    let (expr, var, point) = match args {
+        [expr, var] => (expr, var, None),
+        [expr, var, point] => (expr, var, Some(point)),
+        _ => return Err(EvalError::ArgumentCount("diff".to_string(), Arity::Range(2, 3), args.len())),
+    };
+    match var.inner() {
+        Expr::Var(name) => Ok((expr, name, point)),
+        other => Err(EvalError::ExpectedVariable(other.to_string())),
+    }
+}
+
+
+impl Expr {
+    pub fn depends_on(&self, var: &str) -> bool {
+        match self {
+            Self::Literal(_) | Self::Integer(_) | Self::Decimal(_) => false,
+            Self::Var(name) => name == var,
+            Self::Unary(_, operand) | Self::Postfix(_, operand) => operand.depends_on(var),
+            Self::Binary(_, lhs, rhs) => lhs.depends_on(var) || rhs.depends_on(var),
+            Self::Call(_, args) | Self::List(args) => args.iter().any(|arg| arg.depends_on(var)),
+            Self::Index(target, indices) => target.depends_on(var) || indices.iter().any(|index| index.depends_on(var)),
+            Self::Piecewise(clauses, otherwise) => {
+                clauses.iter().any(|(value, condition)| value.depends_on(var) || condition.depends_on(var))
+                    || otherwise.as_ref().is_some_and(|otherwise| otherwise.depends_on(var))
+            }
+            Self::Assign(_, value) => value.depends_on(var),
+            Self::Define(_, params, body) => !params.iter().any(|param| param == var) && body.depends_on(var),
+            Self::At(_, expr) => expr.depends_on(var),
+        }
+    }
+
+    // Replaces every parameter with its argument at once, so "f(y, x)" can swap names safely.
+    // With "products" a parameter before brackets is multiplied, as implicit multiplication reads "x(x + 1)".
+    pub fn substitute(&self, params: &[String], args: &[Expr], products: bool) -> Expr {
+        let substitute_all = |exprs: &[Expr]| exprs.iter().map(|expr| expr.substitute(params, args, products)).collect();
+        match self {
+            Self::Literal(_) | Self::Integer(_) | Self::Decimal(_) => self.clone(),
+            Self::Var(name) => match params.iter().position(|param| param == name) {
+                Some(i) => args[i].clone(),
+                None => self.clone(),
+            },
+            Self::Unary(op, operand) => Expr::unary(op.clone(), operand.substitute(params, args, products)),
+            Self::Postfix(op, operand) => Expr::postfix(op.clone(), operand.substitute(params, args, products)),
+            Self::Binary(..) => {
+                let (first, links) = self.left_chain(|_, _| true);
+                links.into_iter().fold(first.substitute(params, args, products), |lhs, link| {
+                    let expr = Expr::binary(link.op.clone(), lhs, link.rhs.substitute(params, args, products));
+                    match link.span {
+                        Some(span) => Expr::at(span, expr),
+                        None => expr,
+                    }
+                })
+            }
+            Self::Call(name, call_args) => match (params.iter().position(|param| param == name), call_args.as_slice()) {
+                (Some(i), [arg]) if products => Expr::binary(BinaryOp::Mul, args[i].clone(), arg.substitute(params, args, products)),
+                _ => Self::Call(name.clone(), substitute_all(call_args)),
+            },
+            Self::List(items) => Self::List(substitute_all(items)),
+            Self::Index(target, indices) => Self::Index(Box::new(target.substitute(params, args, products)), substitute_all(indices)),
+            Self::Piecewise(clauses, otherwise) => {
+                let substitute = |expr: &Expr| expr.substitute(params, args, products);
+                map_clauses(clauses, otherwise, substitute, substitute)
+            }
+            Self::Assign(name, value) => Expr::assign(name, value.substitute(params, args, products)),
+            Self::Define(..) => self.clone(),
+            Self::At(span, expr) => Expr::at(*span, expr.substitute(params, args, products)),
+        }
+    }
+
+    // Expands calls to user functions and nested "diff" calls, so only built-in functions remain.
+    pub fn inline<N: Number>(&self, context: &Context<N>, depth: usize) -> Result<Expr, EvalError> {
+        let inline_all = |exprs: &[Expr]| exprs.iter()
+            .map(|expr| expr.inline(context, depth))
+            .collect::<Result<Vec<Expr>, EvalError>>();
+
+        match self {
+            // Spans are dropped here, the result is not tied to the input anymore
+            Self::At(_, expr) => expr.inline(context, depth),
+            Self::Literal(_) | Self::Integer(_) | Self::Decimal(_) | Self::Var(_) | Self::Define(..) => Ok(self.clone()),
+            Self::Unary(op, operand) => Ok(Expr::unary(op.clone(), operand.inline(context, depth)?)),
+            Self::Postfix(op, operand) => Ok(Expr::postfix(op.clone(), operand.inline(context, depth)?)),
+            Self::Binary(..) => {
+                let (first, links) = self.left_chain(|_, _| true);
+                links.into_iter().try_fold(first.inline(context, depth)?, |lhs, link| {
+                    Ok(Expr::binary(link.op.clone(), lhs, link.rhs.inline(context, depth)?))
+                })
+            }
+            Self::List(items) => Ok(Self::List(inline_all(items)?)),
+            Self::Index(target, indices) => Ok(Self::Index(Box::new(target.inline(context, depth)?), inline_all(indices)?)),
+            Self::Piecewise(clauses, otherwise) => {
+                let inline = |expr: &Expr| expr.inline(context, depth);
+                try_map_clauses(clauses, otherwise, inline, inline)
+            }
+            Self::Assign(name, value) => Ok(Expr::assign(name, value.inline(context, depth)?)),
+            Self::Call(name, args) => match context.user_func(name) {
+                Some(func) => {
+                    if func.params.len() != args.len() {
+                        return Err(EvalError::ArgumentCount(name.clone(), Arity::Exact(func.params.len()), args.len()));
+                    }
+                    if depth >= MAX_CALL_DEPTH {
+                        return Err(EvalError::RecursionLimit(name.clone()));
+                    }
+                    func.body.substitute(&func.params, &inline_all(args)?, !context.strict()).inline(context, depth + 1)
+                }
+                None if name == "simplify" && args.len() == 1 => args[0].inline(context, depth)?.try_simplify(),
+                None if name == "diff" => {
+                    let (expr, var, point) = diff_args(args)?;
+                    let derivative = expr.inline_derivative(var, context, depth)?;
+                    match point {
+                        Some(point) => Ok(derivative.substitute(&[var.to_string()], &[point.inline(context, depth)?], false)),
+                        None => Ok(derivative),
+                    }
+                }
+                // A known variable before brackets is a product, as when evaluating
+                None if !context.strict() && args.len() == 1 && context.functions().get(name).is_none() && context.var(name).is_some() => {
+                    Ok(Expr::binary(BinaryOp::Mul, Self::Var(name.clone()), args[0].inline(context, depth)?))
+                }
+                None => Ok(Self::Call(name.clone(), inline_all(args)?)),
+            },
+        }
+    }
+
+    // Derivative with respect to "var", user functions are expanded first.
+    pub fn diff<N: Number>(&self, var: &str, context: &Context<N>) -> Result<Expr, EvalError> {
+        self.inline_derivative(var, context, 0)
+    }
+
+    // "x(u)" with x the variable itself is the product x*u, unless implicit multiplication is off.
+    // Differentiating recurses into every operand, long sums included, so deeper expressions are refused.
+    fn inline_derivative<N: Number>(&self, var: &str, context: &Context<N>, depth: usize) -> Result<Expr, EvalError> {
+        let expr = self.inline(context, depth)?;
+        if expr.height() > MAX_DEPTH {
+            return Err(EvalError::TooDeep("differentiate".to_string()));
+        }
+        match context.strict() {
+            true => expr.derivative(var),
+            false => expr.substitute(&[var.to_string()], &[Expr::var(var)], true).derivative(var),
+        }
+    }
+
+    fn derivative(&self, var: &str) -> Result<Expr, EvalError> {
+        if !matches!(self, Self::List(_)) && !self.depends_on(var) {
+            return Ok(Expr::literal(0.0));
+        }
+
+        let expr = match self {
+            Self::Literal(_) | Self::Integer(_) | Self::Decimal(_) => Expr::literal(0.0),
+            Self::Var(_) => Expr::literal(1.0),
+            Self::Unary(UnaryOp::Neg, operand) => negate(operand.derivative(var)?),
+            // Conditions are piecewise constant, their derivative is 0 wherever it exists
+            Self::Unary(UnaryOp::Not, _) => Expr::literal(0.0),
+            Self::Postfix(PostfixOp::Percent, operand) => quotient(operand.derivative(var)?, Expr::literal(100.0)),
+            // Would need the digamma function
+            Self::Postfix(PostfixOp::Factorial, _) => return Err(EvalError::NotDifferentiable("!".to_string())),
+            Self::Binary(op, ..) if op.is_comparison() || matches!(op, BinaryOp::And | BinaryOp::Or) => Expr::literal(0.0),
+            Self::Binary(op, lhs, rhs) => {
+                let (u, v) = (*lhs.clone(), *rhs.clone());
+                let (du, dv) = (lhs.derivative(var)?, rhs.derivative(var)?);
+                match op {
+                    BinaryOp::Add => sum(du, dv),
+                    BinaryOp::Sub => difference(du, dv),
+                    BinaryOp::Mul => sum(product(du, v), product(u, dv)),
+                    BinaryOp::Div if !rhs.depends_on(var) => quotient(du, v),
+                    BinaryOp::Div => quotient(difference(product(du, v.clone()), product(u, dv)), power(v, Expr::literal(2.0))),
+                    // x mod c only jumps where x crosses a multiple of c, between those it moves with x
+                    BinaryOp::Mod if !rhs.depends_on(var) => du,
+                    BinaryOp::Mod => return Err(EvalError::NotDifferentiable("mod".to_string())),
+                    // Steps between multiples of a constant divisor, flat everywhere else
+                    BinaryOp::FloorDiv if !rhs.depends_on(var) => Expr::literal(0.0),
+                    BinaryOp::FloorDiv => return Err(EvalError::NotDifferentiable("div".to_string())),
+                    // Power rule for constant exponents, exponential rule for constant bases
+                    BinaryOp::Pow if !rhs.depends_on(var) => {
+                        product(product(v.clone(), power(u, difference(v, Expr::literal(1.0)))), du)
+                    }
+                    BinaryOp::Pow if !lhs.depends_on(var) => {
+                        product(product(self.clone(), Expr::call("ln", vec![u])), dv)
+                    }
+                    BinaryOp::Pow => {
+                        let inner = sum(product(dv, Expr::call("ln", vec![u.clone()])), quotient(product(v, du), u));
+                        product(self.clone(), inner)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Self::Call(name, args) => match (name.as_str(), args.as_slice()) {
+                ("if", [condition, then, otherwise]) => {
+                    Expr::call("if", vec![condition.clone(), then.derivative(var)?, otherwise.derivative(var)?])
+                }
+                ("log", [base, x]) => {
+                    let ln = |expr: &Expr| Expr::call("ln", vec![expr.clone()]);
+                    quotient(ln(x), ln(base)).derivative(var)?
+                }
+                (_, [u]) => {
+                    let du = u.derivative(var)?;
+                    let call = |name: &str| Expr::call(name, vec![u.clone()]);
+                    match name.as_str() {
+                        "sin" => product(call("cos"), du),
+                        "cos" => negate(product(call("sin"), du)),
+                        "tan" => quotient(du, power(call("cos"), Expr::literal(2.0))),
+                        "exp" => product(call("exp"), du),
+                        "ln" => quotient(du, u.clone()),
+                        "log" => quotient(du, product(u.clone(), Expr::call("ln", vec![Expr::literal(10.0)]))),
+                        "sqrt" => quotient(du, product(Expr::literal(2.0), call("sqrt"))),
+                        _ => return Err(EvalError::NotDifferentiable(name.clone())),
+                    }
+                }
+                _ => return Err(EvalError::NotDifferentiable(name.clone())),
+            },
+            Self::List(items) => Self::List(items.iter()
+                .map(|item| item.derivative(var))
+                .collect::<Result<Vec<Expr>, EvalError>>()?),
+            Self::Index(target, indices) => Self::Index(Box::new(target.derivative(var)?), indices.clone()),
+            // Differentiated clause by clause, the conditions stay as they are
+            Self::Piecewise(clauses, otherwise) => try_map_clauses(clauses, otherwise, |value| value.derivative(var), |condition| Ok(condition.clone()))?,
+            Self::Assign(..) | Self::Define(..) => return Err(EvalError::IncorrectAssignment(self.to_string())),
+            Self::At(_, expr) => expr.derivative(var)?,
+        };
+        Ok(expr)
+    }
+}
+
+
+#[test]
+fn test_derivative_0() {
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    let context: Context = Context::new();
+    let diff = |input: &str| parse(tokenize(input).unwrap()).unwrap().diff("x", &context).unwrap().to_string();
+
+    assert!(diff("x^3 + 2*x - 7") == "3*x^2 + 2", "Got {}", diff("x^3 + 2*x - 7"));
+    assert!(diff("sin(x)*cos(x)") == "cos(x)*cos(x) - sin(x)*sin(x)", "Got {}", diff("sin(x)*cos(x)"));
+    assert!(diff("ln(x^2)") == "2*x/x^2", "Got {}", diff("ln(x^2)"));
+    assert!(diff("exp(2*x)") == "2*exp(2*x)", "Got {}", diff("exp(2*x)"));
+    assert!(diff("sqrt(x)") == "1/(2*sqrt(x))", "Got {}", diff("sqrt(x)"));
+    assert!(diff("1/x") == "-1/x^2", "Got {}", diff("1/x"));
+    assert!(diff("2^x") == "2^x*ln(2)", "Got {}", diff("2^x"));
+    assert!(diff("y*tan(x)") == "y*(1/cos(x)^2)", "Got {}", diff("y*tan(x)"));
+}
+
+#[test]
+fn test_derivative_1() {
+    use crate::{object::Object, parser::parse, tokenizer::tokenize};
+
+    let mut context: Context = Context::new();
+    let define = parse(tokenize("f(x, y) = x^2*y").unwrap()).unwrap();
+    crate::evaluator::evaluate(&define, &mut context).unwrap();
+
+    let eval = |input: &str| parse(tokenize(input).unwrap()).unwrap().eval(&context).map_err(EvalError::without_span);
+    assert!(eval("diff(f(t, 3), t, 2)").unwrap() == Object::Scalar(12.0));
+    assert!(eval("diff(diff(x^3, x), x, 1)").unwrap() == Object::Scalar(6.0));
+    assert!(eval("diff(if(x > 0 && x < 10, x^2, -x), x, 3)").unwrap() == Object::Scalar(6.0));
+    assert!(eval("diff(if(x > 0, x^2, -x), x, -3)").unwrap() == Object::Scalar(-1.0));
+    assert!(eval("diff({ x^3 if x < 0; 5*x otherwise }, x, -2)").unwrap() == Object::Scalar(12.0));
+    assert!(matches!(eval("diff(x^2, 2)"), Err(EvalError::ExpectedVariable(_))));
+    assert!(matches!(eval("diff(abs(x), x, 1)"), Err(EvalError::NotDifferentiable(_))));
+    assert!(eval("diff(x^2*5%, x, 10)").unwrap() == Object::Scalar(1.0));
+    assert!(matches!(eval("diff(x!, x, 1)"), Err(EvalError::NotDifferentiable(name)) if name == "!"));
+    assert!(eval("diff(x div 3 + x mod 3, x, 4.5)").unwrap() == Object::Scalar(1.0));
+    assert!(matches!(eval("diff(3 div x, x, 1)"), Err(EvalError::NotDifferentiable(name)) if name == "div"));
+    // x(x + 1) is x*(x + 1), whose derivative is 2x + 1
+    assert!(eval("diff(x(x + 1), x, 3)").unwrap() == Object::Scalar(7.0));
+}
@@ -0,0 +1,242 @@
+use std::fmt::Display;
+
+use crate::{
+    app_context::{Arity, Context},
+    ast::Expr,
+    compile::SlotExpr,
+    error::Error,
+    evaluator::{evaluate, EvalOutput},
+    function::{Function, ScalarFunc},
+    number::{Number, Settings},
+    object::Object,
+    parser::{insert_implicit_mul, parse, validate},
+    tokenizer::tokenize,
+    vm::Program,
+};
+
+
+// Parsed input that can be run any number of times.
+#[derive(Debug, Clone)]
+pub struct CompiledExpr {
+    source: String,
+    expr: Expr,
+}
+
+impl CompiledExpr {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+}
+
+impl Display for CompiledExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.expr.fmt(f)
+    }
+}
+
+
+// Entry point for embedding, runs input lines against its own variables and functions.
+pub struct Engine<N: Number = f64> {
+    context: Context<N>,
+}
+
+impl<N: Number> Default for Engine<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: Number> Engine<N> {
+    pub fn new() -> Self {
+        Engine{context: Context::new()}
+    }
+
+    pub fn strict(&self) -> bool {
+        self.context.strict()
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.context.set_strict(strict);
+    }
+
+    // Precision and format of the results, they apply on whichever thread the engine is used from.
+    pub fn settings(&self) -> Settings {
+        self.context.settings()
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.context.set_settings(settings);
+    }
+
+    // Prints a result with the engine's settings, "to_string" outside the engine uses the defaults.
+    pub fn display(&self, value: &impl Display) -> String {
+        N::scoped(&self.settings(), || value.to_string())
+    }
+
+    pub fn compile(&self, input: &str) -> Result<CompiledExpr, Error> {
+        let mut tokens = tokenize(input)?;
+        if !self.strict() {
+            tokens = insert_implicit_mul(tokens);
+        }
+        validate(&tokens)?;
+        Ok(CompiledExpr{source: input.to_string(), expr: parse(tokens)?})
+    }
+
+    // Compiles for repeated evaluation, the named variables are bound per call and everything else is fixed now.
+    pub fn compile_slots(&self, input: &str, slots: &[&str]) -> Result<SlotExpr<N>, Error> {
+        let compiled = self.compile(input)?;
+        Ok(SlotExpr::compile(&compiled.expr, slots, &self.context)?)
+    }
+
+    pub fn compile_program(&self, input: &str, slots: &[&str]) -> Result<Program<N>, Error> {
+        let compiled = self.compile(input)?;
+        Ok(Program::compile(&compiled.expr, slots, &self.context)?)
+    }
+
+    // Runs a line of input, assignments and definitions change the engine.
+    pub fn run(&mut self, compiled: &CompiledExpr) -> Result<EvalOutput<N>, Error> {
+        Ok(evaluate(&compiled.expr, &mut self.context)?)
+    }
+
+    pub fn eval(&mut self, input: &str) -> Result<EvalOutput<N>, Error> {
+        let compiled = self.compile(input)?;
+        self.run(&compiled)
+    }
+
+    // Value of an expression without changing anything, so a shared engine can be used from many threads.
+    // Assignments and definitions are errors here.
+    pub fn value_of(&self, compiled: &CompiledExpr) -> Result<Object<N>, Error> {
+        Ok(compiled.expr.eval(&self.context)?)
+    }
+
+    pub fn value(&self, input: &str) -> Result<Object<N>, Error> {
+        self.value_of(&self.compile(input)?)
+    }
+
+    pub fn var(&self, name: &str) -> Option<Object<N>> {
+        N::scoped(&self.settings(), || self.context.var(name))
+    }
+
+    pub fn set_var(&mut self, name: &str, value: impl Into<Object<N>>) {
+        self.context.set_var(name, value.into());
+    }
+
+    // Makes a host function callable from expressions, it replaces a built-in of the same name.
+    pub fn register(&mut self, name: &str, func: impl Function<N> + 'static) {
+        self.context.functions_mut().register(name, func);
+    }
+
+    pub fn register_function(&mut self, name: &str, arity: Arity, func: impl Fn(&[N]) -> N + Send + Sync + 'static) {
+        self.register(name, ScalarFunc::new(arity, func));
+    }
+
+    pub fn context(&self) -> &Context<N> {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context<N> {
+        &mut self.context
+    }
+}
+
+
+#[test]
+fn test_engine_0() {
+    let mut engine: Engine = Engine::new();
+    assert!(matches!(engine.eval("x = 2"), Ok(EvalOutput::Assignment(..))));
+    assert!(matches!(engine.eval("x^2 + 1"), Ok(EvalOutput::Value(Object::Scalar(5.0)))));
+
+    let compiled = engine.compile("a*x + 1").unwrap();
+    let results: Vec<f64> = (1..=3)
+        .map(|a| {
+            engine.set_var("a", a as f64);
+            match engine.run(&compiled) {
+                Ok(EvalOutput::Value(Object::Scalar(x))) => x,
+                other => panic!("Expected a scalar, got {other:?}"),
+            }
+        })
+        .collect();
+    assert!(results == [3.0, 5.0, 7.0], "Got {results:?}");
+
+    let error = engine.eval("1 + (2").unwrap_err();
+    assert!(error.code() == "E0101");
+}
+
+#[test]
+fn test_engine_1() {
+    let mut engine: Engine = Engine::new();
+    engine.register_function("rate", Arity::Exact(1), |args| if args[0] == 1.0 { 0.25 } else { 0.5 });
+    engine.register_function("sum", Arity::AtLeast(0), |args| args.iter().sum());
+
+    assert!(matches!(engine.eval("rate(1)*sum(1, 2, 3)"), Ok(EvalOutput::Value(Object::Scalar(1.5)))));
+    assert!(matches!(engine.eval("sum(4)"), Ok(EvalOutput::Value(Object::Scalar(4.0)))));
+    assert!(matches!(engine.eval("rate(1, 2)").map_err(|e| e.code()), Err("E0201")));
+}
+
+#[test]
+fn test_engine_2() {
+    fn shareable<T: Send + Sync>() {}
+    shareable::<Engine>();
+    shareable::<CompiledExpr>();
+
+    let mut engine: Engine = Engine::new();
+    engine.eval("f(t) = 2*t").unwrap();
+    engine.set_var("k", 10.0);
+    let compiled = engine.compile("f(k) + 1").unwrap();
+
+    let engine = &engine;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4).map(|_| scope.spawn(|| engine.value_of(&compiled).ok())).collect();
+        assert!(handles.into_iter().all(|handle| handle.join().unwrap() == Some(Object::Scalar(21.0))));
+    });
+    assert!(matches!(engine.value("k = 2").map_err(|e| e.code()), Err("E0205")));
+    assert!(engine.var("k") == Some(Object::Scalar(10.0)));
+}
+
+#[test]
+fn test_engine_3() {
+    let mut engine: Engine = Engine::new();
+    engine.set_var("x", 3.0);
+    assert!(matches!(engine.value("2x + 3(x - 1)"), Ok(Object::Scalar(12.0))));
+
+    engine.set_strict(true);
+    assert!(matches!(engine.value("2x").map_err(|e| e.code()), Err("E0105")));
+    assert!(matches!(engine.value("2*x"), Ok(Object::Scalar(6.0))));
+    // A variable before brackets is a call again, in every path that resolves calls
+    assert!(matches!(engine.value("x(2)").map_err(|e| e.code()), Err("E0214")));
+    assert!(matches!(engine.value("diff(t(t + 1), t, 1)").map_err(|e| e.code()), Err("E0209")));
+    assert!(matches!(engine.compile_slots("x(2) + t", &["t"]).map_err(|e| e.code()), Err("E0214")));
+    assert!(matches!(engine.compile_slots("t(2)", &["t"]).map_err(|e| e.code()), Err("E0214")));
+    engine.eval("f(t) = t(2)").unwrap();
+    assert!(matches!(engine.value("f(3)").map_err(|e| e.code()), Err("E0214")));
+    assert!(matches!(engine.compile_slots("f(t)", &["t"]).map_err(|e| e.code()), Err("E0214")));
+
+    engine.set_strict(false);
+    assert!(matches!(engine.value("x(2) + f(3)"), Ok(Object::Scalar(12.0))));
+}
+
+#[test]
+fn test_engine_4() {
+    let mut engine: Engine = Engine::new();
+    engine.set_var("x", 2.0);
+
+    // Long sums are not nested, every path walks them in a loop
+    let sum = format!("x{}", " + 1".repeat(9_999));
+    assert!(matches!(engine.value(&sum), Ok(Object::Scalar(10001.0))));
+    assert!(engine.compile(&sum).unwrap().to_string() == sum);
+    assert!(engine.compile_slots(&sum, &["x"]).unwrap().eval(&[1.0]).is_ok_and(|result| result == 10000.0));
+    assert!(engine.compile_program(&sum, &["x"]).unwrap().eval(&mut [1.0]).is_ok_and(|result| result == 10000.0));
+    let chain = format!("x > 0{}", " && x < 3".repeat(9_999));
+    assert!(engine.compile_program(&chain, &["x"]).unwrap().eval(&mut [1.0]).is_ok_and(|result| result == 1.0));
+    engine.eval(&format!("f(t) = {}", sum.replace('x', "t"))).unwrap();
+    assert!(engine.compile_slots("f(x) - x", &["x"]).unwrap().eval(&[5.0]).is_ok_and(|result| result == 9999.0));
+
+    // Simplifying and differentiating still recurse, so they refuse such sums
+    assert!(matches!(engine.eval(&format!("simplify({sum})")).map_err(|e| e.code()), Err("E0219")));
+    assert!(matches!(engine.eval(&format!("diff({sum}, x)")).map_err(|e| e.code()), Err("E0219")));
+    assert!(matches!(engine.value(&format!("diff({sum}, x, 1)")).map_err(|e| e.code()), Err("E0219")));
+}
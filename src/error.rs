@@ -0,0 +1,92 @@
+use std::fmt::Display;
+
+use crate::{evaluator::EvalError, parser::ParserError, span::Span, tokenizer::TokenizerError};
+
+
+// Any error from reading, parsing or evaluating an input line.
+#[derive(Debug)]
+pub enum Error {
+    Tokenize(TokenizerError),
+    Parse(ParserError),
+    Eval(EvalError),
+}
+
+impl Error {
+    // Stable code of the underlying variant, E00xx for the tokenizer, E01xx for the parser and E02xx for the evaluator.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Tokenize(error) => error.code(),
+            Self::Parse(error) => error.code(),
+            Self::Eval(error) => error.code(),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Tokenize(error) => Some(error.span()),
+            Self::Parse(error) => Some(error.span()),
+            Self::Eval(error) => error.span(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tokenize(error) => error.fmt(f),
+            Self::Parse(error) => error.fmt(f),
+            Self::Eval(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Tokenize(error) => Some(error),
+            Self::Parse(error) => Some(error),
+            Self::Eval(error) => Some(error),
+        }
+    }
+}
+
+impl From<TokenizerError> for Error {
+    fn from(value: TokenizerError) -> Self {
+        Self::Tokenize(value)
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(value: ParserError) -> Self {
+        Self::Parse(value)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(value: EvalError) -> Self {
+        Self::Eval(value)
+    }
+}
+
+
+#[test]
+fn test_error_0() {
+    use crate::{app_context::Context, evaluator::evaluate, parser::{parse, validate}, tokenizer::tokenize};
+
+    let run = |input: &str| -> Result<(), Error> {
+        let tokens = tokenize(input)?;
+        validate(&tokens)?;
+        evaluate(&parse(tokens)?, &mut Context::<f64>::new())?;
+        Ok(())
+    };
+
+    let error = run("1 + #").unwrap_err();
+    assert!(error.code() == "E0002" && error.span() == Some(Span::new(4, 5)));
+
+    let error = run("(1 + 2").unwrap_err();
+    assert!(error.code() == "E0101" && matches!(error, Error::Parse(ParserError::UnevenBrackets(_))));
+
+    let error = run("2*y").unwrap_err();
+    assert!(error.code() == "E0213" && error.span() == Some(Span::new(2, 3)));
+    assert!(std::error::Error::source(&error).is_some_and(|source| source.to_string() == error.to_string()));
+}
@@ -1,127 +1,519 @@
 use std::fmt::Display;
 
-use crate::{app_context::Context, tokens::{BinaryOp, Function, Token, UnaryOp, Value}};
+use crate::{app_context::{Arity, Context, UserFunc}, ast::Expr, derivative::diff_args, number::Number, parser::MAX_DEPTH, span::Span, object::{Object, Shape}, tokens::{BinaryOp, UnaryOp}};
 
 #[derive(Debug)]
 pub enum EvalError {
-    MissingArgument,
-    InvalidToken,
-    MissingResult,
-    NotImplemented(Token),
-    IncorrectAssignment(Token),
+    ArgumentCount(String, Arity, usize),
+    ExpectedScalar(Shape),
+    ExpectedInteger(String),
+    ExpectedSquareMatrix(Shape),
+    ExpectedVariable(String),
+    IncorrectAssignment(String),
+    IndexOutOfRange(String, usize),
+    InvalidArgument(String, String),
+    InvalidIndex(Shape, usize),
+    InvalidList(Shape),
+    NoMatchingClause(String),
+    NotCompilable(String),
+    NotDifferentiable(String),
+    RecursionLimit(String),
+    ShapeMismatch(String, Shape, Shape),
+    SingularMatrix,
+    TooDeep(String),
     UndefinedVariable(String),
     UndfinedFunction(String),
+    // Where in the input the error happened, set by the innermost expression that has a span
+    At(Span, Box<EvalError>),
+}
+
+impl EvalError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ArgumentCount(..) => "E0201",
+            Self::ExpectedScalar(_) => "E0202",
+            Self::ExpectedSquareMatrix(_) => "E0203",
+            Self::ExpectedVariable(_) => "E0204",
+            Self::IncorrectAssignment(_) => "E0205",
+            Self::IndexOutOfRange(..) => "E0206",
+            Self::InvalidIndex(..) => "E0207",
+            Self::InvalidList(_) => "E0208",
+            Self::NotDifferentiable(_) => "E0209",
+            Self::RecursionLimit(_) => "E0210",
+            Self::ShapeMismatch(..) => "E0211",
+            Self::SingularMatrix => "E0212",
+            Self::UndefinedVariable(_) => "E0213",
+            Self::UndfinedFunction(_) => "E0214",
+            Self::NotCompilable(_) => "E0215",
+            Self::NoMatchingClause(_) => "E0216",
+            Self::ExpectedInteger(_) => "E0217",
+            Self::InvalidArgument(..) => "E0218",
+            Self::TooDeep(_) => "E0219",
+            Self::At(_, error) => error.code(),
+        }
+    }
+
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Self::At(..) => self,
+            error => Self::At(span, Box::new(error)),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::At(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+
+    pub fn without_span(self) -> Self {
+        match self {
+            Self::At(_, error) => *error,
+            error => error,
+        }
+    }
 }
 
 impl Display for EvalError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Self::At(_, error) = self {
+            return error.fmt(f);
+        }
         write!(f, "EvaluatorError -> ")?;
         match self {
-            Self::MissingArgument => write!(f, "Missing Argument"),
-            Self::InvalidToken => write!(f, "Invalid Token"),
-            Self::MissingResult => write!(f, "Missing Result"),
-            Self::NotImplemented(token) => write!(f, "Not Implemented: {token}"),
-            Self::IncorrectAssignment(token) => write!(f, "Cannot assign to {token}"),
+            Self::ArgumentCount(name, arity, count) => write!(f, "\"{name}\" takes {arity} argument(s), got {count}"),
+            Self::ExpectedScalar(shape) => write!(f, "Expected a scalar, got a {shape}"),
+            Self::ExpectedInteger(x) => write!(f, "Expected an integer, got {x}"),
+            Self::ExpectedSquareMatrix(shape) => write!(f, "Expected a square matrix, got a {shape}"),
+            Self::ExpectedVariable(expr) => write!(f, "Expected a variable name, got {expr}"),
+            Self::IncorrectAssignment(expr) => write!(f, "Cannot assign inside {expr}"),
+            Self::IndexOutOfRange(index, len) => write!(f, "Index {index} is out of range 1 to {len}"),
+            Self::InvalidArgument(name, reason) => write!(f, "Invalid argument to \"{name}\": {reason}"),
+            Self::InvalidIndex(shape, count) => write!(f, "Cannot index a {shape} with {count} index(es)"),
+            Self::InvalidList(shape) => write!(f, "A {shape} cannot be an item of this list"),
+            Self::NoMatchingClause(expr) => write!(f, "No clause of {expr} holds"),
+            Self::NotCompilable(expr) => write!(f, "Cannot compile {expr}, only scalar expressions can be compiled"),
+            Self::NotDifferentiable(name) => write!(f, "Cannot differentiate \"{name}\""),
+            Self::RecursionLimit(name) => write!(f, "Recursion limit reached in \"{name}\""),
+            Self::ShapeMismatch(op, lhs, rhs) => write!(f, "Cannot apply \"{op}\" to a {lhs} and a {rhs}"),
+            Self::SingularMatrix => write!(f, "Matrix is singular"),
+            Self::TooDeep(name) => write!(f, "Cannot {name} an expression nested over {MAX_DEPTH} levels deep"),
             Self::UndefinedVariable(name) => write!(f, "Undefined Variable: \"{name}\""),
             Self::UndfinedFunction(name) => write!(f, "Undefined Function: \"{name}\""),
+            Self::At(..) => unreachable!(),
         }
     }
 }
 
+impl std::error::Error for EvalError {}
+
 
 #[derive(Debug)]
-pub enum EvalOutput {
-    Value(f32),
-    Assignment(String, f32),
+pub enum EvalOutput<N: Number = f64> {
+    Value(Object<N>),
+    Assignment(String, Object<N>),
+    Definition(String, Vec<String>),
+    Expression(Expr),
 }
 
 
-impl Value {
-    pub fn collect(self, context: &Context) -> Result<f32, EvalError> {
-        match self {
-            Value::Scalar(x) => Ok(x),
-            Value::Var(name) => match context.var(&name) {
-                Some(x) => Ok(x),
-                None => Err(EvalError::UndefinedVariable(name.clone())),
-            },
-        }
-    }
+pub const MAX_CALL_DEPTH: usize = 256;
 
-    pub fn add(self, other: Value, context: &Context) -> Result<Value, EvalError> {
-        Ok(Value::Scalar(self.collect(context)? + other.collect(context)?))
-    }
+// Parameters bound by the user function currently being evaluated.
+// A derivative taken at a point binds its variable in a frame on top of the current one.
+struct Frame<'a, N: Number> {
+    params: &'a [String],
+    args: &'a [Object<N>],
+    depth: usize,
+    parent: Option<&'a Frame<'a, N>>,
+}
 
-    pub fn sub(self, other: Value, context: &Context) -> Result<Value, EvalError> {
-        Ok(Value::Scalar(self.collect(context)? - other.collect(context)?))
+impl<N: Number> Frame<'_, N> {
+    fn var(&self, name: &str) -> Option<Object<N>> {
+        self.params.iter()
+            .position(|param| param == name)
+            .map(|i| self.args[i].clone())
+            .or_else(|| self.parent?.var(name))
     }
+}
+
 
-    pub fn mul(self, other: Value, context: &Context) -> Result<Value, EvalError> {
-        Ok(Value::Scalar(self.collect(context)? * other.collect(context)?))
+impl Expr {
+    pub fn eval<N: Number>(&self, context: &Context<N>) -> Result<Object<N>, EvalError> {
+        N::scoped(&context.settings(), || self.eval_in(context, &Frame{params: &[], args: &[], depth: 0, parent: None}))
     }
 
-    pub fn div(self, other: Value, context: &Context) -> Result<Value, EvalError> {
-        Ok(Value::Scalar(self.collect(context)? / other.collect(context)?))
+    // Kept out of eval_in along with eval_if, since eval_in recurses for every user function call and its frame adds up.
+    // Left operands of a long chain are evaluated in a loop, so a long sum does not recurse once per term.
+    fn eval_chain<N: Number>(&self, context: &Context<N>, frame: &Frame<N>) -> Result<Object<N>, EvalError> {
+        let at = |span: Option<Span>, e: EvalError| match span {
+            Some(span) => e.at(span),
+            None => e,
+        };
+        let (first, links) = self.left_chain(|_, _| true);
+        let mut value = first.eval_in(context, frame).map_err(|e| at(links[0].span, e))?;
+        for link in links {
+            value = Self::eval_binary(link.op, value, link.rhs, context, frame).map_err(|e| at(link.span, e))?;
+        }
+        Ok(value)
     }
 
-    pub fn pow(self, other: Value, context: &Context) -> Result<Value, EvalError> {
-        Ok(Value::Scalar(self.collect(context)?.powf(other.collect(context)?)))
+    // For "&&" and "||" the right side is only evaluated when the left one does not decide the result.
+    fn eval_binary<N: Number>(op: &BinaryOp, a: Object<N>, rhs: &Expr, context: &Context<N>, frame: &Frame<N>) -> Result<Object<N>, EvalError> {
+        match op {
+            BinaryOp::And => Ok(Object::Scalar(N::from_bool(a.is_true()? && rhs.eval_in(context, frame)?.is_true()?))),
+            BinaryOp::Or => Ok(Object::Scalar(N::from_bool(a.is_true()? || rhs.eval_in(context, frame)?.is_true()?))),
+            op => {
+                let b = rhs.eval_in(context, frame)?;
+                match op {
+                    BinaryOp::Add => a.try_add(b),
+                    BinaryOp::Sub => a.try_sub(b),
+                    BinaryOp::Mul => a.try_mul(b),
+                    BinaryOp::Div => a.try_div(b),
+                    BinaryOp::Mod => a.try_mod(b),
+                    BinaryOp::FloorDiv => a.try_div_floor(b),
+                    BinaryOp::Pow => a.pow(b),
+                    op => a.try_compare(b, op),
+                }
+            }
+        }
     }
 
-    pub fn neg(self, context: &Context) -> Result<Value, EvalError> {
-        Ok(Value::Scalar(-1.0 * self.collect(context)?))
+    // "x(x + 1)" is parsed as a call, with no function of that name and a variable x it is a product.
+    fn call_or_product<N: Number>(name: &str, mut args: Vec<Object<N>>, context: &Context<N>, frame: &Frame<N>) -> Result<Object<N>, EvalError> {
+        if !context.strict() && args.len() == 1 && context.functions().get(name).is_none() {
+            if let Some(x) = frame.var(name).or_else(|| context.var(name)) {
+                return x.try_mul(args.remove(0));
+            }
+        }
+        context.call_func(name, args)
     }
-}
 
+    // Only the chosen branch is evaluated.
+    fn eval_if<N: Number>(args: &[Expr], context: &Context<N>, frame: &Frame<N>) -> Result<Object<N>, EvalError> {
+        match args {
+            [condition, then, otherwise] => match condition.eval_in(context, frame)?.is_true()? {
+                true => then.eval_in(context, frame),
+                false => otherwise.eval_in(context, frame),
+            },
+            _ => Err(EvalError::ArgumentCount("if".to_string(), Arity::Exact(3), args.len())),
+        }
+    }
 
-pub fn evaluate(postfix_tokens: Vec<Token>, context: &mut Context) -> Result<EvalOutput, EvalError>{
-    let mut eval_stack = Vec::new();
+    // Clauses are tried in order, only the first one that holds has its value evaluated.
+    fn eval_piecewise<N: Number>(&self, clauses: &[(Expr, Expr)], otherwise: Option<&Expr>, context: &Context<N>, frame: &Frame<N>) -> Result<Object<N>, EvalError> {
+        for (value, condition) in clauses {
+            if condition.eval_in(context, frame)?.is_true()? {
+                return value.eval_in(context, frame);
+            }
+        }
+        match otherwise {
+            Some(otherwise) => otherwise.eval_in(context, frame),
+            None => Err(EvalError::NoMatchingClause(self.to_string())),
+        }
+    }
 
-    for token in postfix_tokens {
-        match token {
-            Token::Val(value) => eval_stack.push(value),
-            Token::Func(function) => match function {
-                Function::Assign => {
-                    let n1 = eval_stack.pop().ok_or(EvalError::MissingArgument)?;
-                    let n2 = eval_stack.pop().ok_or(EvalError::MissingArgument)?;
-                    if let Value::Var(name) = n2 {
-                        let value = n1.collect(&context)?;
-                        context.set_var(&name, value);
-                        return Ok(EvalOutput::Assignment(name.clone(), value));
-                    }
-                }
-                Function::BinaryOp(op) => {
-                    let n1 = eval_stack.pop().ok_or(EvalError::MissingArgument)?;
-                    let n2 = eval_stack.pop().ok_or(EvalError::MissingArgument)?;
-                    match op {
-                        BinaryOp::Add => eval_stack.push(n2.add(n1, &context)?),
-                        BinaryOp::Sub => eval_stack.push(n2.sub(n1, &context)?),
-                        BinaryOp::Mul => eval_stack.push(n2.mul(n1, &context)?),
-                        BinaryOp::Div => eval_stack.push(n2.div(n1, &context)?),
-                        BinaryOp::Pow => eval_stack.push(n2.pow(n1, &context)?),
-                    }
+    fn eval_in<N: Number>(&self, context: &Context<N>, frame: &Frame<N>) -> Result<Object<N>, EvalError> {
+        match self {
+            Self::At(span, expr) => expr.eval_in(context, frame).map_err(|e| e.at(*span)),
+            Self::Literal(x) => Ok(Object::Scalar(N::from_f64(*x))),
+            Self::Integer(x) => Ok(Object::Scalar(N::from_integer(x))),
+            Self::Decimal(text) => Ok(Object::Scalar(N::from_literal(text))),
+            Self::Var(name) => frame.var(name)
+                .or_else(|| context.var(name))
+                .ok_or_else(|| EvalError::UndefinedVariable(name.clone())),
+            Self::Unary(op, operand) => {
+                let x = operand.eval_in(context, frame)?;
+                match op {
+                    UnaryOp::Neg => Ok(-x),
+                    UnaryOp::Not => Ok(!x),
                 }
-                Function::UnaryOp(op) => {
-                    let n = eval_stack.pop().ok_or(EvalError::MissingArgument)?;
-                    match op {
-                        UnaryOp::Neg => eval_stack.push(n.neg(&context)?),
+            }
+            Self::Postfix(op, operand) => operand.eval_in(context, frame)?.try_map(|x| Ok(op.apply(x))),
+            Self::Binary(..) if self.is_long_chain() => self.eval_chain(context, frame),
+            Self::Binary(op, lhs, rhs) => Self::eval_binary(op, lhs.eval_in(context, frame)?, rhs, context, frame),
+            Self::Call(name, args) if name == "if" && context.user_func(name).is_none() => Self::eval_if(args, context, frame),
+            // "diff" takes its arguments unevaluated, unless a user function shadows it
+            Self::Call(name, args) if name == "diff" && context.user_func(name).is_none() => {
+                let (expr, var, point) = diff_args(args)?;
+                let derivative = expr.diff(var, context)?;
+                match point {
+                    Some(point) => {
+                        let params = [var.to_string()];
+                        let args = [point.eval_in(context, frame)?];
+                        derivative.eval_in(context, &Frame{params: &params, args: &args, depth: frame.depth, parent: Some(frame)})
                     }
+                    None => derivative.eval_in(context, frame),
                 }
-                Function::NamedFunc(name) => {
-                    let n = eval_stack.pop().ok_or(EvalError::MissingArgument)?;
-                    match context.call_func(&name, n.collect(&context)?) {
-                        Some(n) => eval_stack.push(Value::Scalar(n)),
-                        None => return Err(EvalError::UndfinedFunction(name.clone())),
-                    }
+            }
+            Self::Call(name, args) => {
+                let args = args.iter()
+                    .map(|arg| arg.eval_in(context, frame))
+                    .collect::<Result<Vec<Object<N>>, EvalError>>()?;
+
+                // User functions shadow the built-in ones
+                match context.user_func(name) {
+                    Some(func) => func.call(name, &args, context, frame.depth),
+                    None => Self::call_or_product(name, args, context, frame),
                 }
             }
-            _ => return Err(EvalError::InvalidToken),
+            Self::List(items) => {
+                let items = items.iter()
+                    .map(|item| item.eval_in(context, frame))
+                    .collect::<Result<Vec<Object<N>>, EvalError>>()?;
+                Object::from_list(items)
+            }
+            Self::Index(target, indices) => {
+                let target = target.eval_in(context, frame)?;
+                let indices = indices.iter()
+                    .map(|index| index.eval_in(context, frame)?.scalar())
+                    .collect::<Result<Vec<N>, EvalError>>()?;
+                target.index(&indices)
+            }
+            Self::Piecewise(clauses, otherwise) => self.eval_piecewise(clauses, otherwise.as_deref(), context, frame),
+            Self::Assign(..) | Self::Define(..) => Err(EvalError::IncorrectAssignment(self.to_string())),
         }
     }
+}
+
+
+impl UserFunc {
+    fn call<N: Number>(&self, name: &str, args: &[Object<N>], context: &Context<N>, depth: usize) -> Result<Object<N>, EvalError> {
+        if self.params.len() != args.len() {
+            return Err(EvalError::ArgumentCount(name.to_string(), Arity::Exact(self.params.len()), args.len()));
+        }
+        if depth >= MAX_CALL_DEPTH {
+            return Err(EvalError::RecursionLimit(name.to_string()));
+        }
 
-    if let Some(val) = eval_stack.pop() {
-        Ok(EvalOutput::Value(val.collect(&context)?))
+        self.body.eval_in(context, &Frame{params: &self.params, args, depth: depth + 1, parent: None})
     }
-    else {
-        Err(EvalError::MissingResult)
+}
+
+
+pub fn evaluate<N: Number>(expr: &Expr, context: &mut Context<N>) -> Result<EvalOutput<N>, EvalError> {
+    let at = |e: EvalError| match expr.span() {
+        Some(span) => e.at(span),
+        None => e,
+    };
+
+    match expr.inner() {
+        Expr::Assign(name, value) => {
+            let value = value.eval(context)?;
+            context.set_var(name, value.clone());
+            Ok(EvalOutput::Assignment(name.clone(), value))
+        }
+        Expr::Define(name, params, body) => {
+            // The body outlives this line of input, so its spans would point at the wrong text later
+            context.set_func(name, UserFunc{params: params.clone(), body: body.without_spans()});
+            Ok(EvalOutput::Definition(name.clone(), params.clone()))
+        }
+        // Without a point to evaluate at, the derivative is returned as an expression
+        Expr::Call(name, args) if name == "diff" && args.len() == 2 && context.user_func(name).is_none() => {
+            let (expr, var, _) = diff_args(args).map_err(at)?;
+            Ok(EvalOutput::Expression(expr.diff(var, context).map_err(at)?.try_simplify().map_err(at)?))
+        }
+        Expr::Call(name, args) if name == "simplify" && args.len() == 1 && context.user_func(name).is_none() => {
+            Ok(EvalOutput::Expression(args[0].inline(context, 0).map_err(at)?.try_simplify().map_err(at)?))
+        }
+        _ => Ok(EvalOutput::Value(expr.eval(context)?)),
     }
-}
\ No newline at end of file
+}
+
+
+// Runs a line of input against the context and prints what it gives.
+#[cfg(test)]
+fn eval_line(input: &str, context: &mut Context) -> Result<String, EvalError> {
+    use crate::{parser::{parse, validate}, tokenizer::tokenize};
+
+    let tokens = tokenize(input).unwrap();
+    validate(&tokens).unwrap();
+    evaluate(&parse(tokens).unwrap(), context).map_err(EvalError::without_span).map(|output| match output {
+        EvalOutput::Value(value) | EvalOutput::Assignment(_, value) => value.to_string(),
+        EvalOutput::Definition(name, _) => name,
+        EvalOutput::Expression(expr) => expr.to_string(),
+    })
+}
+
+
+#[test]
+fn test_evaluate_0() {
+    use crate::{parser::{parse, validate}, tokenizer::tokenize};
+
+    let mut context: Context = Context::new();
+    let tokens = tokenize("x = 2*sin(0) + 3").unwrap();
+    validate(&tokens).unwrap();
+    let expr = parse(tokens).unwrap();
+    assert!(matches!(evaluate(&expr, &mut context), Ok(EvalOutput::Assignment(_, x)) if x == Object::Scalar(3.0)));
+
+    let tokens = tokenize("-x^2").unwrap();
+    let expr = parse(tokens).unwrap();
+    assert!(matches!(evaluate(&expr, &mut context), Ok(EvalOutput::Value(x)) if x == Object::Scalar(-9.0)));
+}
+
+#[test]
+fn test_evaluate_1() {
+    use core::f64::consts;
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    let mut context: Context = Context::new();
+    let eval = |input: &str, context: &mut Context| {
+        let expr = parse(tokenize(input).unwrap()).unwrap();
+        evaluate(&expr, context).map_err(EvalError::without_span)
+    };
+
+    assert!(matches!(eval("max(1, 7, 3) + min(4, 2)", &mut context), Ok(EvalOutput::Value(x)) if x == Object::Scalar(9.0)));
+    assert!(matches!(eval("log(2, 8)", &mut context), Ok(EvalOutput::Value(Object::Scalar(x))) if (x - 3.0).abs() < 1e-6));
+    assert!(matches!(eval("clamp(5, 0, 1)", &mut context), Ok(EvalOutput::Value(x)) if x == Object::Scalar(1.0)));
+    assert!(matches!(eval("atan2(1, 1)*4", &mut context), Ok(EvalOutput::Value(Object::Scalar(x))) if (x - consts::PI).abs() < 1e-6));
+    assert!(matches!(eval("atan2(1)", &mut context), Err(EvalError::ArgumentCount(_, Arity::Exact(2), 1))));
+    assert!(matches!(eval("sin(1, 2)", &mut context), Err(EvalError::ArgumentCount(_, Arity::Exact(1), 2))));
+}
+
+#[test]
+fn test_evaluate_2() {
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    let mut context: Context = Context::new();
+    let mut eval = |input: &str| {
+        let expr = parse(tokenize(input).unwrap()).unwrap();
+        evaluate(&expr, &mut context).map_err(EvalError::without_span)
+    };
+
+    assert!(matches!(eval("f(x, y) = x^2 + y"), Ok(EvalOutput::Definition(..))));
+    assert!(matches!(eval("g(x) = f(x, 1) * 2"), Ok(EvalOutput::Definition(..))));
+    assert!(matches!(eval("x = 10"), Ok(EvalOutput::Assignment(..))));
+    assert!(matches!(eval("g(3) + x"), Ok(EvalOutput::Value(x)) if x == Object::Scalar(30.0)));
+    assert!(matches!(eval("sin(x) = 2*x"), Ok(EvalOutput::Definition(..))));
+    assert!(matches!(eval("sin(4)"), Ok(EvalOutput::Value(x)) if x == Object::Scalar(8.0)));
+    assert!(matches!(eval("g(1, 2)"), Err(EvalError::ArgumentCount(_, Arity::Exact(1), 2))));
+    assert!(matches!(eval("h(n) = h(n - 1)"), Ok(EvalOutput::Definition(..))));
+    assert!(matches!(eval("h(1)"), Err(EvalError::RecursionLimit(_))));
+}
+
+#[test]
+fn test_evaluate_3() {
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    let expr = parse(tokenize("0.1 + 0.2 - 0.3").unwrap()).unwrap();
+
+    let context: Context<f64> = Context::new();
+    let x = expr.eval(&context).unwrap().scalar().unwrap();
+    assert!(x.abs() < 1e-16 && x != 0.0, "Expected an f64 rounding error, got {x}");
+
+    let context: Context<f32> = Context::new();
+    let x = expr.eval(&context).unwrap().scalar().unwrap();
+    assert!(x.abs() < 1e-7, "Expected an f32 result close to zero, got {x}");
+}
+
+#[test]
+fn test_evaluate_4() {
+    let mut context: Context = Context::new();
+    let mut eval = |input: &str| eval_line(input, &mut context);
+
+    assert!(eval("m = [[1, 2], [3, 4]]").unwrap() == "[[1, 2], [3, 4]]");
+    assert!(eval("m*[1, -1]").unwrap() == "[-1, -1]");
+    assert!(eval("inv([[2, 1], [1, 1]])*[[2, 1], [1, 1]]").unwrap() == "[[1, 0], [0, 1]]");
+    assert!(eval("det(m) + m[2, 1] + m[1][2]").unwrap() == "3");
+    assert!(eval("transpose(m)*2").unwrap() == "[[2, 6], [4, 8]]");
+    assert!(eval("dot([1, 2, 3], [4, 5, 6])").unwrap() == "32");
+    assert!(eval("cross([1, 0, 0], [0, 1, 0])").unwrap() == "[0, 0, 1]");
+    assert!(eval("sqrt([4, 9]) + max([1, 5, 2])").unwrap() == "[7, 8]");
+    assert!(eval("f(v) = v[1]^2").is_ok() && eval("f([3, 4])").unwrap() == "9");
+    assert!(matches!(eval("[1, 2] + [1, 2, 3]"), Err(EvalError::ShapeMismatch(..))));
+    assert!(matches!(eval("inv([[1, 2], [2, 4]])"), Err(EvalError::SingularMatrix)));
+    assert!(matches!(eval("sin(1, [1])"), Err(EvalError::ArgumentCount(..))));
+}
+
+#[test]
+fn test_evaluate_5() {
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    let mut context: Context = Context::new();
+    let define = parse(tokenize("f(x) = x + y").unwrap()).unwrap();
+    evaluate(&define, &mut context).unwrap();
+
+    let mut span = |input: &str| {
+        let expr = parse(tokenize(input).unwrap()).unwrap();
+        evaluate(&expr, &mut context).unwrap_err().span()
+    };
+
+    assert!(span("2*foo + 1") == Some(Span::new(2, 5)));
+    assert!(span("1 + sin(1, 2)") == Some(Span::new(4, 13)));
+    assert!(span("[1, 2] + [1, 2, 3]*2") == Some(Span::new(0, 20)));
+    // Errors inside a function body point at the call
+    assert!(span("3*f(1)") == Some(Span::new(2, 6)));
+}
+
+#[test]
+fn test_evaluate_6() {
+    let mut context: Context = Context::new();
+    let mut eval = |input: &str| eval_line(input, &mut context);
+
+    assert!(eval("1 + 1 == 2").unwrap() == "1");
+    assert!(eval("3 < 2 || 2 <= 2 && !(1 != 1)").unwrap() == "1");
+    assert!(eval("[1, 5, 3] > 2").unwrap() == "[0, 1, 1]");
+    // The branch not taken and the right side of a decided "&&" or "||" are never evaluated
+    assert!(eval("if(2 > 1, 10, undefined)").unwrap() == "10");
+    assert!(eval("0 && undefined").unwrap() == "0" && eval("1 || undefined").unwrap() == "1");
+    assert!(eval("price(q) = if(q < 10, 5*q, if(q < 100, 4.5*q, 4*q))").is_ok());
+    assert!(eval("price(5) + price(20) + price(100)").unwrap() == "515");
+    assert!(matches!(eval("if(1, 2)"), Err(EvalError::ArgumentCount(_, Arity::Exact(3), 2))));
+    assert!(matches!(eval("if([1, 0], 1, 2)"), Err(EvalError::ExpectedScalar(..))));
+}
+
+#[test]
+fn test_evaluate_7() {
+    let mut context: Context = Context::new();
+    let mut eval = |input: &str| eval_line(input, &mut context);
+
+    assert!(eval("f(x) = { x^2 if x < 0; sqrt(x) otherwise }").unwrap() == "f");
+    assert!(eval("f(-3) + f(16)").unwrap() == "13");
+    // Brackets of 10% up to 10000, 20% up to 50000 and 40% above
+    assert!(eval("tax(i) = { 0.1*i if i <= 10000; 1000 + 0.2*(i - 10000) if i <= 50000; 9000 + 0.4*(i - 50000) otherwise }").is_ok());
+    assert!(eval("[tax(5000), tax(10000), tax(30000), tax(60000)]").unwrap() == "[500, 1000, 5000, 13000]");
+    // Clauses are tried in order and only the value of the first that holds is evaluated
+    assert!(eval("{ 1 if 1; 2 if 1; undefined otherwise }").unwrap() == "1");
+    assert!(eval("sign(x) = { 1 if x > 0; -1 if x < 0 }").is_ok());
+    assert!(matches!(eval("sign(0)"), Err(EvalError::NoMatchingClause(expr)) if expr == "{ 1 if x > 0; -1 if x < 0 }"));
+    assert!(matches!(eval("{ 1 if [1, 1] }"), Err(EvalError::ExpectedScalar(..))));
+}
+
+#[test]
+fn test_evaluate_8() {
+    let mut context: Context = Context::new();
+    let mut eval = |input: &str| eval_line(input, &mut context);
+
+    assert!(eval("[0!, 1!, 5!, 3!!, -3!, 2^3!]").unwrap() == "[1, 1, 120, 720, -6, 64]");
+    assert!(eval("20%*150").unwrap() == "30" && eval("200 + 5%").unwrap() == "200.05");
+    assert!(eval("[1, 2, 3]! + [50, 100, 150]%").unwrap() == "[1.5, 3, 7.5]");
+    assert!(eval("choose(n, k) = n! / (k!*(n - k)!)").is_ok());
+    assert!(eval("choose(10, 3)").unwrap() == "120");
+    // Non-integers go through the gamma function, 0.5! is sqrt(pi)/2
+    assert!(eval("abs(0.5! - sqrt(pi)/2) < 10^-14 && abs((-0.5)! - sqrt(pi)) < 10^-14 && abs(4.5! - 52.34277778455352) < 10^-12").unwrap() == "1");
+    assert!(eval("(-1)!").unwrap() == "NaN" && eval("171!").unwrap() == "inf");
+    assert!(eval("170! > 7.25*10^306").unwrap() == "1");
+
+    // Between two operands "%" is still a percentage, not the remainder it used to be
+    let engine: crate::engine::Engine = crate::engine::Engine::new();
+    assert!(matches!(engine.value("200 % 3").map_err(|e| e.code()), Err("E0105")));
+    assert!(matches!(engine.value("200 % (3)"), Ok(Object::Scalar(6.0))));
+    assert!(matches!(engine.value("200 mod 3"), Ok(Object::Scalar(2.0))));
+}
+
+#[test]
+fn test_evaluate_9() {
+    let mut context: Context = Context::new();
+    let mut eval = |input: &str| eval_line(input, &mut context);
+
+    // A variable before brackets is a product when no function has its name
+    assert!(eval("x = 2").is_ok());
+    assert!(eval("x(x + 1)").unwrap() == "6" && eval("pi(0)").unwrap() == "0");
+    assert!(eval("f(t) = t(t - 1)").is_ok() && eval("f(4)").unwrap() == "12");
+    assert!(eval("simplify(x(y + 1))").unwrap() == "x*y + x");
+    // Functions still win over variables, and a name that is neither stays undefined
+    assert!(eval("sin = 3").is_ok() && eval("sin(0)").unwrap() == "0");
+    assert!(matches!(eval("y(2)"), Err(EvalError::UndfinedFunction(name)) if name == "y"));
+    assert!(matches!(eval("x(1, 2)"), Err(EvalError::UndfinedFunction(_))));
+}
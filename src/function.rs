@@ -0,0 +1,294 @@
+use std::{collections::HashMap, sync::Arc};
+
+use num_bigint::BigInt;
+use num_integer::Integer as _;
+
+use crate::{app_context::Arity, evaluator::EvalError, integer, number::Number, object::Object, tokens::BinaryOp};
+
+
+// Anything callable by name from an expression, the argument count is checked against the arity before calling.
+pub trait Function<N: Number>: Send + Sync {
+    fn arity(&self) -> Arity;
+
+    // Pure functions give the same result for the same arguments, so their calls can be folded ahead of time.
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn call(&self, args: Vec<Object<N>>) -> Result<Object<N>, EvalError>;
+
+    // Same as "call" for scalar arguments, overridden where it can be done without building objects.
+    fn call_scalar(&self, args: &[N]) -> Result<N, EvalError> {
+        self.call(args.iter().cloned().map(Object::Scalar).collect())?.scalar()
+    }
+}
+
+
+pub type ScalarFn<N> = Box<dyn Fn(&[N]) -> Result<N, EvalError> + Send + Sync>;
+
+// Function of scalar arguments, with a single argument it applies to every element of an array.
+pub struct ScalarFunc<N: Number> {
+    arity: Arity,
+    pure: bool,
+    func: ScalarFn<N>,
+}
+
+impl<N: Number> ScalarFunc<N> {
+    pub fn new(arity: Arity, func: impl Fn(&[N]) -> N + Send + Sync + 'static) -> Self {
+        Self::fallible(arity, move |args| Ok(func(args)))
+    }
+
+    pub fn fallible(arity: Arity, func: impl Fn(&[N]) -> Result<N, EvalError> + Send + Sync + 'static) -> Self {
+        ScalarFunc{arity, pure: true, func: Box::new(func)}
+    }
+
+    // Marks the function as depending on something besides its arguments, e.g. host state.
+    pub fn impure(mut self) -> Self {
+        self.pure = false;
+        self
+    }
+}
+
+impl<N: Number> Function<N> for ScalarFunc<N> {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn is_pure(&self) -> bool {
+        self.pure
+    }
+
+    fn call(&self, args: Vec<Object<N>>) -> Result<Object<N>, EvalError> {
+        let mut args = args.into_iter();
+        if args.len() == 1 {
+            return args.next().unwrap().try_map(|x| (self.func)(&[x]));
+        }
+        let args = args.map(Object::scalar).collect::<Result<Vec<N>, EvalError>>()?;
+        (self.func)(&args).map(Object::Scalar)
+    }
+
+    fn call_scalar(&self, args: &[N]) -> Result<N, EvalError> {
+        (self.func)(args)
+    }
+}
+
+
+// Variadic function combining its arguments pairwise, a single array is folded over its elements.
+pub struct FoldFunc<N: Number> {
+    name: &'static str,
+    func: fn(N, N) -> Result<N, EvalError>,
+}
+
+impl<N: Number> Function<N> for FoldFunc<N> {
+    fn arity(&self) -> Arity {
+        Arity::AtLeast(1)
+    }
+
+    fn call(&self, args: Vec<Object<N>>) -> Result<Object<N>, EvalError> {
+        let values = match args.as_slice() {
+            [array] if array.elements().is_empty() => return Err(EvalError::InvalidList(array.shape())),
+            [array] => array.elements().to_vec(),
+            _ => args.into_iter().map(Object::scalar).collect::<Result<Vec<N>, EvalError>>()?,
+        };
+        let mut values = values.into_iter();
+        let first = values.next().unwrap();
+        values.try_fold(first, self.func).map(Object::Scalar)
+    }
+
+    fn call_scalar(&self, args: &[N]) -> Result<N, EvalError> {
+        let (first, rest) = args.split_first().ok_or(EvalError::ArgumentCount(self.name.to_string(), self.arity(), 0))?;
+        rest.iter().cloned().try_fold(first.clone(), self.func)
+    }
+}
+
+
+pub type ObjectFn<N> = fn(Vec<Object<N>>) -> Result<Object<N>, EvalError>;
+
+// Function working on whole arrays, e.g. the matrix operations.
+pub struct ObjectFunc<N: Number> {
+    arity: Arity,
+    func: ObjectFn<N>,
+}
+
+impl<N: Number> Function<N> for ObjectFunc<N> {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, args: Vec<Object<N>>) -> Result<Object<N>, EvalError> {
+        (self.func)(args)
+    }
+}
+
+
+type UnaryFn<N> = fn(N) -> N;
+
+// Only real numbers are ordered, anything else would make the result depend on the order of the arguments.
+fn real<N: Number>(name: &str, x: &N) -> Result<(), EvalError> {
+    match x.is_real() {
+        true => Ok(()),
+        false => Err(EvalError::InvalidArgument(name.to_string(), format!("{x} is not real"))),
+    }
+}
+
+fn max_of<N: Number>(a: N, b: N) -> Result<N, EvalError> {
+    real("max", &a).and(real("max", &b))?;
+    Ok(if b > a { b } else { a })
+}
+
+fn min_of<N: Number>(a: N, b: N) -> Result<N, EvalError> {
+    real("min", &a).and(real("min", &b))?;
+    Ok(if b < a { b } else { a })
+}
+
+fn integer_arg<N: Number>(x: &N) -> Result<BigInt, EvalError> {
+    x.to_integer().ok_or_else(|| EvalError::ExpectedInteger(x.to_string()))
+}
+
+// Folds whole number arguments, anything else is an error.
+fn fold_integers<N: Number>(args: &[N], f: fn(&BigInt, &BigInt) -> BigInt) -> Result<N, EvalError> {
+    let values = args.iter().map(integer_arg).collect::<Result<Vec<BigInt>, EvalError>>()?;
+    let result = values.into_iter().reduce(|a, b| f(&a, &b)).unwrap_or_default();
+    Ok(N::from_integer(&result))
+}
+
+
+pub struct Registry<N: Number> {
+    funcs: HashMap<String, Arc<dyn Function<N>>>,
+}
+
+impl<N: Number> Default for Registry<N> {
+    fn default() -> Self {
+        Self::builtins()
+    }
+}
+
+impl<N: Number> Registry<N> {
+    pub fn new() -> Self {
+        Registry{funcs: HashMap::new()}
+    }
+
+    pub fn builtins() -> Self {
+        let mut registry = Self::new();
+
+        let unary: [(&str, UnaryFn<N>); 11] = [
+            ("sqrt", N::sqrt), ("sin", N::sin), ("cos", N::cos), ("tan", N::tan), ("exp", N::exp), ("ln", N::ln),
+            ("abs", N::abs), ("arg", N::arg), ("re", N::re), ("im", N::im), ("conj", N::conj),
+        ];
+        for (name, func) in unary {
+            registry.register(name, ScalarFunc::new(Arity::Exact(1), move |args: &[N]| func(args[0].clone())));
+        }
+
+        registry.register("log", ScalarFunc::new(Arity::Range(1, 2), |args: &[N]| match args {
+            [base, x] => x.clone().log(base.clone()),
+            _ => args[0].clone().log(N::from_f64(10.0)),
+        }));
+        registry.register("atan2", ScalarFunc::new(Arity::Exact(2), |args: &[N]| args[0].clone().atan2(args[1].clone())));
+        registry.register("hypot", ScalarFunc::new(Arity::Exact(2), |args: &[N]| args[0].clone().hypot(args[1].clone())));
+        registry.register("clamp", ScalarFunc::fallible(Arity::Exact(3), |args: &[N]| {
+            args.iter().try_for_each(|x| real("clamp", x))?;
+            max_of(args[1].clone(), min_of(args[0].clone(), args[2].clone())?)
+        }));
+        registry.register("max", FoldFunc{name: "max", func: max_of});
+        registry.register("min", FoldFunc{name: "min", func: min_of});
+
+        registry.register("mod", ScalarFunc::fallible(Arity::Exact(2), |args: &[N]| BinaryOp::Mod.try_apply(args[0].clone(), args[1].clone())));
+        registry.register("div", ScalarFunc::fallible(Arity::Exact(2), |args: &[N]| BinaryOp::FloorDiv.try_apply(args[0].clone(), args[1].clone())));
+        registry.register("gcd", ScalarFunc::fallible(Arity::AtLeast(2), |args: &[N]| fold_integers(args, BigInt::gcd)));
+        registry.register("lcm", ScalarFunc::fallible(Arity::AtLeast(2), |args: &[N]| fold_integers(args, BigInt::lcm)));
+        registry.register("isprime", ScalarFunc::new(Arity::Exact(1), |args: &[N]| {
+            N::from_bool(args[0].to_integer().is_some_and(|n| integer::is_prime(&n)))
+        }));
+        registry.register("modpow", ScalarFunc::fallible(Arity::Exact(3), |args: &[N]| {
+            let (base, exponent, m) = (integer_arg(&args[0])?, integer_arg(&args[1])?, integer_arg(&args[2])?);
+            let invalid = |reason: String| EvalError::InvalidArgument("modpow".to_string(), reason);
+            match integer::modpow(&base, &exponent, &m) {
+                Some(x) => Ok(N::from_integer(&x)),
+                None if m == BigInt::ZERO => Err(invalid("the modulus is 0".to_string())),
+                None => Err(invalid(format!("{base} has no inverse modulo {m}"))),
+            }
+        }));
+        registry.register("factor", ObjectFunc{arity: Arity::Exact(1), func: |mut args| {
+            let n = integer_arg(&args.remove(0).scalar()?)?;
+            Ok(Object::Vector(integer::factor(&n).iter().map(N::from_integer).collect()))
+        }});
+
+        let matrix: [(&str, Arity, ObjectFn<N>); 6] = [
+            // Only changes how an expression prints, so the value passes through
+            ("simplify", Arity::Exact(1), |mut args| Ok(args.remove(0))),
+            ("transpose", Arity::Exact(1), |mut args| Ok(args.remove(0).transpose())),
+            ("det", Arity::Exact(1), |mut args| args.remove(0).det()),
+            ("inv", Arity::Exact(1), |mut args| args.remove(0).inv()),
+            ("dot", Arity::Exact(2), |mut args| args.remove(0).dot(args.remove(0))),
+            ("cross", Arity::Exact(2), |mut args| args.remove(0).cross(args.remove(0))),
+        ];
+        for (name, arity, func) in matrix {
+            registry.register(name, ObjectFunc{arity, func});
+        }
+
+        registry
+    }
+
+    // Replaces any function already registered under the name.
+    pub fn register(&mut self, name: &str, func: impl Function<N> + 'static) {
+        self.funcs.insert(name.to_string(), Arc::new(func));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Function<N>> {
+        self.funcs.get(name).map(|func| func.as_ref())
+    }
+
+    // Shared handle that stays valid if the name is registered again later.
+    pub fn handle(&self, name: &str) -> Option<Arc<dyn Function<N>>> {
+        self.funcs.get(name).cloned()
+    }
+
+    pub fn arity(&self, name: &str) -> Option<Arity> {
+        self.get(name).map(|func| func.arity())
+    }
+
+    pub fn is_pure(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|func| func.is_pure())
+    }
+
+    pub fn call(&self, name: &str, args: Vec<Object<N>>) -> Result<Object<N>, EvalError> {
+        let func = self.get(name).ok_or_else(|| EvalError::UndfinedFunction(name.to_string()))?;
+        if !func.arity().accepts(args.len()) {
+            return Err(EvalError::ArgumentCount(name.to_string(), func.arity(), args.len()));
+        }
+        func.call(args)
+    }
+}
+
+
+#[test]
+fn test_function_0() {
+    struct Lookup;
+
+    impl Function<f64> for Lookup {
+        fn arity(&self) -> Arity {
+            Arity::Exact(1)
+        }
+
+        fn is_pure(&self) -> bool {
+            false
+        }
+
+        fn call(&self, args: Vec<Object<f64>>) -> Result<Object<f64>, EvalError> {
+            args[0].clone().try_map(|code| Ok(if code == 1.0 { 0.25 } else { 0.5 }))
+        }
+    }
+
+    let mut registry = Registry::<f64>::builtins();
+    registry.register("lookup_rate", Lookup);
+    assert!(registry.arity("max") == Some(Arity::AtLeast(1)) && registry.is_pure("max"));
+    assert!(!registry.is_pure("lookup_rate") && !registry.is_pure("nothing"));
+
+    let list = Object::from_list(vec![Object::Scalar(1.0), Object::Scalar(2.0)]).unwrap();
+    assert!(matches!(registry.call("lookup_rate", vec![Object::Scalar(1.0)]), Ok(Object::Scalar(0.25))));
+    assert!(matches!(registry.call("max", vec![list.clone()]), Ok(Object::Scalar(2.0))));
+    assert!(matches!(registry.call("clamp", vec![Object::Scalar(5.0), Object::Scalar(0.0), Object::Scalar(3.0)]), Ok(Object::Scalar(3.0))));
+    assert!(registry.call("sqrt", vec![list]).is_ok_and(|result| result.elements() == [1.0, 2f64.sqrt()]));
+    assert!(matches!(registry.call("sqrt", vec![]), Err(EvalError::ArgumentCount(_, Arity::Exact(1), 0))));
+    assert!(matches!(registry.call("lookup", vec![]), Err(EvalError::UndfinedFunction(_))));
+}
@@ -0,0 +1,461 @@
+use std::{cmp::Ordering, fmt::Display, ops::{Add, Div, Mul, Neg, Sub}};
+
+use num_bigint::BigInt;
+use num_integer::Integer as _;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+
+use crate::number::{literal_parts, Number};
+
+
+// Largest power computed exactly, in bits of the result, anything bigger is left to floats.
+const MAX_POWER_BITS: u64 = 1 << 20;
+// Largest factorial computed exactly, 20000! has about 77000 digits.
+const MAX_FACTORIAL: u64 = 20_000;
+
+
+// Exact integer of any size, or a float once an operation had a fractional or non-finite result.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Integer {
+    Exact(BigInt),
+    Approx(f64),
+}
+
+impl Integer {
+    pub fn is_exact(&self) -> bool {
+        matches!(self, Self::Exact(_))
+    }
+
+    pub fn to_approx(&self) -> f64 {
+        match self {
+            Self::Exact(x) => x.to_f64().unwrap_or(f64::NAN),
+            Self::Approx(x) => *x,
+        }
+    }
+
+    fn exact_or(self, exact: impl FnOnce(&BigInt) -> Option<BigInt>, approx: impl FnOnce(f64) -> f64) -> Self {
+        if let Self::Exact(x) = &self {
+            if let Some(result) = exact(x) {
+                return Self::Exact(result);
+            }
+        }
+        Self::Approx(approx(self.to_approx()))
+    }
+
+    fn combine(
+        self,
+        other: Self,
+        exact: impl FnOnce(&BigInt, &BigInt) -> Option<BigInt>,
+        approx: impl FnOnce(f64, f64) -> f64,
+    ) -> Self {
+        if let (Self::Exact(a), Self::Exact(b)) = (&self, &other) {
+            if let Some(result) = exact(a, b) {
+                return Self::Exact(result);
+            }
+        }
+        Self::Approx(approx(self.to_approx(), other.to_approx()))
+    }
+}
+
+
+fn exact_pow(base: &BigInt, exponent: &BigInt) -> Option<BigInt> {
+    if base.is_zero() || base.abs().is_one() {
+        let odd = exponent.is_odd();
+        return match base.to_i32()? {
+            0 if exponent.is_positive() => Some(BigInt::zero()),
+            0 => None,
+            1 => Some(BigInt::one()),
+            _ => Some(if odd { -BigInt::one() } else { BigInt::one() }),
+        };
+    }
+    let exponent = exponent.to_u32()?;
+    (base.bits() * u64::from(exponent) <= MAX_POWER_BITS).then(|| base.pow(exponent))
+}
+
+fn exact_root(x: &BigInt) -> Option<BigInt> {
+    if x.is_negative() {
+        return None;
+    }
+    let root = x.sqrt();
+    (&root * &root == *x).then_some(root)
+}
+
+
+// Deterministic for every n below 3.3*10^24, above that a composite passing all bases is vanishingly unlikely.
+pub fn is_prime(n: &BigInt) -> bool {
+    const BASES: [u32; 13] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41];
+
+    if *n < BigInt::from(2) {
+        return false;
+    }
+    for p in BASES {
+        if (n % p).is_zero() {
+            return *n == BigInt::from(p);
+        }
+    }
+
+    // Miller-Rabin, n - 1 = d*2^s with d odd
+    let n_minus_one = n - 1u32;
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+    BASES.iter().all(|&base| {
+        let mut x = BigInt::from(base).modpow(&d, n);
+        if x.is_one() || x == n_minus_one {
+            return true;
+        }
+        for _ in 1..s {
+            x = &x * &x % n;
+            if x == n_minus_one {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+// Some proper divisor of an odd composite n, with Pollard's rho.
+fn find_divisor(n: &BigInt) -> BigInt {
+    let mut c = BigInt::one();
+    loop {
+        let step = |x: &BigInt| (x * x + &c) % n;
+        let (mut x, mut y) = (BigInt::from(2), BigInt::from(2));
+        let mut divisor = BigInt::one();
+        while divisor.is_one() {
+            x = step(&x);
+            y = step(&step(&y));
+            divisor = (&x - &y).abs().gcd(n);
+        }
+        if divisor != *n {
+            return divisor;
+        }
+        c += 1u32;
+    }
+}
+
+fn factor_into(n: BigInt, factors: &mut Vec<BigInt>) {
+    if n.is_one() {
+        return;
+    }
+    if is_prime(&n) {
+        factors.push(n);
+        return;
+    }
+    let divisor = find_divisor(&n);
+    factor_into(&n / &divisor, factors);
+    factor_into(divisor, factors);
+}
+
+// Prime factors in ascending order, repeated by multiplicity, with -1 first for negative n.
+// Small factors are divided out directly, the rest is split with Pollard's rho, which is slow for
+// products of two primes of more than about 20 digits each.
+pub fn factor(n: &BigInt) -> Vec<BigInt> {
+    if n.is_zero() {
+        return vec![BigInt::zero()];
+    }
+
+    let mut factors = Vec::new();
+    if n.is_negative() {
+        factors.push(-BigInt::one());
+    }
+    let mut n = n.abs();
+    let mut p = 2u32;
+    while p < 1000 && BigInt::from(p * p) <= n {
+        while (&n % p).is_zero() {
+            factors.push(BigInt::from(p));
+            n /= p;
+        }
+        p += if p == 2 { 1 } else { 2 };
+    }
+    factor_into(n, &mut factors);
+    factors.sort();
+    factors
+}
+
+// n! for whole n from 0 up to MAX_FACTORIAL.
+pub fn factorial(n: &BigInt) -> Option<BigInt> {
+    let n = n.to_u64().filter(|&n| n <= MAX_FACTORIAL)?;
+    Some((2..=n).fold(BigInt::one(), |product, k| product * k))
+}
+
+// base^exponent modulo m, with the sign of m like "mod". A negative exponent takes the inverse of the
+// base, so there is no result when m is 0 or the base has no inverse modulo m.
+pub fn modpow(base: &BigInt, exponent: &BigInt, m: &BigInt) -> Option<BigInt> {
+    if m.is_zero() {
+        return None;
+    }
+    let modulus = m.abs();
+    let mut base = base.mod_floor(&modulus);
+    if exponent.is_negative() {
+        let gcd = base.extended_gcd(&modulus);
+        if !gcd.gcd.is_one() {
+            return None;
+        }
+        base = gcd.x.mod_floor(&modulus);
+    }
+
+    let result = base.modpow(&exponent.abs(), &modulus);
+    match m.is_negative() && !result.is_zero() {
+        true => Some(result + m),
+        false => Some(result),
+    }
+}
+
+
+impl Display for Integer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact(x) => write!(f, "{x}"),
+            Self::Approx(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Exact(a), Self::Exact(b)) => a.partial_cmp(b),
+            (a, b) => a.to_approx().partial_cmp(&b.to_approx()),
+        }
+    }
+}
+
+impl Add for Integer {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.combine(other, |a, b| Some(a + b), |a, b| a + b)
+    }
+}
+
+impl Sub for Integer {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.combine(other, |a, b| Some(a - b), |a, b| a - b)
+    }
+}
+
+impl Mul for Integer {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        self.combine(other, |a, b| Some(a * b), |a, b| a * b)
+    }
+}
+
+// Exact only when the division leaves no remainder, "7/2" is 3.5
+impl Div for Integer {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        self.combine(other, |a, b| (!b.is_zero() && a.is_multiple_of(b)).then(|| a / b), |a, b| a / b)
+    }
+}
+
+impl Neg for Integer {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self {
+            Self::Exact(x) => Self::Exact(-x),
+            Self::Approx(x) => Self::Approx(-x),
+        }
+    }
+}
+
+
+impl Number for Integer {
+    // Whole numbers are exact, anything else stays a float
+    fn from_f64(x: f64) -> Self {
+        match x.fract() == 0.0 {
+            true => BigInt::from_f64(x).map_or(Self::Approx(x), Self::Exact),
+            false => Self::Approx(x),
+        }
+    }
+
+    // Whole literals like "1e30" or "2.5e3" are read digit for digit, fractional ones stay floats
+    fn from_literal(text: &str) -> Self {
+        match literal_parts(text) {
+            Some((mantissa, exponent)) if exponent.unsigned_abs() * 4 <= MAX_POWER_BITS => {
+                let power = num_traits::pow(BigInt::from(10), exponent.unsigned_abs() as usize);
+                match exponent < 0 {
+                    false => Self::Exact(mantissa * power),
+                    true if mantissa.is_multiple_of(&power) => Self::Exact(mantissa / power),
+                    true => Self::Approx(text.parse().unwrap_or(f64::NAN)),
+                }
+            }
+            _ => Self::from_f64(text.parse().unwrap_or(f64::NAN)),
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.to_approx()
+    }
+
+    fn pi() -> Self {
+        Self::Approx(std::f64::consts::PI)
+    }
+
+    fn e() -> Self {
+        Self::Approx(std::f64::consts::E)
+    }
+
+    fn from_integer(x: &BigInt) -> Self {
+        Self::Exact(x.clone())
+    }
+
+    fn to_integer(&self) -> Option<BigInt> {
+        match self {
+            Self::Exact(x) => Some(x.clone()),
+            Self::Approx(x) if x.fract() == 0.0 => BigInt::from_f64(*x),
+            Self::Approx(_) => None,
+        }
+    }
+
+    fn pow(self, exponent: Self) -> Self {
+        self.combine(exponent, exact_pow, f64::powf)
+    }
+
+    fn sqrt(self) -> Self {
+        self.exact_or(exact_root, f64::sqrt)
+    }
+
+    fn exp(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigInt::one), f64::exp)
+    }
+
+    fn ln(self) -> Self {
+        self.exact_or(|x| x.is_one().then(BigInt::zero), f64::ln)
+    }
+
+    fn sin(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigInt::zero), f64::sin)
+    }
+
+    fn cos(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigInt::one), f64::cos)
+    }
+
+    fn tan(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigInt::zero), f64::tan)
+    }
+
+    fn log(self, base: Self) -> Self {
+        // Exact when x is a whole power of the base, e.g. log(2, 1024)
+        if let (Self::Exact(x), Self::Exact(b)) = (&self, &base) {
+            let approx = (self.to_approx().ln() / base.to_approx().ln()).round();
+            if let Some(power) = approx.to_u32() {
+                if b.abs() > BigInt::one() && exact_pow(b, &BigInt::from(power)).is_some_and(|p| p == *x) {
+                    return Self::Exact(BigInt::from(power));
+                }
+            }
+        }
+        Self::Approx(self.to_approx().log(base.to_approx()))
+    }
+
+    fn abs(self) -> Self {
+        match self {
+            Self::Exact(x) => Self::Exact(x.abs()),
+            Self::Approx(x) => Self::Approx(x.abs()),
+        }
+    }
+
+    // The one float operation whose result is whole, so it is exact again where the float allows
+    fn floor(self) -> Self {
+        match self {
+            Self::Exact(x) => Self::Exact(x),
+            Self::Approx(x) => Self::from_f64(x.floor()),
+        }
+    }
+
+    fn div_floor(self, other: Self) -> Self {
+        self.combine(other, |a, b| (!b.is_zero()).then(|| a.div_floor(b)), |a, b| (a / b).floor())
+    }
+
+    fn modulo(self, other: Self) -> Self {
+        self.combine(other, |a, b| (!b.is_zero()).then(|| a.mod_floor(b)), |a, b| a - b * (a / b).floor())
+    }
+}
+
+
+#[test]
+fn test_integer_0() {
+    let int = |x: f64| Integer::from_f64(x);
+
+    assert!(int(2.0).pow(int(100.0)).to_string() == "1267650600228229401496703205376");
+    assert!((int(2.0).pow(int(64.0)) - int(1.0)).to_string() == "18446744073709551615");
+    assert!(int(-3.0).pow(int(3.0)) == int(-27.0) && int(-1.0).pow(int(1e12 + 1.0)) == int(-1.0));
+    assert!((int(12.0) / int(4.0)).is_exact() && int(7.0) / int(2.0) == Integer::Approx(3.5));
+    assert!(int(1.0) / int(0.0) == Integer::Approx(f64::INFINITY));
+    assert!(int(144.0).sqrt() == int(12.0) && !int(2.0).sqrt().is_exact());
+    assert!(int(2.0).pow(int(-1.0)) == Integer::Approx(0.5));
+    assert!(int(1024.0).log(int(2.0)) == int(10.0));
+
+    assert!(int(-7.0).div_floor(int(2.0)) == int(-4.0) && int(-7.0).modulo(int(3.0)) == int(2.0));
+    assert!(int(7.0).modulo(int(-3.0)) == int(-2.0) && int(7.5).modulo(int(2.0)) == Integer::Approx(1.5));
+    assert!(!int(7.0).modulo(int(0.0)).is_exact());
+    // A float that comes back to a whole number is exact again
+    assert!((int(7.0) / int(2.0)).floor() == int(3.0));
+}
+
+#[test]
+fn test_integer_1() {
+    let big = |s: &str| s.parse::<BigInt>().unwrap();
+
+    let primes: Vec<u32> = (0..60).filter(|&n| is_prime(&BigInt::from(n))).collect();
+    assert!(primes == [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59]);
+    assert!(is_prime(&big("170141183460469231731687303715884105727")));
+    // Carmichael numbers and a strong pseudoprime to small bases
+    assert!(!is_prime(&big("561")) && !is_prime(&big("3215031751")) && !is_prime(&big("3825123056546413051")));
+
+    let factors = |n: &str| factor(&big(n)).iter().map(BigInt::to_string).collect::<Vec<_>>().join(" ");
+    assert!(factors("360") == "2 2 2 3 3 5");
+    assert!(factors("-91") == "-1 7 13");
+    assert!(factors("1").is_empty() && factors("0") == "0");
+    assert!(factors("600851475143") == "71 839 1471 6857");
+    assert!(factors("1000000016000000063") == "1000000007 1000000009");
+
+    assert!(modpow(&big("4"), &big("13"), &big("497")) == Some(big("445")));
+    assert!(modpow(&big("-2"), &big("3"), &big("5")) == Some(big("2")));
+    assert!(modpow(&big("3"), &big("-1"), &big("7")) == Some(big("5")));
+    assert!(modpow(&big("2"), &big("3"), &big("-5")) == Some(big("-2")));
+    assert!(modpow(&big("2"), &big("-1"), &big("4")).is_none() && modpow(&big("2"), &big("3"), &big("0")).is_none());
+}
+
+#[test]
+fn test_integer_2() {
+    use crate::{engine::Engine, error::Error, evaluator::EvalError};
+
+    let engine: Engine<Integer> = Engine::new();
+    let eval = |input: &str| match engine.value(input) {
+        Ok(value) => Ok(value.to_string()),
+        Err(Error::Eval(e)) => Err(e.without_span()),
+        Err(e) => panic!("{input} failed with {e}"),
+    };
+
+    assert!(eval("2^100 + 1").unwrap() == "1267650600228229401496703205377");
+    assert!(eval("123456789012345678901234567890 - 1").unwrap() == "123456789012345678901234567889");
+    assert!(eval("[-7 mod 3, 7 mod -3, div(-7, 2), div(2^70, 2^69)]").unwrap() == "[2, -2, -4, 2]");
+    assert!(eval("[7 div 2, -7 div 2, 7 div -2, 2^70 div 2^69 + 1]").unwrap() == "[3, -4, -4, 3]");
+    assert!(eval("[gcd(2^64, 6^20, 10^30), lcm(4, 6, 10)]").unwrap() == "[1048576, 60]");
+    assert!(eval("[isprime(2^61 - 1), isprime(2^61 + 1), isprime(1)]").unwrap() == "[1, 0, 0]");
+    assert!(eval("factor(2^64 + 1)").unwrap() == "[274177, 67280421310721]");
+    assert!(eval("[25!, 30!/28!, 0!]").unwrap() == "[15511210043330985984000000, 870, 1]");
+    assert!(eval("modpow(2, 10^18, 10^9 + 7)").unwrap() == "719476260");
+    // Results stay exact until something inexact comes in
+    assert!(eval("7/2 + 1/2").unwrap() == "4" && eval("sqrt(2)^2").unwrap() != "2");
+    // Whole literals are exact even past what an f64 holds, fractional ones are floats
+    assert!(eval("1e30").unwrap() == "1000000000000000000000000000000" && eval("1e30 == 10^30").unwrap() == "1");
+    assert!(eval("[2.5e3, 1234567890123456789.0, 12e-1]").unwrap() == "[2500, 1234567890123456789, 1.2]");
+
+    assert!(matches!(eval("gcd(4, 2.5)"), Err(EvalError::ExpectedInteger(x)) if x == "2.5"));
+    assert!(matches!(eval("modpow(2, -1, 4)"), Err(EvalError::InvalidArgument(..))));
+    assert!(matches!(eval("modpow(2, 3, 0)"), Err(EvalError::InvalidArgument(..))));
+    for input in ["7 mod 0", "div(7, 0)", "7 div 0", "mod(2^70, 0)", "[1, 2] mod [1, 0]"] {
+        assert!(matches!(eval(input), Err(EvalError::InvalidArgument(..))), "{input} did not fail");
+    }
+
+    // The same functions work on floats that hold whole numbers
+    let engine: Engine = Engine::new();
+    assert!(engine.value("[7.5 mod 2, -7 mod 3, gcd(12, 18), isprime(97), isprime(9.5)]").unwrap().to_string() == "[1.5, 2, 6, 1, 0]");
+    assert!(engine.value("factor(360)").unwrap().to_string() == "[2, 2, 2, 3, 3, 5]");
+}
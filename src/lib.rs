@@ -0,0 +1,33 @@
+pub mod span;
+pub mod tokens;
+pub mod ast;
+pub mod tokenizer;
+pub mod parser;
+pub mod evaluator;
+pub mod app_context;
+pub mod number;
+pub mod rational;
+pub mod integer;
+pub mod decimal;
+pub mod complex;
+pub mod derivative;
+pub mod error;
+pub mod simplify;
+pub mod matrix;
+pub mod object;
+pub mod function;
+pub mod compile;
+pub mod vm;
+pub mod batch;
+pub mod engine;
+
+pub use app_context::Arity;
+pub use batch::BatchVm;
+pub use compile::SlotExpr;
+pub use engine::{CompiledExpr, Engine};
+pub use error::Error;
+pub use evaluator::{EvalError, EvalOutput};
+pub use function::{Function, Registry, ScalarFunc};
+pub use number::Number;
+pub use object::Object;
+pub use vm::{Program, Vm};
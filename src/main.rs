@@ -1,47 +1,168 @@
 use std::io::{self, Write};
 
-use app_context::Context;
-use evaluator::{evaluate, EvalOutput};
-use tokenizer::tokenize;
-use parser::{shunting_yard, validate};
+use f_ops::{
+    complex::{Complex, ComplexFormat},
+    decimal::Decimal,
+    integer::Integer,
+    number::Settings,
+    rational::Rational,
+    Engine, EvalOutput, Number,
+};
 
-pub mod tokens;
-pub mod tokenizer;
-pub mod parser;
-pub mod evaluator;
-pub mod app_context;
+
+enum Session {
+    Float(Engine<f64>),
+    Rational(Engine<Rational>),
+    Integer(Engine<Integer>),
+    Decimal(Engine<Decimal>),
+    Complex(Engine<Complex>),
+}
+
+impl Session {
+    fn run(&mut self, input: &str) {
+        match self {
+            Self::Float(engine) => run(input, engine),
+            Self::Rational(engine) => run(input, engine),
+            Self::Integer(engine) => run(input, engine),
+            Self::Decimal(engine) => run(input, engine),
+            Self::Complex(engine) => run(input, engine),
+        }
+    }
+
+    fn strict(&self) -> bool {
+        match self {
+            Self::Float(engine) => engine.strict(),
+            Self::Rational(engine) => engine.strict(),
+            Self::Integer(engine) => engine.strict(),
+            Self::Decimal(engine) => engine.strict(),
+            Self::Complex(engine) => engine.strict(),
+        }
+    }
+
+    fn set_strict(&mut self, strict: bool) {
+        match self {
+            Self::Float(engine) => engine.set_strict(strict),
+            Self::Rational(engine) => engine.set_strict(strict),
+            Self::Integer(engine) => engine.set_strict(strict),
+            Self::Decimal(engine) => engine.set_strict(strict),
+            Self::Complex(engine) => engine.set_strict(strict),
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        match self {
+            Self::Float(engine) => engine.settings(),
+            Self::Rational(engine) => engine.settings(),
+            Self::Integer(engine) => engine.settings(),
+            Self::Decimal(engine) => engine.settings(),
+            Self::Complex(engine) => engine.settings(),
+        }
+    }
+
+    fn set_settings(&mut self, settings: Settings) {
+        match self {
+            Self::Float(engine) => engine.set_settings(settings),
+            Self::Rational(engine) => engine.set_settings(settings),
+            Self::Integer(engine) => engine.set_settings(settings),
+            Self::Decimal(engine) => engine.set_settings(settings),
+            Self::Complex(engine) => engine.set_settings(settings),
+        }
+    }
+
+    // Starts over in another mode, keeping the settings that are not about values.
+    fn switch(&mut self, session: Session) {
+        let (strict, settings) = (self.strict(), self.settings());
+        *self = session;
+        self.set_strict(strict);
+        self.set_settings(settings);
+    }
+
+    fn command(&mut self, command: &str) {
+        match command.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["mode", "float"] => {
+                self.switch(Self::Float(Engine::new()));
+                println!("Switched to float mode\n");
+            }
+            ["mode", "rational"] => {
+                self.switch(Self::Rational(Engine::new()));
+                println!("Switched to rational mode\n");
+            }
+            ["mode", "integer"] => {
+                self.switch(Self::Integer(Engine::new()));
+                println!("Switched to integer mode\n");
+            }
+            ["mode", "decimal"] => {
+                self.switch(Self::Decimal(Engine::new()));
+                println!("Switched to decimal mode, {} digits\n", self.settings().precision);
+            }
+            ["mode", "complex"] => {
+                self.switch(Self::Complex(Engine::new()));
+                println!("Switched to complex mode\n");
+            }
+            ["strict", "on"] => {
+                self.set_strict(true);
+                println!("Implicit multiplication is off\n");
+            }
+            ["strict", "off"] => {
+                self.set_strict(false);
+                println!("Implicit multiplication is on\n");
+            }
+            ["format", "rect"] => {
+                self.set_settings(Settings{format: ComplexFormat::Rectangular, ..self.settings()});
+                println!("Printing complex numbers in rectangular form\n");
+            }
+            ["format", "polar"] => {
+                self.set_settings(Settings{format: ComplexFormat::Polar, ..self.settings()});
+                println!("Printing complex numbers in polar form\n");
+            }
+            ["precision"] => println!("{} digits\n", self.settings().precision),
+            ["precision", digits] => match digits.parse::<usize>() {
+                Ok(digits) => {
+                    self.set_settings(Settings{precision: digits.max(1), ..self.settings()});
+                    println!("Precision set to {} digits\n", self.settings().precision);
+                }
+                Err(_) => println!("Invalid precision: \"{digits}\"\n"),
+            }
+            _ => println!("Unknown command: \":{command}\"\n"),
+        }
+    }
+}
+
+
+fn run<N: Number>(input: &str, engine: &mut Engine<N>) {
+    match engine.eval(input) {
+        Ok(result) => match result {
+            EvalOutput::Assignment(var, val) => println!("Assigned {} to {var}\n", engine.display(&val)),
+            EvalOutput::Definition(name, params) => println!("Defined {name}({})\n", params.join(", ")),
+            EvalOutput::Value(value) => println!("{}\n", engine.display(&value)),
+            EvalOutput::Expression(expr) => println!("{expr}\n"),
+        },
+        // The input is printed again with the failing part underlined
+        Err(e) => {
+            if let Some(span) = e.span() {
+                println!("{input}\n{}", span.underline());
+            }
+            println!("[{}] {e}\n", e.code());
+        }
+    }
+}
 
 fn main() {
-    let mut context = Context::new();
+    let mut session = Session::Float(Engine::new());
     let stdin = io::stdin();
     let mut stdout = io::stdout();
     loop {
         let mut input = String::new();
         print!(">>> ");
         stdout.flush().unwrap();
-        
-        stdin.read_line(&mut input).unwrap();
-        
-        let mut tokens = match tokenize(input.trim()) {
-            Ok(val) => val,
-            Err(e) => {
-                println!("{e}\n");
-                continue;
-            }
-        };
 
-        if let Err(e) = validate(&tokens) {
-            println!("{e}\n");
-            continue;
+        if stdin.read_line(&mut input).unwrap() == 0 {
+            break;
         }
 
-        tokens = shunting_yard(tokens);
-        match evaluate(tokens, &mut context) {
-            Ok(result) => match result {
-                EvalOutput::Assignment(var, val) => println!("Assigned {val} to {var}\n"),
-                EvalOutput::Value(value) => println!("{value}\n"),
-            },
-            Err(e) => println!("{e}\n"),
+        match input.trim().strip_prefix(':') {
+            Some(command) => session.command(command),
+            None => session.run(input.trim()),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,220 @@
+use std::{fmt::Display, ops::{Index, IndexMut}};
+
+use crate::number::Number;
+
+
+// Dense row-major matrix.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Matrix<N: Number> {
+    rows: usize,
+    cols: usize,
+    data: Vec<N>,
+}
+
+impl<N: Number> Matrix<N> {
+    pub fn new(rows: usize, cols: usize, data: Vec<N>) -> Self {
+        assert!(rows * cols == data.len(), "Matrix data does not match its shape");
+        Matrix{rows, cols, data}
+    }
+
+    // None when the rows are empty or of different lengths.
+    pub fn from_rows(rows: Vec<Vec<N>>) -> Option<Self> {
+        let cols = rows.first()?.len();
+        if cols == 0 || rows.iter().any(|row| row.len() != cols) {
+            return None;
+        }
+        let row_count = rows.len();
+        Some(Self::new(row_count, cols, rows.into_iter().flatten().collect()))
+    }
+
+    pub fn identity(n: usize) -> Self {
+        let data = (0..n * n)
+            .map(|i| N::from_f64(if i / n == i % n { 1.0 } else { 0.0 }))
+            .collect();
+        Self::new(n, n, data)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn is_square(&self) -> bool {
+        self.rows == self.cols
+    }
+
+    pub fn row(&self, row: usize) -> Vec<N> {
+        self.data[row * self.cols..(row + 1) * self.cols].to_vec()
+    }
+
+    pub fn elements(&self) -> &[N] {
+        &self.data
+    }
+
+    pub fn map(&self, f: impl Fn(N) -> N) -> Self {
+        Self::new(self.rows, self.cols, self.data.iter().cloned().map(f).collect())
+    }
+
+    pub fn try_map<E>(&self, f: impl Fn(N) -> Result<N, E>) -> Result<Self, E> {
+        let data = self.data.iter().cloned().map(f).collect::<Result<Vec<N>, E>>()?;
+        Ok(Self::new(self.rows, self.cols, data))
+    }
+
+    // Element-wise combination, None when the shapes differ.
+    pub fn try_zip<E>(&self, other: &Self, f: impl Fn(N, N) -> Result<N, E>) -> Option<Result<Self, E>> {
+        if (self.rows, self.cols) != (other.rows, other.cols) {
+            return None;
+        }
+        let data = self.data.iter().cloned()
+            .zip(other.data.iter().cloned())
+            .map(|(a, b)| f(a, b))
+            .collect::<Result<Vec<N>, E>>();
+        Some(data.map(|data| Self::new(self.rows, self.cols, data)))
+    }
+
+    pub fn transpose(&self) -> Self {
+        let data = (0..self.rows * self.cols)
+            .map(|i| self[(i % self.rows, i / self.rows)].clone())
+            .collect();
+        Self::new(self.cols, self.rows, data)
+    }
+
+    // Matrix product, None when the inner dimensions differ or are zero.
+    pub fn matmul(&self, other: &Self) -> Option<Self> {
+        if self.cols != other.rows || self.cols == 0 {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(self.rows * other.cols);
+        for row in 0..self.rows {
+            for col in 0..other.cols {
+                let sum = (1..self.cols).fold(self[(row, 0)].clone() * other[(0, col)].clone(), |sum, k| {
+                    sum + self[(row, k)].clone() * other[(k, col)].clone()
+                });
+                data.push(sum);
+            }
+        }
+        Some(Self::new(self.rows, other.cols, data))
+    }
+
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        for col in 0..self.cols {
+            self.data.swap(a * self.cols + col, b * self.cols + col);
+        }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting on a square matrix.
+    // Returns the determinant, and the inverse when the matrix is not singular.
+    fn gauss_jordan(&self) -> (N, Option<Self>) {
+        let n = self.rows;
+        let zero = N::from_f64(0.0);
+        let mut a = self.clone();
+        let mut inverse = Self::identity(n);
+        let mut det = N::from_f64(1.0);
+
+        for col in 0..n {
+            let pivot = (col..n)
+                .filter(|&row| a[(row, col)] != zero)
+                .max_by(|&x, &y| {
+                    let (x, y) = (a[(x, col)].clone().abs(), a[(y, col)].clone().abs());
+                    x.partial_cmp(&y).unwrap_or(std::cmp::Ordering::Equal)
+                });
+            let Some(pivot) = pivot else {
+                return (zero, None);
+            };
+
+            if pivot != col {
+                a.swap_rows(pivot, col);
+                inverse.swap_rows(pivot, col);
+                det = -det;
+            }
+
+            let p = a[(col, col)].clone();
+            det = det * p.clone();
+            for c in 0..n {
+                a[(col, c)] = a[(col, c)].clone() / p.clone();
+                inverse[(col, c)] = inverse[(col, c)].clone() / p.clone();
+            }
+
+            for row in (0..n).filter(|&row| row != col) {
+                let factor = a[(row, col)].clone();
+                if factor == zero {
+                    continue;
+                }
+                for c in 0..n {
+                    a[(row, c)] = a[(row, c)].clone() - factor.clone() * a[(col, c)].clone();
+                    inverse[(row, c)] = inverse[(row, c)].clone() - factor.clone() * inverse[(col, c)].clone();
+                }
+            }
+        }
+
+        (det, Some(inverse))
+    }
+
+    // None when the matrix is not square.
+    pub fn det(&self) -> Option<N> {
+        self.is_square().then(|| self.gauss_jordan().0)
+    }
+
+    // None when the matrix is not square or is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        if !self.is_square() {
+            return None;
+        }
+        self.gauss_jordan().1
+    }
+}
+
+impl<N: Number> Index<(usize, usize)> for Matrix<N> {
+    type Output = N;
+
+    fn index(&self, (row, col): (usize, usize)) -> &N {
+        &self.data[row * self.cols + col]
+    }
+}
+
+impl<N: Number> IndexMut<(usize, usize)> for Matrix<N> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut N {
+        &mut self.data[row * self.cols + col]
+    }
+}
+
+impl<N: Number> Display for Matrix<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for row in 0..self.rows {
+            if row > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "[")?;
+            for col in 0..self.cols {
+                if col > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", self[(row, col)])?;
+            }
+            write!(f, "]")?;
+        }
+        write!(f, "]")
+    }
+}
+
+
+#[test]
+fn test_matrix_0() {
+    let m = Matrix::from_rows(vec![vec![1.0, 2.0], vec![3.0, 4.0]]).unwrap();
+    assert!(m.det() == Some(-2.0));
+    assert!(m.transpose() == Matrix::new(2, 2, vec![1.0, 3.0, 2.0, 4.0]));
+    assert!(m.matmul(&m.transpose()) == Matrix::from_rows(vec![vec![5.0, 11.0], vec![11.0, 25.0]]));
+    let a = Matrix::from_rows(vec![vec![2.0, 1.0], vec![1.0, 1.0]]).unwrap();
+    assert!(a.inverse() == Matrix::from_rows(vec![vec![1.0, -1.0], vec![-1.0, 2.0]]));
+    assert!(m.to_string() == "[[1, 2], [3, 4]]");
+
+    let singular = Matrix::from_rows(vec![vec![1.0, 2.0], vec![2.0, 4.0]]).unwrap();
+    assert!(singular.inverse().is_none() && singular.det() == Some(0.0));
+    assert!(Matrix::<f64>::from_rows(vec![vec![1.0], vec![2.0, 3.0]]).is_none());
+    assert!(Matrix::<f64>::new(1, 0, vec![]).matmul(&Matrix::new(0, 2, vec![])).is_none());
+}
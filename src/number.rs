@@ -0,0 +1,270 @@
+use std::{fmt::{Debug, Display}, ops::{Add, Div, Mul, Neg, Sub}};
+
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use crate::{complex::ComplexFormat, decimal::DEFAULT_PRECISION, integer};
+
+
+// How an engine computes and prints its numbers, types read the parts that apply to them.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Settings {
+    // Significant digits of decimal results
+    pub precision: usize,
+    pub format: ComplexFormat,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings{precision: DEFAULT_PRECISION, format: ComplexFormat::Rectangular}
+    }
+}
+
+
+// Numeric type the evaluator and context are generic over.
+pub trait Number:
+    Clone + PartialEq + PartialOrd + Debug + Display + Send + Sync + 'static +
+    Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self>
+{
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(&self) -> f64;
+
+    fn pi() -> Self;
+    fn e() -> Self;
+
+    // Integer literals too large for an f64 come in here, types that can hold them exactly override it.
+    fn from_integer(x: &BigInt) -> Self {
+        Self::from_f64(x.to_f64().unwrap_or(f64::NAN))
+    }
+
+    // Decimal literals an f64 cannot hold exactly come in here as written, like "0.1" or "1e30".
+    fn from_literal(text: &str) -> Self {
+        Self::from_f64(text.parse().unwrap_or(f64::NAN))
+    }
+
+    // The value as an integer, if it is one.
+    fn to_integer(&self) -> Option<BigInt> {
+        let x = self.to_f64();
+        match x.fract() == 0.0 {
+            true => BigInt::from_f64(x),
+            false => None,
+        }
+    }
+
+    // Named constants, looked up when no variable of that name is set.
+    fn constant(name: &str) -> Option<Self> {
+        match name {
+            "pi" => Some(Self::pi()),
+            "e" => Some(Self::e()),
+            _ => None,
+        }
+    }
+
+    // Conditions are numbers, zero is false and anything else is true.
+    fn is_true(&self) -> bool {
+        *self != Self::from_f64(0.0)
+    }
+
+    fn from_bool(value: bool) -> Self {
+        Self::from_f64(if value { 1.0 } else { 0.0 })
+    }
+
+    fn pow(self, exponent: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+
+    fn tan(self) -> Self {
+        self.clone().sin() / self.cos()
+    }
+
+    fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+
+    fn atan2(self, x: Self) -> Self {
+        Self::from_f64(self.to_f64().atan2(x.to_f64()))
+    }
+
+    fn hypot(self, other: Self) -> Self {
+        (self.clone() * self + other.clone() * other).sqrt()
+    }
+
+    fn abs(self) -> Self {
+        if self < Self::from_f64(0.0) { -self } else { self }
+    }
+
+    fn floor(self) -> Self {
+        Self::from_f64(self.to_f64().floor())
+    }
+
+    // Division rounding down, so "div(-7, 2)" is -4.
+    fn div_floor(self, other: Self) -> Self {
+        (self / other).floor()
+    }
+
+    // Remainder of "div_floor", it takes the sign of the divisor, so "-7 mod 3" is 2.
+    fn modulo(self, other: Self) -> Self {
+        self.clone() - other.clone() * self.div_floor(other)
+    }
+
+    // n! for whole n and the gamma function of x + 1 for everything else, types holding integers exactly keep n! exact.
+    fn factorial(self) -> Self {
+        match self.to_integer().as_ref().and_then(integer::factorial) {
+            Some(x) => Self::from_integer(&x),
+            None => Self::from_f64(gamma(self.to_f64() + 1.0)),
+        }
+    }
+
+    fn arg(self) -> Self {
+        if self < Self::from_f64(0.0) { Self::pi() } else { Self::from_f64(0.0) }
+    }
+
+    fn re(self) -> Self {
+        self
+    }
+
+    // Only values on the real line are ordered, types with complex values override it.
+    fn is_real(&self) -> bool {
+        true
+    }
+
+    fn im(self) -> Self {
+        Self::from_f64(0.0)
+    }
+
+    fn conj(self) -> Self {
+        self
+    }
+
+    // Runs f with the settings in effect for the arithmetic and printing of this type on the current thread.
+    fn scoped<R>(_settings: &Settings, f: impl FnOnce() -> R) -> R {
+        f()
+    }
+}
+
+
+// Splits a decimal literal like "1.25e-3" into its digits and power of ten, 125 and -5.
+pub(crate) fn literal_parts(text: &str) -> Option<(BigInt, i64)> {
+    let (digits, exponent) = match text.split_once(['e', 'E']) {
+        Some((digits, exponent)) => (digits, exponent.parse::<i64>().ok()?),
+        None => (text, 0),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    let mantissa: BigInt = format!("{whole}{fraction}").parse().ok()?;
+    Some((mantissa, exponent.checked_sub(fraction.len() as i64)?))
+}
+
+
+// Lanczos approximation, good to about 15 digits. Non-positive integers are poles and give NaN.
+pub fn gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9, 676.520_368_121_885_1, -1_259.139_216_722_402_8, 771.323_428_777_653_1,
+        -176.615_029_162_140_6, 12.507_343_278_686_905, -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x <= 0.0 && x.fract() == 0.0 {
+        return f64::NAN;
+    }
+    if x > 171.7 {
+        return f64::INFINITY;
+    }
+    // Reflection formula, the approximation only holds right of 1/2
+    if x < 0.5 {
+        return std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x));
+    }
+
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let sum = COEFFICIENTS[1..].iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| sum + c / (x + i as f64 + 1.0));
+    (2.0 * std::f64::consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+}
+
+
+macro_rules! impl_float {
+    ($t:ident) => {
+        impl Number for $t {
+            fn from_f64(x: f64) -> Self {
+                x as $t
+            }
+
+            fn to_f64(&self) -> f64 {
+                *self as f64
+            }
+
+            fn pi() -> Self {
+                std::$t::consts::PI
+            }
+
+            fn e() -> Self {
+                std::$t::consts::E
+            }
+
+            fn pow(self, exponent: Self) -> Self {
+                self.powf(exponent)
+            }
+
+            fn sqrt(self) -> Self {
+                $t::sqrt(self)
+            }
+
+            fn exp(self) -> Self {
+                $t::exp(self)
+            }
+
+            fn ln(self) -> Self {
+                $t::ln(self)
+            }
+
+            fn sin(self) -> Self {
+                $t::sin(self)
+            }
+
+            fn cos(self) -> Self {
+                $t::cos(self)
+            }
+
+            fn tan(self) -> Self {
+                $t::tan(self)
+            }
+
+            fn log(self, base: Self) -> Self {
+                $t::log(self, base)
+            }
+
+            fn atan2(self, x: Self) -> Self {
+                $t::atan2(self, x)
+            }
+
+            fn hypot(self, other: Self) -> Self {
+                $t::hypot(self, other)
+            }
+
+            fn abs(self) -> Self {
+                $t::abs(self)
+            }
+
+            fn floor(self) -> Self {
+                $t::floor(self)
+            }
+
+            // Whole numbers are multiplied out, which is exact as long as the float can hold the result
+            fn factorial(self) -> Self {
+                let x = self as f64;
+                match x >= 0.0 && x.fract() == 0.0 {
+                    true => (2..=x.min(171.0) as u64).fold(1.0, |product, k| product * k as f64) as $t,
+                    false => gamma(x + 1.0) as $t,
+                }
+            }
+        }
+    };
+}
+
+impl_float!(f32);
+impl_float!(f64);
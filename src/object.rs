@@ -0,0 +1,311 @@
+use std::{fmt::Display, ops::{Neg, Not}};
+
+use crate::{evaluator::EvalError, matrix::Matrix, number::Number, tokens::BinaryOp};
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Shape {
+    Scalar,
+    Vector(usize),
+    Matrix(usize, usize),
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Scalar => write!(f, "scalar"),
+            Self::Vector(len) => write!(f, "vector of {len}"),
+            Self::Matrix(rows, cols) => write!(f, "{rows}x{cols} matrix"),
+        }
+    }
+}
+
+
+// Value produced by evaluating an expression.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Object<N: Number> {
+    Scalar(N),
+    Vector(Vec<N>),
+    Matrix(Matrix<N>),
+}
+
+impl<N: Number> From<N> for Object<N> {
+    fn from(value: N) -> Self {
+        Self::Scalar(value)
+    }
+}
+
+impl<N: Number> Object<N> {
+    pub fn shape(&self) -> Shape {
+        match self {
+            Self::Scalar(_) => Shape::Scalar,
+            Self::Vector(v) => Shape::Vector(v.len()),
+            Self::Matrix(m) => Shape::Matrix(m.rows(), m.cols()),
+        }
+    }
+
+    pub fn scalar(self) -> Result<N, EvalError> {
+        match self {
+            Self::Scalar(x) => Ok(x),
+            other => Err(EvalError::ExpectedScalar(other.shape())),
+        }
+    }
+
+    // Builds a vector from a list of scalars, or a matrix from a list of equally long vectors.
+    pub fn from_list(items: Vec<Object<N>>) -> Result<Self, EvalError> {
+        match items.first() {
+            Some(Self::Vector(_)) => {
+                let rows = items.into_iter()
+                    .map(|item| match item {
+                        Self::Vector(row) => Ok(row),
+                        other => Err(EvalError::InvalidList(other.shape())),
+                    })
+                    .collect::<Result<Vec<Vec<N>>, EvalError>>()?;
+                // Empty rows, which a host can set, make no matrix either
+                match rows.iter().find(|row| row.is_empty() || row.len() != rows[0].len()) {
+                    Some(row) => Err(EvalError::InvalidList(Shape::Vector(row.len()))),
+                    None => Matrix::from_rows(rows).map(Self::Matrix).ok_or(EvalError::InvalidList(Shape::Vector(0))),
+                }
+            }
+            _ => items.into_iter()
+                .map(|item| match item {
+                    Self::Scalar(x) => Ok(x),
+                    other => Err(EvalError::InvalidList(other.shape())),
+                })
+                .collect::<Result<Vec<N>, EvalError>>()
+                .map(Self::Vector),
+        }
+    }
+
+    pub fn try_map(self, f: impl Fn(N) -> Result<N, EvalError>) -> Result<Self, EvalError> {
+        match self {
+            Self::Scalar(x) => f(x).map(Self::Scalar),
+            Self::Vector(v) => v.into_iter().map(f).collect::<Result<Vec<N>, EvalError>>().map(Self::Vector),
+            Self::Matrix(m) => m.try_map(f).map(Self::Matrix),
+        }
+    }
+
+    pub fn elements(&self) -> &[N] {
+        match self {
+            Self::Scalar(x) => std::slice::from_ref(x),
+            Self::Vector(v) => v,
+            Self::Matrix(m) => m.elements(),
+        }
+    }
+
+    // Element-wise operation, where a scalar is combined with every element of the other side.
+    fn zip(self, other: Self, op: &str, f: impl Fn(N, N) -> Result<N, EvalError>) -> Result<Self, EvalError> {
+        let mismatch = EvalError::ShapeMismatch(op.to_string(), self.shape(), other.shape());
+        match (self, other) {
+            (Self::Scalar(a), b) => b.try_map(|b| f(a.clone(), b)),
+            (a, Self::Scalar(b)) => a.try_map(|a| f(a, b.clone())),
+            (Self::Vector(a), Self::Vector(b)) if a.len() == b.len() => {
+                a.into_iter().zip(b).map(|(a, b)| f(a, b)).collect::<Result<Vec<N>, EvalError>>().map(Self::Vector)
+            }
+            (Self::Matrix(a), Self::Matrix(b)) => a.try_zip(&b, f).ok_or(mismatch)?.map(Self::Matrix),
+            _ => Err(mismatch),
+        }
+    }
+
+    pub fn try_add(self, other: Self) -> Result<Self, EvalError> {
+        self.zip(other, "+", |a, b| Ok(a + b))
+    }
+
+    pub fn try_sub(self, other: Self) -> Result<Self, EvalError> {
+        self.zip(other, "-", |a, b| Ok(a - b))
+    }
+
+    pub fn try_div(self, other: Self) -> Result<Self, EvalError> {
+        self.zip(other, "/", |a, b| Ok(a / b))
+    }
+
+    // Remainder with the sign of the divisor, see Number::modulo.
+    pub fn try_mod(self, other: Self) -> Result<Self, EvalError> {
+        self.zip(other, "mod", |a, b| BinaryOp::Mod.try_apply(a, b))
+    }
+
+    pub fn try_div_floor(self, other: Self) -> Result<Self, EvalError> {
+        self.zip(other, "div", |a, b| BinaryOp::FloorDiv.try_apply(a, b))
+    }
+
+    // Comparisons go element by element, giving 1 where they hold and 0 elsewhere.
+    pub fn try_compare(self, other: Self, op: &BinaryOp) -> Result<Self, EvalError> {
+        self.zip(other, op.symbol(), |a, b| op.try_apply(a, b))
+    }
+
+    // Value of a condition, which has to be a single number.
+    pub fn is_true(self) -> Result<bool, EvalError> {
+        self.scalar().map(|x| x.is_true())
+    }
+
+    // Matrix product when both sides are arrays, a vector acting as a column on the right and a row on the left.
+    pub fn try_mul(self, other: Self) -> Result<Self, EvalError> {
+        let mismatch = EvalError::ShapeMismatch("*".to_string(), self.shape(), other.shape());
+        let product = |a: Matrix<N>, b: Matrix<N>| a.matmul(&b).ok_or(mismatch);
+
+        match (self, other) {
+            (Self::Matrix(a), Self::Matrix(b)) => product(a, b).map(Self::Matrix),
+            (Self::Matrix(a), Self::Vector(v)) => {
+                let column = Matrix::new(v.len(), 1, v);
+                product(a, column).map(|m| Self::Vector(m.elements().to_vec()))
+            }
+            (Self::Vector(v), Self::Matrix(b)) => {
+                let row = Matrix::new(1, v.len(), v);
+                product(row, b).map(|m| Self::Vector(m.elements().to_vec()))
+            }
+            (a, b) => a.zip(b, "*", |a, b| Ok(a * b)),
+        }
+    }
+
+    // Integer powers of square matrices, element-wise powers for everything else.
+    pub fn pow(self, other: Self) -> Result<Self, EvalError> {
+        match (self, other) {
+            (Self::Matrix(m), Self::Scalar(n)) => {
+                let shape = Shape::Matrix(m.rows(), m.cols());
+                let power = n.to_f64();
+                if !m.is_square() || power.fract() != 0.0 {
+                    return Err(EvalError::ShapeMismatch("^".to_string(), shape, Shape::Scalar));
+                }
+
+                let mut base = if power < 0.0 { m.inverse().ok_or(EvalError::SingularMatrix)? } else { m };
+                let mut result = Matrix::identity(base.rows());
+                let mut exponent = power.abs() as u64;
+                while exponent > 0 {
+                    if exponent % 2 == 1 {
+                        result = result.matmul(&base).unwrap();
+                    }
+                    base = base.matmul(&base).unwrap();
+                    exponent /= 2;
+                }
+                Ok(Self::Matrix(result))
+            }
+            (a, b) => a.zip(b, "^", |a, b| Ok(a.pow(b))),
+        }
+    }
+
+    // 1-based indexing, a matrix with one index gives back a row.
+    pub fn index(self, indices: &[N]) -> Result<Self, EvalError> {
+        let position = |index: &N, len: usize| {
+            let i = index.to_f64();
+            match i.fract() == 0.0 && i >= 1.0 && i <= len as f64 {
+                true => Ok(i as usize - 1),
+                false => Err(EvalError::IndexOutOfRange(index.to_string(), len)),
+            }
+        };
+
+        match (self, indices) {
+            (Self::Vector(v), [i]) => Ok(Self::Scalar(v[position(i, v.len())?].clone())),
+            (Self::Matrix(m), [row]) => Ok(Self::Vector(m.row(position(row, m.rows())?))),
+            (Self::Matrix(m), [row, col]) => {
+                let (row, col) = (position(row, m.rows())?, position(col, m.cols())?);
+                Ok(Self::Scalar(m[(row, col)].clone()))
+            }
+            (target, _) => Err(EvalError::InvalidIndex(target.shape(), indices.len())),
+        }
+    }
+
+    pub fn dot(self, other: Self) -> Result<Self, EvalError> {
+        match (self, other) {
+            (Self::Vector(a), Self::Vector(b)) if a.len() == b.len() && !a.is_empty() => {
+                let mut products = a.into_iter().zip(b).map(|(a, b)| a * b);
+                let first = products.next().unwrap();
+                Ok(Self::Scalar(products.fold(first, |sum, x| sum + x)))
+            }
+            (a, b) => Err(EvalError::ShapeMismatch("dot".to_string(), a.shape(), b.shape())),
+        }
+    }
+
+    pub fn cross(self, other: Self) -> Result<Self, EvalError> {
+        match (self, other) {
+            (Self::Vector(a), Self::Vector(b)) if a.len() == 3 && b.len() == 3 => {
+                let product = |i: usize, j: usize| a[i].clone() * b[j].clone() - a[j].clone() * b[i].clone();
+                Ok(Self::Vector(vec![product(1, 2), product(2, 0), product(0, 1)]))
+            }
+            (a, b) => Err(EvalError::ShapeMismatch("cross".to_string(), a.shape(), b.shape())),
+        }
+    }
+
+    // A vector is treated as a row, so its transpose is a column matrix.
+    pub fn transpose(self) -> Self {
+        match self {
+            Self::Scalar(x) => Self::Scalar(x),
+            Self::Vector(v) => Self::Matrix(Matrix::new(v.len(), 1, v)),
+            Self::Matrix(m) => Self::Matrix(m.transpose()),
+        }
+    }
+
+    pub fn det(self) -> Result<Self, EvalError> {
+        match &self {
+            Self::Matrix(m) if m.is_square() => Ok(Self::Scalar(m.det().unwrap())),
+            other => Err(EvalError::ExpectedSquareMatrix(other.shape())),
+        }
+    }
+
+    pub fn inv(self) -> Result<Self, EvalError> {
+        match &self {
+            Self::Matrix(m) if m.is_square() => m.inverse().map(Self::Matrix).ok_or(EvalError::SingularMatrix),
+            other => Err(EvalError::ExpectedSquareMatrix(other.shape())),
+        }
+    }
+}
+
+impl<N: Number> Neg for Object<N> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self {
+            Self::Scalar(x) => Self::Scalar(-x),
+            Self::Vector(v) => Self::Vector(v.into_iter().map(|x| -x).collect()),
+            Self::Matrix(m) => Self::Matrix(m.map(|x| -x)),
+        }
+    }
+}
+
+impl<N: Number> Not for Object<N> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        let not = |x: N| N::from_bool(!x.is_true());
+        match self {
+            Self::Scalar(x) => Self::Scalar(not(x)),
+            Self::Vector(v) => Self::Vector(v.into_iter().map(not).collect()),
+            Self::Matrix(m) => Self::Matrix(m.map(not)),
+        }
+    }
+}
+
+impl<N: Number> Display for Object<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Scalar(x) => write!(f, "{x}"),
+            Self::Vector(v) => {
+                write!(f, "[")?;
+                for (i, x) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{x}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Matrix(m) => write!(f, "{m}"),
+        }
+    }
+}
+
+
+#[test]
+fn test_object_0() {
+    let m = Object::Matrix(Matrix::from_rows(vec![vec![1.0, 2.0], vec![3.0, 4.0]]).unwrap());
+    let v = Object::Vector(vec![1.0, 1.0]);
+
+    assert!(m.clone().try_mul(v.clone()).unwrap() == Object::Vector(vec![3.0, 7.0]));
+    assert!(v.clone().try_mul(m.clone()).unwrap() == Object::Vector(vec![4.0, 6.0]));
+    assert!(v.clone().try_add(Object::Scalar(1.0)).unwrap() == Object::Vector(vec![2.0, 2.0]));
+    assert!(m.clone().pow(Object::Scalar(2.0)).unwrap().to_string() == "[[7, 10], [15, 22]]");
+    assert!(m.clone().index(&[2.0, 1.0]).unwrap() == Object::Scalar(3.0));
+    assert!(matches!(v.clone().try_add(Object::Vector(vec![1.0])), Err(EvalError::ShapeMismatch(..))));
+    assert!(matches!(v.index(&[3.0]), Err(EvalError::IndexOutOfRange(..))));
+    let empty = Object::<f64>::Vector(vec![]);
+    assert!(matches!(Object::from_list(vec![empty.clone(), empty]), Err(EvalError::InvalidList(Shape::Vector(0)))));
+}
@@ -1,24 +1,60 @@
 use std::fmt::Display;
-use crate::tokens::{BinaryOp, Function, Glyph, Token, UnaryOp, Value};
+use crate::{ast::Expr, span::{Span, Spanned}, tokens::{Associativity, BinaryOp, Function, Glyph, PostfixOp, Token, UnaryOp, Value}};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ParserError {
-    UnevenBrackets,
-    IncorrectAssign,
-    OrderError(Token, Token),
+    UnevenBrackets(Span),
+    IncorrectAssign(Span),
+    MisplacedComma(Span),
+    MissingOperand(Span),
+    OrderError(Token, Token, Span),
+    IncorrectPiecewise(Span),
+    TooDeep(Span),
+}
+
+impl ParserError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnevenBrackets(_) => "E0101",
+            Self::IncorrectAssign(_) => "E0102",
+            Self::MisplacedComma(_) => "E0103",
+            Self::MissingOperand(_) => "E0104",
+            Self::OrderError(..) => "E0105",
+            Self::IncorrectPiecewise(_) => "E0106",
+            Self::TooDeep(_) => "E0107",
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnevenBrackets(span) |
+            Self::IncorrectAssign(span) |
+            Self::MisplacedComma(span) |
+            Self::MissingOperand(span) |
+            Self::OrderError(_, _, span) |
+            Self::IncorrectPiecewise(span) |
+            Self::TooDeep(span) => *span,
+        }
+    }
 }
 
 impl Display for ParserError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "ParseError -> ")?;
         match self {
-            Self::UnevenBrackets => write!(f, "Uneven Brackets"),
-            Self::IncorrectAssign => write!(f, "Incorrect Assign"),
-            Self::OrderError(t1, t2) => write!(f, "{t1} cannot precede {t2}"),
+            Self::UnevenBrackets(_) => write!(f, "Uneven Brackets"),
+            Self::IncorrectAssign(_) => write!(f, "Incorrect Assign"),
+            Self::MisplacedComma(_) => write!(f, "Misplaced Comma"),
+            Self::MissingOperand(_) => write!(f, "Missing Operand"),
+            Self::OrderError(t1, t2, _) => write!(f, "{t1} cannot precede {t2}"),
+            Self::IncorrectPiecewise(_) => write!(f, "Incorrect Piecewise"),
+            Self::TooDeep(_) => write!(f, "Nested Too Deeply (over {MAX_DEPTH} levels)"),
         }
     }
 }
 
+impl std::error::Error for ParserError {}
+
 
 pub trait Ordering {
     fn can_precede(&self, other: &Token) -> bool;
@@ -31,13 +67,14 @@ impl Ordering for Token {
             Token::Val(value) => value.can_precede(other),
             Token::Glyph(glyph) => glyph.can_precede(other),
             Self::End => false,
-            Self::Start => match other {
-                Token::Val(_) => true,
-                Token::Func(Function::UnaryOp(_)) => true,
-                Token::Func(Function::NamedFunc(_)) => true,
-                Token::Glyph(Glyph::LBracket) => true,
-                _ => false, 
-            }
+            Self::Start => matches!(other,
+                Token::Val(_) |
+                Token::Func(Function::UnaryOp(_)) |
+                Token::Func(Function::NamedFunc(_)) |
+                Token::Glyph(Glyph::LBracket) |
+                Token::Glyph(Glyph::LSquare) |
+                Token::Glyph(Glyph::LBrace)
+            ),
         }
     }
 }
@@ -45,180 +82,524 @@ impl Ordering for Token {
 impl Ordering for Function {
     fn can_precede(&self, other: &Token) -> bool {
         match self {
-            Self::Assign => match other {
-                Token::Val(_) => true,
-                Token::Func(Function::UnaryOp(_)) => true,
-                Token::Func(Function::NamedFunc(_)) => true,
-                Token::Glyph(Glyph::LBracket) => true,
-                _ => false,                
-            }
+            Self::Assign => matches!(other,
+                Token::Val(_) |
+                Token::Func(Function::UnaryOp(_)) |
+                Token::Func(Function::NamedFunc(_)) |
+                Token::Glyph(Glyph::LBracket) |
+                Token::Glyph(Glyph::LSquare) |
+                Token::Glyph(Glyph::LBrace)
+            ),
             Self::BinaryOp(op) => op.can_precede(other),
             Self::UnaryOp(op) => op.can_precede(other),
-            Self::NamedFunc(_) => match other {
-                Token::Glyph(Glyph::LBracket) => true,
-                _ => false,
-            }
+            Self::PostfixOp(op) => op.can_precede(other),
+            Self::NamedFunc(_) => matches!(other, Token::Glyph(Glyph::LBracket)),
         }
     }
 }
 
 impl Ordering for BinaryOp {
     fn can_precede(&self, other: &Token) -> bool {
-        match other {
-            Token::Val(_) => true,
-            Token::Func(Function::UnaryOp(_)) => true,
-            Token::Func(Function::NamedFunc(_)) => true,
-            Token::Glyph(Glyph::LBracket) => true,
-            _ => false,
-        }
+        matches!(other,
+            Token::Val(_) |
+            Token::Func(Function::UnaryOp(_)) |
+            Token::Func(Function::NamedFunc(_)) |
+            Token::Glyph(Glyph::LBracket) |
+            Token::Glyph(Glyph::LSquare) |
+            Token::Glyph(Glyph::LBrace)
+        )
     }
 }
 
 impl Ordering for UnaryOp {
     fn can_precede(&self, other: &Token) -> bool {
-        match other {
-            Token::Val(_) => true,
-            Token::Func(Function::UnaryOp(_)) => true,
-            Token::Func(Function::NamedFunc(_)) => true,
-            Token::Glyph(Glyph::LBracket) => true,
-            _ => false,
-        }
+        matches!(other,
+            Token::Val(_) |
+            Token::Func(Function::UnaryOp(_)) |
+            Token::Func(Function::NamedFunc(_)) |
+            Token::Glyph(Glyph::LBracket) |
+            Token::Glyph(Glyph::LSquare) |
+            Token::Glyph(Glyph::LBrace)
+        )
+    }
+}
+
+// Whatever may follow an operand, a postfix operator ends one.
+impl Ordering for PostfixOp {
+    fn can_precede(&self, other: &Token) -> bool {
+        matches!(other,
+            Token::Func(Function::BinaryOp(_)) |
+            Token::Func(Function::PostfixOp(_)) |
+            Token::Glyph(Glyph::Comma) |
+            Token::Glyph(Glyph::RBracket) |
+            Token::Glyph(Glyph::LSquare) |
+            Token::Glyph(Glyph::RSquare) |
+            Token::Glyph(Glyph::RBrace) |
+            Token::Glyph(Glyph::Semicolon) |
+            Token::Glyph(Glyph::If) |
+            Token::Glyph(Glyph::Otherwise) |
+            Token::End
+        )
     }
 }
 
 impl Ordering for Value {
     fn can_precede(&self, other: &Token) -> bool {
-        match other {
-            Token::Func(Function::Assign) => true,
-            Token::Func(Function::BinaryOp(_)) => true,
-            Token::Glyph(Glyph::Comma) => true,
-            Token::Glyph(Glyph::RBracket) => true,
-            Token::End => true,
-            _ => false,
-        }
+        matches!(other,
+            Token::Func(Function::Assign) |
+            Token::Func(Function::BinaryOp(_)) |
+            Token::Func(Function::PostfixOp(_)) |
+            Token::Glyph(Glyph::Comma) |
+            Token::Glyph(Glyph::RBracket) |
+            Token::Glyph(Glyph::LSquare) |
+            Token::Glyph(Glyph::RSquare) |
+            Token::Glyph(Glyph::RBrace) |
+            Token::Glyph(Glyph::Semicolon) |
+            Token::Glyph(Glyph::If) |
+            Token::Glyph(Glyph::Otherwise) |
+            Token::End
+        )
     }
 }
 
 impl Ordering for Glyph {
     fn can_precede(&self, other: &Token) -> bool {
         match self {
-            Glyph::LBracket | Glyph::Comma => {
-                match other {
-                    Token::Func(Function::UnaryOp(_)) => true,
-                    Token::Func(Function::NamedFunc(_)) => true,
-                    Token::Val(_) => true,
-                    Token::Glyph(Glyph::LBracket) => true,
-                    _ => false,
-                }
-            }
-            Glyph::RBracket => {
-                match other {
-                    Token::Func(Function::BinaryOp(_)) => true,
-                    Token::Glyph(Glyph::Comma) => true,
-                    Token::Glyph(Glyph::RBracket) => true,
-                    Token::End => true,
-                    _ => false,
-                }
-            }
+            Glyph::LBracket | Glyph::LSquare | Glyph::Comma | Glyph::LBrace | Glyph::Semicolon | Glyph::If => matches!(other,
+                Token::Func(Function::UnaryOp(_)) |
+                Token::Func(Function::NamedFunc(_)) |
+                Token::Val(_) |
+                Token::Glyph(Glyph::LBracket) |
+                Token::Glyph(Glyph::LSquare) |
+                Token::Glyph(Glyph::LBrace)
+            ),
+            Glyph::RBracket => matches!(other,
+                Token::Func(Function::Assign) |
+                Token::Func(Function::BinaryOp(_)) |
+                Token::Func(Function::PostfixOp(_)) |
+                Token::Glyph(Glyph::Comma) |
+                Token::Glyph(Glyph::RBracket) |
+                Token::Glyph(Glyph::LSquare) |
+                Token::Glyph(Glyph::RSquare) |
+                Token::Glyph(Glyph::RBrace) |
+                Token::Glyph(Glyph::Semicolon) |
+                Token::Glyph(Glyph::If) |
+                Token::Glyph(Glyph::Otherwise) |
+                Token::End
+            ),
+            Glyph::RSquare | Glyph::RBrace => matches!(other,
+                Token::Func(Function::BinaryOp(_)) |
+                Token::Func(Function::PostfixOp(_)) |
+                Token::Glyph(Glyph::Comma) |
+                Token::Glyph(Glyph::RBracket) |
+                Token::Glyph(Glyph::LSquare) |
+                Token::Glyph(Glyph::RSquare) |
+                Token::Glyph(Glyph::RBrace) |
+                Token::Glyph(Glyph::Semicolon) |
+                Token::Glyph(Glyph::If) |
+                Token::Glyph(Glyph::Otherwise) |
+                Token::End
+            ),
+            Glyph::Otherwise => matches!(other, Token::Glyph(Glyph::Semicolon | Glyph::RBrace)),
         }
     }
 }
 
 
 
-fn validate_brackets(tokens: &Vec<Token>) -> Result<(), ParserError> {
-    let mut l_bracket_count = 0;
-    let mut r_bracket_count = 0;
+// Matches every closing bracket with the last open one, pointing at the first that has no partner.
+fn validate_brackets(tokens: &[Spanned<Token>]) -> Result<(), ParserError> {
+    let mut open: Vec<&Spanned<Token>> = Vec::new();
     
     for t in tokens {
-        if let Token::Glyph(glyph) = t {
-            match glyph {
-                Glyph::LBracket => l_bracket_count += 1,
-                Glyph::RBracket => r_bracket_count += 1,
-                _ => continue,
+        let opening = match t.value {
+            Token::Glyph(Glyph::LBracket | Glyph::LSquare | Glyph::LBrace) => {
+                open.push(t);
+                continue;
             }
+            Token::Glyph(Glyph::RBracket) => Glyph::LBracket,
+            Token::Glyph(Glyph::RSquare) => Glyph::LSquare,
+            Token::Glyph(Glyph::RBrace) => Glyph::LBrace,
+            _ => continue,
+        };
+
+        match open.pop() {
+            Some(open) if open.value == Token::Glyph(opening) => (),
+            _ => return Err(ParserError::UnevenBrackets(t.span)),
         }
     }
 
-    if l_bracket_count == r_bracket_count {
-        return Ok(());
+    match open.last() {
+        Some(t) => Err(ParserError::UnevenBrackets(t.span)),
+        None => Ok(()),
     }
-    Err(ParserError::UnevenBrackets)
 }
 
 
-pub fn validate(tokens: &Vec<Token>) -> Result<(), ParserError> {
+// Checks that the tokens before "=" name a variable, "x", or a function with parameters, "f(x, y)".
+fn is_assign_target(tokens: &[Spanned<Token>]) -> bool {
+    let tokens: Vec<&Token> = tokens.iter().map(|t| &t.value).collect();
+    match tokens.as_slice() {
+        [Token::Val(Value::Var(_))] => true,
+        [Token::Func(Function::NamedFunc(_)), Token::Glyph(Glyph::LBracket), params @ .., Token::Glyph(Glyph::RBracket)] => {
+            params.len() % 2 == 1 && params.iter().enumerate().all(|(i, token)| match i % 2 {
+                0 => matches!(token, Token::Val(Value::Var(_))),
+                _ => matches!(token, Token::Glyph(Glyph::Comma)),
+            })
+        }
+        _ => false,
+    }
+}
+
+
+// Writes out the multiplication in "2x", "3(x + 1)", "(a + b)(a - b)", "2pi", "2sin(x)" and "x y".
+// It is an ordinary "*" afterwards, with the same precedence. A name directly followed by "(" stays a call,
+// "[" after an operand stays an index and a number never starts an implicit product, so "2 3" is still an error.
+pub fn insert_implicit_mul(tokens: Vec<Spanned<Token>>) -> Vec<Spanned<Token>> {
+    let mut output: Vec<Spanned<Token>> = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        if let Some(prev) = output.last() {
+            let ends_operand = matches!(prev.value,
+                Token::Val(_) | Token::Func(Function::PostfixOp(_)) | Token::Glyph(Glyph::RBracket) | Token::Glyph(Glyph::RSquare)
+            );
+            let starts_operand = matches!(token.value,
+                Token::Val(Value::Var(_)) | Token::Func(Function::NamedFunc(_)) | Token::Glyph(Glyph::LBracket)
+            );
+            if ends_operand && starts_operand {
+                let span = Span::new(prev.span.end, token.span.start.max(prev.span.end));
+                output.push(Spanned::new(BinaryOp::Mul.into(), span));
+            }
+        }
+        output.push(token);
+    }
+    output
+}
+
+pub fn validate(tokens: &[Spanned<Token>]) -> Result<(), ParserError> {
     validate_brackets(tokens)?;
     
-    for (count, token) in tokens.windows(2).enumerate() {
-        if !token[0].can_precede(&token[1]) {
-            return Err(ParserError::OrderError(token[0].clone(), token[1].clone()));
-        }
-        if token[0] == Token::Func(Function::Assign) && count != 2 {
-            return Err(ParserError::IncorrectAssign);
+    for token in tokens.windows(2) {
+        if !token[0].value.can_precede(&token[1].value) {
+            let span = token[0].span.to(token[1].span);
+            return Err(ParserError::OrderError(token[0].value.clone(), token[1].value.clone(), span));
         }
     };
 
+    let mut assigns = tokens.iter()
+        .enumerate()
+        .filter(|(_, token)| token.value == Token::Func(Function::Assign));
+    if let Some((position, assign)) = assigns.next() {
+        if let Some((_, second)) = assigns.next() {
+            return Err(ParserError::IncorrectAssign(second.span));
+        }
+        if !is_assign_target(&tokens[1..position]) {
+            return Err(ParserError::IncorrectAssign(assign.span));
+        }
+    }
+
     Ok(())
 }
 
-// Converts infix to postfix. Needs to be done before evaluation.
-pub fn shunting_yard(tokens: Vec<Token>) -> Vec<Token> {
-    let mut output: Vec<Token> = Vec::new();
-    let mut operations: Vec<Token> = Vec::new();
+// Passes over the tree recurse into operands, so deeper expressions are rejected before they can overflow the stack.
+// Left operands of binary operations do not count, passes follow those in a loop so long sums are not limited by it.
+pub const MAX_DEPTH: usize = 256;
 
-    for token in tokens {
-        match token {
-            Token::Val(_) => output.push(token),
-            Token::Func(Function::NamedFunc(_)) => operations.push(token),
-            Token::Func(ref function) => 
-            loop {
-                match operations.last() {
-                    None | Some(Token::Glyph(_)) => {
-                        operations.push(token);
-                        break;
+// The operands built so far and how deep each of them is.
+// Every node is built from the operands taken off since the previous push, so those give its depth.
+// A left operand is taken off one level up, it adds nothing to the operation built from it.
+#[derive(Default)]
+struct Output {
+    exprs: Vec<Expr>,
+    depths: Vec<usize>,
+    taken: usize,
+}
+
+impl Output {
+    fn push(&mut self, expr: Expr) -> Result<(), ParserError> {
+        let depth = std::mem::take(&mut self.taken) + 1;
+        if depth > MAX_DEPTH {
+            return Err(ParserError::TooDeep(span_of(&expr, Span::default())));
+        }
+        self.exprs.push(expr);
+        self.depths.push(depth);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Expr> {
+        self.taken = self.taken.max(self.depths.pop()?);
+        self.exprs.pop()
+    }
+
+    fn pop_left(&mut self) -> Option<Expr> {
+        self.taken = self.taken.max(self.depths.pop()? - 1);
+        self.exprs.pop()
+    }
+
+    fn split_off(&mut self, first: usize) -> Vec<Expr> {
+        self.taken = self.depths.split_off(first).into_iter().fold(self.taken, usize::max);
+        self.exprs.split_off(first)
+    }
+
+    fn len(&self) -> usize {
+        self.exprs.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.exprs.is_empty()
+    }
+}
+
+// Span of an operand, falling back to the operator's own when it has none.
+fn span_of(expr: &Expr, fallback: Span) -> Span {
+    expr.span().unwrap_or(fallback)
+}
+
+fn pop_function(operations: &mut Vec<Spanned<Token>>, output: &mut Output) -> Result<(), ParserError> {
+    let Some(Spanned{value: token, span}) = operations.pop() else {
+        return Err(ParserError::MissingOperand(Span::default()));
+    };
+    let function = match token {
+        Token::Func(function) => function,
+        Token::Glyph(Glyph::LBracket | Glyph::LSquare | Glyph::LBrace) => return Err(ParserError::UnevenBrackets(span)),
+        _ => return Err(ParserError::MissingOperand(span)),
+    };
+    let missing = ParserError::MissingOperand(span);
+
+    let expr = match function {
+        Function::Assign => {
+            let value = output.pop().ok_or(missing.clone())?;
+            let target = output.pop().ok_or(missing)?;
+            let span = span_of(&target, span).to(span_of(&value, span));
+            let expr = match target.inner() {
+                Expr::Var(name) => Expr::assign(name, value),
+                Expr::Call(name, args) => {
+                    let mut params: Vec<String> = Vec::with_capacity(args.len());
+                    for arg in args {
+                        match arg.inner() {
+                            Expr::Var(param) if !params.contains(param) => params.push(param.clone()),
+                            _ => return Err(ParserError::IncorrectAssign(span_of(arg, span))),
+                        }
                     }
-                    Some(Token::Func(prev_function)) if prev_function.presedence() <= function.presedence() => {
-                        operations.push(token);
+                    Expr::Define(name.clone(), params, Box::new(value))
+                }
+                _ => return Err(ParserError::IncorrectAssign(span)),
+            };
+            Expr::at(span, expr)
+        }
+        Function::BinaryOp(op) => {
+            let rhs = output.pop().ok_or(missing.clone())?;
+            let lhs = output.pop_left().ok_or(missing)?;
+            Expr::at(span_of(&lhs, span).to(span_of(&rhs, span)), Expr::binary(op, lhs, rhs))
+        }
+        Function::UnaryOp(op) => {
+            let operand = output.pop().ok_or(missing)?;
+            Expr::at(span.to(span_of(&operand, span)), Expr::unary(op, operand))
+        }
+        Function::PostfixOp(op) => {
+            let operand = output.pop().ok_or(missing)?;
+            Expr::at(span_of(&operand, span).to(span), Expr::postfix(op, operand))
+        }
+        // Calls are built when their closing bracket is reached
+        Function::NamedFunc(_) => return Err(missing),
+    };
+
+    output.push(expr)
+}
+
+// Pops operators down to the innermost open bracket of either kind and returns its kind, leaving it on the stack.
+fn pop_until_bracket(operations: &mut Vec<Spanned<Token>>, output: &mut Output, span: Span) -> Result<Glyph, ParserError> {
+    while let Some(token) = operations.last() {
+        if let Token::Glyph(glyph @ (Glyph::LBracket | Glyph::LSquare | Glyph::LBrace)) = &token.value {
+            return Ok(glyph.clone());
+        }
+        pop_function(operations, output)?;
+    }
+    Err(ParserError::UnevenBrackets(span))
+}
+
+// Takes the last "count" expressions off the output, the arguments of a call or the items of a list.
+fn split_items(output: &mut Output, count: usize, span: Span) -> Result<Vec<Expr>, ParserError> {
+    let first = output.len()
+        .checked_sub(count)
+        .ok_or(ParserError::MissingOperand(span))?;
+    Ok(output.split_off(first))
+}
+
+// Clauses of an open piecewise block, whether each has a condition, and how many of them are complete.
+#[derive(Default)]
+struct Block {
+    conditional: Vec<bool>,
+    complete: usize,
+}
+
+impl Block {
+    // Called on "if" and "otherwise", each clause has exactly one of them and no clause follows "otherwise".
+    fn start_condition(&mut self, conditional: bool, span: Span) -> Result<(), ParserError> {
+        if self.conditional.len() != self.complete || self.conditional.contains(&false) {
+            return Err(ParserError::IncorrectPiecewise(span));
+        }
+        self.conditional.push(conditional);
+        Ok(())
+    }
+
+    // Called on ";" and "}".
+    fn end_clause(&mut self, span: Span) -> Result<(), ParserError> {
+        if self.conditional.len() != self.complete + 1 {
+            return Err(ParserError::IncorrectPiecewise(span));
+        }
+        self.complete += 1;
+        Ok(())
+    }
+
+    // Takes the values and conditions of every clause off the output.
+    fn build(self, output: &mut Output, span: Span) -> Result<Expr, ParserError> {
+        let count = self.conditional.iter().map(|&conditional| if conditional { 2 } else { 1 }).sum();
+        let mut items = split_items(output, count, span)?.into_iter();
+        let mut clauses = Vec::with_capacity(self.conditional.len());
+        let mut otherwise = None;
+        for conditional in self.conditional {
+            let value = items.next().unwrap();
+            match conditional {
+                true => clauses.push((value, items.next().unwrap())),
+                false => otherwise = Some(value),
+            }
+        }
+        Ok(Expr::piecewise(clauses, otherwise))
+    }
+}
+
+// Pops operators down to the open "{" of the innermost block, anything else in between is out of place.
+fn pop_clause<'a>(operations: &mut Vec<Spanned<Token>>, output: &mut Output, blocks: &'a mut [Block], span: Span) -> Result<&'a mut Block, ParserError> {
+    match pop_until_bracket(operations, output, span) {
+        Ok(Glyph::LBrace) => blocks.last_mut().ok_or(ParserError::IncorrectPiecewise(span)),
+        Ok(_) | Err(ParserError::UnevenBrackets(_)) => Err(ParserError::IncorrectPiecewise(span)),
+        Err(error) => Err(error),
+    }
+}
+
+// Builds the expression tree with the shunting yard algorithm. Needs to be done before evaluation.
+// Every node is wrapped with the span of the input it was built from.
+pub fn parse(tokens: Vec<Spanned<Token>>) -> Result<Expr, ParserError> {
+    let mut output = Output::default();
+    let mut operations: Vec<Spanned<Token>> = Vec::new();
+    // Number of commas inside each open bracket, used to count call arguments and list items
+    let mut commas: Vec<usize> = Vec::new();
+    // Whether each open square bracket indexes the operand before it, or starts a list
+    let mut indexing: Vec<bool> = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut after_operand = false;
+    let mut end = Span::default();
+
+    for Spanned{value: token, span} in tokens {
+        let ends_operand = matches!(token,
+            Token::Val(_) | Token::Func(Function::PostfixOp(_)) |
+            Token::Glyph(Glyph::RBracket) | Token::Glyph(Glyph::RSquare) | Token::Glyph(Glyph::RBrace)
+        );
+
+        match token {
+            Token::Val(Value::Scalar(x)) => output.push(Expr::at(span, Expr::Literal(x)))?,
+            Token::Val(Value::Integer(x)) => output.push(Expr::at(span, Expr::Integer(x)))?,
+            Token::Val(Value::Decimal(text)) => output.push(Expr::at(span, Expr::Decimal(text)))?,
+            Token::Val(Value::Var(name)) => output.push(Expr::at(span, Expr::Var(name)))?,
+            Token::Func(Function::NamedFunc(_) | Function::UnaryOp(_)) => operations.push(Spanned::new(token, span)),
+            Token::Func(ref function) => {
+                // Operators of equal precedence are applied first when the incoming one groups to the left
+                while let Some(Token::Func(prev_function)) = operations.last().map(|t| &t.value) {
+                    let applies_first = match function.associativity() {
+                        Associativity::Left => prev_function.presedence() >= function.presedence(),
+                        Associativity::Right => prev_function.presedence() > function.presedence(),
+                    };
+                    if !applies_first {
                         break;
                     }
-                    Some(Token::Func(_)) => {
-                        let prev_function = operations.pop().unwrap();
-                        output.push(prev_function);
-                    }
-                    Some(_) => (),                   
+                    pop_function(&mut operations, &mut output)?;
+                }
+                operations.push(Spanned::new(token, span));
+            }
+            Token::Glyph(Glyph::LBracket) => {
+                operations.push(Spanned::new(token, span));
+                commas.push(0);
+            }
+            Token::Glyph(Glyph::RBracket) => {
+                if pop_until_bracket(&mut operations, &mut output, span)? != Glyph::LBracket {
+                    return Err(ParserError::UnevenBrackets(span));
                 }
+                operations.pop();
+                let comma_count = commas.pop().ok_or(ParserError::UnevenBrackets(span))?;
 
-            },
-            Token::Glyph(Glyph::LBracket) => operations.push(token),
-            Token::Glyph(Glyph::RBracket) => 
-            while let Some(prev_token) = operations.last() {
-                if let Token::Glyph(Glyph::LBracket) = prev_token{
-                    operations.pop().unwrap();
-                    break;
+                if let Some(Token::Func(Function::NamedFunc(_))) = operations.last().map(|t| &t.value) {
+                    let Some(Spanned{value: Token::Func(Function::NamedFunc(name)), span: name_span}) = operations.pop() else {
+                        unreachable!()
+                    };
+                    let args = split_items(&mut output, comma_count + 1, span)?;
+                    output.push(Expr::at(name_span.to(span), Expr::Call(name, args)))?;
+                }
+                else if comma_count > 0 {
+                    return Err(ParserError::MisplacedComma(span));
                 }
-                let prev_token = operations.pop().unwrap();
-                output.push(prev_token);
             }
-            Token::Glyph(Glyph::Comma) => 
-            while let Some(Token::Func(_)) = operations.last() {
-                let prev_function = operations.pop().unwrap();
-                output.push(prev_function);
+            Token::Glyph(Glyph::LSquare) => {
+                operations.push(Spanned::new(token, span));
+                commas.push(0);
+                indexing.push(after_operand);
             }
-            Token::Start | Token::End => continue,
+            Token::Glyph(Glyph::RSquare) => {
+                if pop_until_bracket(&mut operations, &mut output, span)? != Glyph::LSquare {
+                    return Err(ParserError::UnevenBrackets(span));
+                }
+                let open = operations.pop().map_or(span, |t| t.span);
+                let comma_count = commas.pop().ok_or(ParserError::UnevenBrackets(span))?;
+
+                let items = split_items(&mut output, comma_count + 1, span)?;
+                let expr = match indexing.pop() {
+                    Some(true) => {
+                        let target = output.pop().ok_or(ParserError::MissingOperand(open))?;
+                        Expr::at(span_of(&target, open).to(span), Expr::index(target, items))
+                    }
+                    _ => Expr::at(open.to(span), Expr::list(items)),
+                };
+                output.push(expr)?;
+            }
+            Token::Glyph(Glyph::Comma) => {
+                if pop_until_bracket(&mut operations, &mut output, span)? == Glyph::LBrace {
+                    return Err(ParserError::MisplacedComma(span));
+                }
+                *commas.last_mut().ok_or(ParserError::MisplacedComma(span))? += 1;
+            }
+            Token::Glyph(Glyph::LBrace) => {
+                operations.push(Spanned::new(token, span));
+                blocks.push(Block::default());
+            }
+            Token::Glyph(Glyph::If) => pop_clause(&mut operations, &mut output, &mut blocks, span)?.start_condition(true, span)?,
+            Token::Glyph(Glyph::Otherwise) => pop_clause(&mut operations, &mut output, &mut blocks, span)?.start_condition(false, span)?,
+            Token::Glyph(Glyph::Semicolon) => pop_clause(&mut operations, &mut output, &mut blocks, span)?.end_clause(span)?,
+            Token::Glyph(Glyph::RBrace) => {
+                pop_clause(&mut operations, &mut output, &mut blocks, span)?.end_clause(span)?;
+                let open = operations.pop().map_or(span, |t| t.span);
+                let block = blocks.pop().ok_or(ParserError::UnevenBrackets(span))?;
+                let expr = block.build(&mut output, span)?;
+                output.push(Expr::at(open.to(span), expr))?;
+            }
+            Token::Start => (),
+            Token::End => end = span,
         }
+        after_operand = ends_operand;
     }
 
-    for op in operations.into_iter().rev() {
-        output.push(op);
+    while !operations.is_empty() {
+        pop_function(&mut operations, &mut output)?;
+    }
+
+    match (output.pop(), output.is_empty()) {
+        (Some(expr), true) => Ok(expr),
+        (Some(expr), false) => Err(ParserError::MissingOperand(span_of(&expr, end))),
+        (None, _) => Err(ParserError::MissingOperand(end)),
     }
-    
-    output
 }
 
 #[test]
 fn test_validate_0() {
-    let input = vec![
+    let input: Vec<Spanned<Token>> = [
         Token::Start,
         Value::Var("a".to_string()).into(),
         BinaryOp::Add.into(),
@@ -229,13 +610,13 @@ fn test_validate_0() {
         Value::Var("x".to_string()).into(),
         Glyph::RBracket.into(),
         Token::End,
-    ];
+    ].into_iter().map(Spanned::from).collect();
     assert!(validate(&input).is_ok(), "a + b * sin(x) was validated to false, expected: true.");
 }
 
 #[test]
 fn test_validate_1() {
-    let input = vec![
+    let input: Vec<Spanned<Token>> = [
         Token::Start,
         Value::Scalar(11.0).into(),
         BinaryOp::Sub.into(),
@@ -244,7 +625,7 @@ fn test_validate_1() {
         UnaryOp::Neg.into(),
         Value::Var("e".to_string()).into(),
         Token::End
-    ];
+    ].into_iter().map(Spanned::from).collect();
     assert!(validate(&input).is_ok(), "11-2/-e was validated to false, expected: true.");
 }
 
@@ -266,7 +647,7 @@ fn test_validate_2() {
 }
 
 #[test]
-fn tes_shunting_0() {
+fn test_parse_0() {
     use crate::tokens::ExpressionBuilder;
     let exp_builder = ExpressionBuilder::new();
     let input = exp_builder
@@ -275,23 +656,318 @@ fn tes_shunting_0() {
         .add()
         .func("sin")
         .lbracket()
-        .scalar(3.14)
+        .scalar(3.5)
         .mul()
         .scalar(2.0)
         .rbracket()
         .end()
         .collect();
 
+    let output = Expr::binary(
+        BinaryOp::Add,
+        Expr::literal(1.0),
+        Expr::call("sin", vec![Expr::binary(BinaryOp::Mul, Expr::literal(3.5), Expr::literal(2.0))]),
+    );
+
+    assert!(parse(input).unwrap().without_spans() == output)
+}
+
+#[test]
+fn test_parse_1() {
+    use crate::tokens::ExpressionBuilder;
     let exp_builder = ExpressionBuilder::new();
-    let output = exp_builder
+    let input = exp_builder
+        .start()
+        .var("y")
+        .assign()
+        .func("max")
+        .lbracket()
+        .var("a")
+        .comma()
+        .neg()
+        .lbracket()
+        .var("b")
+        .sub()
         .scalar(1.0)
-        .scalar(3.14)
+        .rbracket()
+        .rbracket()
+        .end()
+        .collect();
+
+    let output = Expr::assign("y", Expr::call("max", vec![
+        Expr::var("a"),
+        Expr::unary(UnaryOp::Neg, Expr::binary(BinaryOp::Sub, Expr::var("b"), Expr::literal(1.0))),
+    ]));
+
+    assert!(parse(input).unwrap().without_spans() == output, "y = max(a, -(b - 1)) parsed incorrectly");
+}
+
+#[test]
+fn test_parse_2() {
+    use crate::tokens::ExpressionBuilder;
+    let exp_builder = ExpressionBuilder::new();
+    let input = exp_builder
+        .start()
+        .lbracket()
+        .scalar(1.0)
+        .comma()
         .scalar(2.0)
-        .mul()
-        .func("sin")
-        .add()
+        .rbracket()
+        .end()
         .collect();
 
-    
-    assert!(shunting_yard(input) == output)
-}
\ No newline at end of file
+    assert!(parse(input) == Err(ParserError::MisplacedComma(Span::new(5, 6))), "(1, 2) was parsed, expected an error.");
+}
+
+#[test]
+fn test_parse_3() {
+    use crate::tokenizer::tokenize;
+
+    let tokens = tokenize("f(x, y) = x^2 + y").unwrap();
+    assert!(validate(&tokens).is_ok(), "f(x, y) = x^2 + y was validated to false, expected: true.");
+
+    let output = Expr::define("f", &["x", "y"], Expr::binary(
+        BinaryOp::Add,
+        Expr::binary(BinaryOp::Pow, Expr::var("x"), Expr::literal(2.0)),
+        Expr::var("y"),
+    ));
+    assert!(parse(tokens).unwrap().without_spans() == output, "f(x, y) = x^2 + y parsed incorrectly");
+
+    for input in ["f(x, 2) = x", "f(x, x) = x", "f(x) + 1 = x", "x = y = 2"] {
+        let tokens = tokenize(input).unwrap();
+        let result = validate(&tokens).and_then(|_| parse(tokens).map(|_| ()));
+        assert!(result.is_err(), "{input} was accepted, expected an error.");
+    }
+}
+
+#[test]
+fn test_parse_4() {
+    use crate::tokenizer::tokenize;
+
+    let output = Expr::binary(
+        BinaryOp::Pow,
+        Expr::index(Expr::list(vec![Expr::literal(1.0), Expr::unary(UnaryOp::Neg, Expr::literal(2.0))]), vec![Expr::literal(2.0)]),
+        Expr::index(Expr::var("m"), vec![Expr::literal(1.0), Expr::call("f", vec![Expr::var("x")])]),
+    );
+    let tokens = tokenize("[1, -2][2]^m[1, f(x)]").unwrap();
+    assert!(validate(&tokens).is_ok());
+    assert!(parse(tokens).unwrap().without_spans() == output, "[1, -2][2]^m[1, f(x)] parsed incorrectly");
+
+    let tokens = tokenize("[1, (2]").unwrap();
+    assert!(parse(tokens).is_err(), "[1, (2] parsed, expected an error");
+}
+
+#[test]
+fn test_parse_5() {
+    use crate::tokenizer::tokenize;
+
+    let error = |input: &str| {
+        let tokens = tokenize(input).unwrap();
+        validate(&tokens).and_then(|_| parse(tokens).map(|_| ())).unwrap_err()
+    };
+    assert!(error("1 + (2*x") == ParserError::UnevenBrackets(Span::new(4, 5)));
+    assert!(error("[1, 2)") == ParserError::UnevenBrackets(Span::new(5, 6)));
+    assert!(error("2 * + 3").span() == Span::new(2, 5));
+    assert!(error("x + 1 = 2") == ParserError::IncorrectAssign(Span::new(6, 7)));
+
+    let expr = parse(tokenize("max(1, y) + [2, 3][1]").unwrap()).unwrap();
+    let Expr::Binary(_, lhs, rhs) = expr.inner() else {
+        panic!("Expected a sum, got {expr}");
+    };
+    assert!(expr.span() == Some(Span::new(0, 21)));
+    assert!(lhs.span() == Some(Span::new(0, 9)) && rhs.span() == Some(Span::new(12, 21)));
+}
+
+#[test]
+fn test_parse_6() {
+    use crate::tokenizer::tokenize;
+
+    let parse_implicit = |input: &str| {
+        let tokens = insert_implicit_mul(tokenize(input).unwrap());
+        validate(&tokens).and_then(|_| parse(tokens)).map(|expr| expr.without_spans())
+    };
+    let parse_explicit = |input: &str| parse(tokenize(input).unwrap()).unwrap().without_spans();
+
+    let cases = [
+        ("2x", "2*x"), ("3(x + 1)", "3*(x + 1)"), ("(a + b)(a - b)", "(a + b)*(a - b)"), ("2pi", "2*pi"),
+        ("2sin(x)", "2*sin(x)"), ("x y", "x*y"), ("2x^2", "2*x^2"), ("-2x", "-2*x"), ("x[1]y", "x[1]*y"),
+        ("sin(x)cos(x)", "sin(x)*cos(x)"), ("f(x) = 2x", "f(x) = 2*x"),
+    ];
+    for (implicit, explicit) in cases {
+        assert!(parse_implicit(implicit) == Ok(parse_explicit(explicit)), "{implicit} parsed differently from {explicit}");
+    }
+
+    for input in ["2 3", "x 2", "(1)2", "2x = 1"] {
+        assert!(parse_implicit(input).is_err(), "{input} was accepted, expected an error");
+    }
+    let tokens = insert_implicit_mul(tokenize("2x").unwrap());
+    assert!(tokens[2].value == Token::from(BinaryOp::Mul) && tokens[2].span == Span::new(1, 1));
+}
+
+#[test]
+fn test_parse_7() {
+    use crate::tokenizer::tokenize;
+
+    let parse_str = |input: &str| {
+        let tokens = insert_implicit_mul(tokenize(input).unwrap());
+        validate(&tokens).and_then(|_| parse(tokens)).map(|expr| expr.without_spans())
+    };
+
+    // Each input next to the same expression with every group written out
+    let table = [
+        ("1 + 2 + 3", "(1 + 2) + 3"),
+        ("1 - 2 - 3", "(1 - 2) - 3"),
+        ("1 - 2 + 3", "(1 - 2) + 3"),
+        ("1 + 2 - 3", "(1 + 2) - 3"),
+        ("8/4/2", "(8/4)/2"),
+        ("8/4*2", "(8/4)*2"),
+        ("8*4/2", "(8*4)/2"),
+        ("1 + 2*3", "1 + (2*3)"),
+        ("1 - 6/3", "1 - (6/3)"),
+        ("2*3^2", "2*(3^2)"),
+        ("2^3^2", "2^(3^2)"),
+        ("2^3*4", "(2^3)*4"),
+        ("-2^2", "-(2^2)"),
+        ("-2*3", "(-2)*3"),
+        ("-2 + 3", "(-2) + 3"),
+        ("--2^2", "-(-(2^2))"),
+        ("2^-3", "2^(-3)"),
+        ("2^-3*4", "(2^(-3))*4"),
+        ("2^-x^2", "2^(-(x^2))"),
+        ("a*-b^c", "a*(-(b^c))"),
+        ("1/2x", "(1/2)*x"),
+        ("2x^2", "2*(x^2)"),
+        ("x = 1 - 2 - 3", "x = ((1 - 2) - 3)"),
+        ("x = -2^2", "x = (-(2^2))"),
+        ("sin(x)^2", "(sin(x))^2"),
+        ("m[1]^2", "(m[1])^2"),
+        ("1 + 2 < 3*4", "(1 + 2) < (3*4)"),
+        ("a < b == c", "(a < b) == c"),
+        ("a < b && c || d && e", "((a < b) && c) || (d && e)"),
+        ("!a && b", "(!a) && b"),
+        ("!x > 1", "(!x) > 1"),
+        ("-x < 2", "(-x) < 2"),
+        ("!-x^2", "!(-(x^2))"),
+        ("y = a || b", "y = (a || b)"),
+        ("7 mod 3*2", "(7 mod 3)*2"),
+        ("2*7 mod 3", "(2*7) mod 3"),
+        ("a mod b^2", "a mod (b^2)"),
+        ("-7 mod 3", "(-7) mod 3"),
+        ("1 + a mod b", "1 + (a mod b)"),
+        ("7 div 2*3", "(7 div 2)*3"),
+        ("a div b^2 mod c", "(a div (b^2)) mod c"),
+        ("-7 div 3", "(-7) div 3"),
+        ("-3!", "-(3!)"),
+        ("2^3!", "2^(3!)"),
+        ("3!^2", "(3!)^2"),
+        ("x!!", "(x!)!"),
+        ("3! - 1", "(3!) - 1"),
+        ("f(x)!*2", "(f(x)!)*2"),
+        ("m[1]!", "(m[1])!"),
+        ("5!x", "(5!)*x"),
+        ("!x!", "!(x!)"),
+        ("20%*150", "(20%)*150"),
+        ("a + b%", "a + (b%)"),
+        ("20% - 5", "(20%) - 5"),
+        ("20%-5", "(20%) - 5"),
+        ("20% -5", "(20%) - 5"),
+        ("50% x", "(50%)*x"),
+        ("50%x", "(50%)*x"),
+        ("7 % (2)", "(7%)*2"),
+        ("x%%", "(x%)%"),
+        ("x%^2", "(x%)^2"),
+    ];
+    for (input, grouped) in table {
+        let expr = parse_str(input).unwrap();
+        assert!(expr == parse_str(grouped).unwrap(), "{input} parsed as {expr}, expected {grouped}");
+        // Printing keeps the grouping
+        assert!(parse_str(&expr.to_string()).unwrap() == expr, "{input} printed as {expr}, which parses differently");
+    }
+}
+
+#[test]
+fn test_parse_8() {
+    use crate::tokenizer::tokenize;
+
+    let parse_str = |input: &str| {
+        let tokens = insert_implicit_mul(tokenize(input).unwrap());
+        validate(&tokens).and_then(|_| parse(tokens)).map(|expr| expr.without_spans())
+    };
+
+    let expected = Expr::define("f", &["x"], Expr::piecewise(
+        vec![(
+            Expr::binary(BinaryOp::Pow, Expr::var("x"), Expr::literal(2.0)),
+            Expr::binary(BinaryOp::Lt, Expr::var("x"), Expr::literal(0.0)),
+        )],
+        Some(Expr::call("sqrt", vec![Expr::var("x")])),
+    ));
+    assert!(parse_str("f(x) = { x^2 if x < 0; sqrt(x) otherwise }").unwrap() == expected);
+
+    // Printing gives back the input
+    for input in [
+        "f(x) = { x^2 if x < 0; sqrt(x) otherwise }",
+        "{ -1 if x < -1; { 0 if x == 0; x otherwise } if x <= 1 } + if(y, 1, 2)",
+        "2*{ a if b && c }[1]",
+        "{ 5 otherwise } + 1",
+    ] {
+        let expr = parse_str(input).unwrap();
+        assert!(expr.to_string() == input, "{input} printed as {expr}");
+    }
+
+    assert!(matches!(parse_str("{ 1 otherwise; 2 if x }"), Err(ParserError::IncorrectPiecewise(span)) if span == Span::new(17, 19)));
+    assert!(matches!(parse_str("{ 1 if x if y }"), Err(ParserError::IncorrectPiecewise(_))));
+    assert!(matches!(parse_str("{ 1; 2 otherwise }"), Err(ParserError::IncorrectPiecewise(_))));
+    assert!(matches!(parse_str("1 if x"), Err(ParserError::IncorrectPiecewise(_))));
+    assert!(matches!(parse_str("{ 1, 2 if x }"), Err(ParserError::MisplacedComma(_))));
+    assert!(matches!(parse_str("{ 1 if x ]"), Err(ParserError::UnevenBrackets(_))));
+    assert!(matches!(parse_str("{}"), Err(ParserError::OrderError(..))));
+}
+
+#[test]
+fn test_parse_9() {
+    use crate::tokenizer::tokenize;
+
+    let parse_str = |input: &str| {
+        let tokens = insert_implicit_mul(tokenize(input).unwrap());
+        validate(&tokens).and_then(|_| parse(tokens))
+    };
+
+    let expected = Expr::binary(
+        BinaryOp::Mul,
+        Expr::postfix(PostfixOp::Percent, Expr::literal(20.0)),
+        Expr::postfix(PostfixOp::Factorial, Expr::var("n")),
+    );
+    assert!(parse_str("20%*n!").unwrap().without_spans() == expected);
+    assert!(parse_str("(1 + 2)! + 1").unwrap().to_string() == "(1 + 2)! + 1");
+    assert!(parse_str("(-3)!").unwrap().to_string() == "(-3)!");
+    // The postfix operator is part of its operand's span
+    let Expr::At(_, sum) = parse_str("1 + x!").unwrap() else { panic!("Expected a span") };
+    assert!(matches!(*sum, Expr::Binary(_, _, ref rhs) if rhs.span() == Some(Span::new(4, 6))));
+
+    for input in ["!", "%", "(!)", "2 + %", "x! = 3", "[1, %]"] {
+        assert!(matches!(parse_str(input), Err(ParserError::OrderError(..))), "{input} was accepted, expected an order error");
+    }
+}
+
+#[test]
+fn test_parse_10() {
+    use crate::tokenizer::tokenize;
+
+    let parse_str = |input: &str| {
+        let tokens = insert_implicit_mul(tokenize(input).unwrap());
+        validate(&tokens).and_then(|_| parse(tokens))
+    };
+    let sum = |terms: usize| format!("1{}", "+1".repeat(terms - 1));
+
+    assert!(matches!(parse_str(&format!("{}1", "-".repeat(10_000))), Err(ParserError::TooDeep(_))));
+    assert!(matches!(parse_str(&format!("max({})", "f(".repeat(10_000) + "1" + &")".repeat(10_000))), Err(ParserError::TooDeep(_))));
+    // Right operands nest, "^" groups to the right and brackets can put a sum there too
+    let power = |levels: usize| format!("2{}", "^2".repeat(levels));
+    assert!(parse_str(&power(MAX_DEPTH - 1)).is_ok() && matches!(parse_str(&power(MAX_DEPTH)), Err(ParserError::TooDeep(_))));
+    assert!(matches!(parse_str(&format!("{}1{}", "1+(".repeat(10_000), ")".repeat(10_000))), Err(ParserError::TooDeep(_))));
+    // Only nesting counts, not length or brackets, left operands of a long sum are not nested
+    assert!(parse_str(&sum(10_000)).is_ok() && parse_str(&format!("{}*2", sum(10_000))).is_ok());
+    assert!(parse_str(&format!("(x > 0{})", " && x < 1".repeat(10_000))).is_ok());
+    assert!(parse_str(&format!("[1{}]", ", 1".repeat(10_000))).is_ok());
+    assert!(parse_str(&format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000))).is_ok());
+}
@@ -0,0 +1,289 @@
+use std::{cmp::Ordering, fmt::Display, ops::{Add, Div, Mul, Neg, Sub}};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+
+use crate::number::{literal_parts, Number};
+
+
+// Largest power computed exactly, in bits of the numerator or denominator, anything bigger is left to floats.
+const MAX_POWER_BITS: u64 = 1 << 20;
+
+
+// Exact fraction, or a float when an operation could not be carried out exactly.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Rational {
+    Exact(BigRational),
+    Approx(f64),
+}
+
+impl Rational {
+    pub fn is_exact(&self) -> bool {
+        matches!(self, Self::Exact(_))
+    }
+
+    pub fn to_approx(&self) -> f64 {
+        match self {
+            Self::Exact(x) => x.to_f64().unwrap_or(f64::NAN),
+            Self::Approx(x) => *x,
+        }
+    }
+
+    fn integer(x: i64) -> Self {
+        Self::Exact(BigRational::from_integer(BigInt::from(x)))
+    }
+
+    fn exact_or(self, exact: impl FnOnce(&BigRational) -> Option<BigRational>, approx: impl FnOnce(f64) -> f64) -> Self {
+        if let Self::Exact(x) = &self {
+            if let Some(result) = exact(x) {
+                return Self::Exact(result);
+            }
+        }
+        Self::Approx(approx(self.to_approx()))
+    }
+
+    fn combine(
+        self,
+        other: Self,
+        exact: impl FnOnce(&BigRational, &BigRational) -> Option<BigRational>,
+        approx: impl FnOnce(f64, f64) -> f64,
+    ) -> Self {
+        if let (Self::Exact(a), Self::Exact(b)) = (&self, &other) {
+            if let Some(result) = exact(a, b) {
+                return Self::Exact(result);
+            }
+        }
+        Self::Approx(approx(self.to_approx(), other.to_approx()))
+    }
+}
+
+
+// Integer n-th root of x, if x is a perfect n-th power.
+fn exact_root(x: &BigInt, n: u32) -> Option<BigInt> {
+    if x.is_negative() && n.is_multiple_of(2) {
+        return None;
+    }
+
+    let root = x.abs().nth_root(n);
+    let root = if x.is_negative() { -root } else { root };
+    (num_traits::pow(root.clone(), n as usize) == *x).then_some(root)
+}
+
+fn exact_pow(base: &BigRational, exponent: &BigRational) -> Option<BigRational> {
+    let power = exponent.numer().to_i32()?;
+    let root = exponent.denom().to_u32()?;
+    let base = BigRational::new(exact_root(base.numer(), root)?, exact_root(base.denom(), root)?);
+
+    if base.is_zero() && power < 0 {
+        return None;
+    }
+    let bits = base.numer().bits().max(base.denom().bits());
+    (bits * u64::from(power.unsigned_abs()) <= MAX_POWER_BITS).then(|| base.pow(power))
+}
+
+
+impl Display for Rational {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact(x) => write!(f, "{x}"),
+            Self::Approx(x) => write!(f, "~{x}"),
+        }
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Exact(a), Self::Exact(b)) => a.partial_cmp(b),
+            (a, b) => a.to_approx().partial_cmp(&b.to_approx()),
+        }
+    }
+}
+
+impl Add for Rational {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.combine(other, |a, b| Some(a + b), |a, b| a + b)
+    }
+}
+
+impl Sub for Rational {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.combine(other, |a, b| Some(a - b), |a, b| a - b)
+    }
+}
+
+impl Mul for Rational {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        self.combine(other, |a, b| Some(a * b), |a, b| a * b)
+    }
+}
+
+impl Div for Rational {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        self.combine(other, |a, b| (!b.is_zero()).then(|| a / b), |a, b| a / b)
+    }
+}
+
+impl Neg for Rational {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self {
+            Self::Exact(x) => Self::Exact(-x),
+            Self::Approx(x) => Self::Approx(-x),
+        }
+    }
+}
+
+
+impl Number for Rational {
+    // Goes through the shortest decimal form of x, so a literal like 0.1 becomes exactly 1/10
+    fn from_f64(x: f64) -> Self {
+        if !x.is_finite() {
+            return Self::Approx(x);
+        }
+
+        Self::from_literal(&x.to_string())
+    }
+
+    fn from_literal(text: &str) -> Self {
+        match literal_parts(text) {
+            // A power of ten takes a little over 3 bits per digit
+            Some((mantissa, exponent)) if exponent.unsigned_abs() * 4 <= MAX_POWER_BITS => {
+                let power = num_traits::pow(BigInt::from(10), exponent.unsigned_abs() as usize);
+                match exponent < 0 {
+                    true => Self::Exact(BigRational::new(mantissa, power)),
+                    false => Self::Exact(BigRational::from_integer(mantissa * power)),
+                }
+            }
+            _ => Self::Approx(text.parse().unwrap_or(f64::NAN)),
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.to_approx()
+    }
+
+    fn from_integer(x: &BigInt) -> Self {
+        Self::Exact(BigRational::from_integer(x.clone()))
+    }
+
+    fn to_integer(&self) -> Option<BigInt> {
+        match self {
+            Self::Exact(x) if x.is_integer() => Some(x.to_integer()),
+            Self::Exact(_) => None,
+            Self::Approx(x) if x.fract() == 0.0 => BigInt::from_f64(*x),
+            Self::Approx(_) => None,
+        }
+    }
+
+    fn floor(self) -> Self {
+        self.exact_or(|x| Some(x.floor()), f64::floor)
+    }
+
+    fn pi() -> Self {
+        Self::Approx(std::f64::consts::PI)
+    }
+
+    fn e() -> Self {
+        Self::Approx(std::f64::consts::E)
+    }
+
+    fn pow(self, exponent: Self) -> Self {
+        self.combine(exponent, exact_pow, f64::powf)
+    }
+
+    fn sqrt(self) -> Self {
+        self.pow(Self::Exact(BigRational::new(One::one(), BigInt::from(2))))
+    }
+
+    fn exp(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigRational::one), f64::exp)
+    }
+
+    fn ln(self) -> Self {
+        self.exact_or(|x| x.is_one().then(BigRational::zero), f64::ln)
+    }
+
+    fn sin(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigRational::zero), f64::sin)
+    }
+
+    fn cos(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigRational::one), f64::cos)
+    }
+
+    fn tan(self) -> Self {
+        self.exact_or(|x| x.is_zero().then(BigRational::zero), f64::tan)
+    }
+
+    fn log(self, base: Self) -> Self {
+        // Exact when x is an integer power of the base, e.g. log(2, 8)
+        if let (Self::Exact(x), Self::Exact(b)) = (&self, &base) {
+            let approx = (self.to_approx().ln() / base.to_approx().ln()).round();
+            if let Some(power) = approx.to_i32() {
+                let exponent = BigRational::from_integer(power.into());
+                if !b.is_zero() && exact_pow(b, &exponent).is_some_and(|y| y == *x) {
+                    return Self::integer(power.into());
+                }
+            }
+        }
+        Self::Approx(self.to_approx().log(base.to_approx()))
+    }
+}
+
+
+#[test]
+fn test_rational_0() {
+    let third = Rational::from_f64(1.0) / Rational::from_f64(3.0);
+    let sixth = Rational::from_f64(1.0) / Rational::from_f64(6.0);
+    assert!((third + sixth).to_string() == "1/2");
+    assert!((Rational::from_f64(0.1) + Rational::from_f64(0.2)).to_string() == "3/10");
+    assert!(Rational::from_f64(2.25).sqrt().to_string() == "3/2");
+    assert!(Rational::from_f64(8.0).pow(Rational::from_f64(-2.0) / Rational::from_f64(3.0)).to_string() == "1/4");
+}
+
+#[test]
+fn test_rational_1() {
+    let root = Rational::from_f64(2.0).sqrt();
+    assert!(!root.is_exact(), "sqrt(2) should not be exact");
+    assert!(root.to_string().starts_with("~1.414"));
+
+    let inf = Rational::from_f64(1.0) / Rational::from_f64(0.0);
+    assert!(inf == Rational::Approx(f64::INFINITY));
+    assert!(Rational::from_f64(8.0).log(Rational::from_f64(2.0)) == Rational::integer(3));
+}
+
+#[test]
+fn test_rational_2() {
+    let power = Rational::from_f64(2.0).pow(Rational::from_f64(300_000_000.0));
+    assert!(power == Rational::Approx(f64::INFINITY), "2^300000000 should be left to floats");
+    let power = Rational::from_f64(0.5).pow(Rational::from_f64(-300_000_000.0));
+    assert!(power == Rational::Approx(f64::INFINITY));
+    assert!(Rational::from_f64(2.0).pow(Rational::from_f64(1000.0)).is_exact());
+    // The base would be raised to about 7 million here, which is left to floats as well
+    let log = Rational::from_f64(2.0).log(Rational::from_literal("1.0000001"));
+    assert!(!log.is_exact() && (log.to_approx() - 6931472.1).abs() < 1.0);
+}
+
+#[test]
+fn test_rational_3() {
+    use crate::engine::Engine;
+
+    let engine: Engine<Rational> = Engine::new();
+    let value = |input: &str| engine.value(input).unwrap().to_string();
+    // Decimal literals become exact fractions, digits an f64 cannot hold included
+    assert!(value("0.1234567890123456789") == "1234567890123456789/10000000000000000000");
+    assert!(value("1e30") == "1000000000000000000000000000000" && value("2.5e-3") == "1/400");
+    assert!(value("0.1 + 0.2 == 0.3") == "1");
+    assert!(!Rational::from_literal("1e999999").is_exact());
+}
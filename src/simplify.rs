@@ -0,0 +1,488 @@
+use std::cmp::Ordering;
+
+use num_rational::BigRational;
+
+use crate::{ast::{map_clauses, Expr}, evaluator::EvalError, number::Number, parser::MAX_DEPTH, rational::Rational, tokens::{BinaryOp, PostfixOp, UnaryOp}};
+
+
+// Product of powers with an exact coefficient, like 3*x^2*sin(y).
+// Factors that may be matrices keep the order they were multiplied in, plain numbers are sorted after them.
+#[derive(Debug, Clone)]
+struct Term {
+    coefficient: Rational,
+    factors: Vec<(Expr, Expr)>,
+}
+
+fn constant(x: f64) -> Rational {
+    Rational::from_f64(x)
+}
+
+// The value of an already simplified expression that is a plain number, see `coefficient_expr`.
+fn constant_value(expr: &Expr) -> Option<Rational> {
+    match expr {
+        Expr::Literal(x) => Some(constant(*x)),
+        Expr::Integer(x) => Some(Rational::Exact(BigRational::from_integer(x.clone()))),
+        Expr::Binary(BinaryOp::Div, p, q) => match (p.as_ref(), q.as_ref()) {
+            (p @ (Expr::Literal(_) | Expr::Integer(_)), q @ (Expr::Literal(_) | Expr::Integer(_))) => {
+                Some(constant_value(p)? / constant_value(q)?)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_integer(x: &Rational) -> bool {
+    matches!(x, Rational::Exact(x) if x.is_integer())
+}
+
+// Without variables a factor is a plain number, anything else may hold a matrix and does not commute.
+fn is_scalar(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::Integer(_) | Expr::Decimal(_) => true,
+        Expr::Unary(_, operand) | Expr::Postfix(_, operand) | Expr::At(_, operand) => is_scalar(operand),
+        Expr::Binary(_, lhs, rhs) => is_scalar(lhs) && is_scalar(rhs),
+        Expr::Call(_, args) => args.iter().all(is_scalar),
+        _ => false,
+    }
+}
+
+// Variables come before function calls and other compound factors, "x^2*cos(x)".
+fn factor_key(factor: &(Expr, Expr)) -> (u8, String) {
+    let rank = match factor.0 {
+        Expr::Var(_) => 0,
+        Expr::Call(..) => 1,
+        _ => 2,
+    };
+    (rank, format!("{}^{}", factor.0, factor.1))
+}
+
+impl Term {
+    fn constant(coefficient: Rational) -> Self {
+        Term{coefficient, factors: Vec::new()}
+    }
+
+    fn factor(base: Expr, exponent: Expr) -> Self {
+        let mut term = Self::constant(constant(1.0));
+        term.mul_factor(base, exponent);
+        term
+    }
+
+    // Multiplies in base^exponent, adding exponents of equal bases and folding exact constants.
+    // A base that may be a matrix only combines with the last such factor, A*B*A is not A^2*B.
+    fn mul_factor(&mut self, base: Expr, exponent: Expr) {
+        let position = match is_scalar(&base) {
+            true => self.factors.iter().position(|(b, _)| *b == base),
+            false => self.factors.iter().rposition(|(b, _)| !is_scalar(b)).filter(|&i| self.factors[i].0 == base),
+        };
+        let exponent = match position {
+            Some(i) => {
+                let (_, previous) = self.factors.remove(i);
+                Expr::binary(BinaryOp::Add, previous, exponent).simplify()
+            }
+            None => exponent,
+        };
+
+        match (constant_value(&base), constant_value(&exponent)) {
+            (_, Some(n)) if n == constant(0.0) => (),
+            (Some(b), Some(n)) if b.clone().pow(n.clone()).is_exact() && b != constant(0.0) => {
+                self.coefficient = self.coefficient.clone() * b.pow(n);
+            }
+            _ => {
+                self.factors.push((base, exponent));
+                // Stable, so the factors that may be matrices stay in order
+                self.factors.sort_by_key(|factor| is_scalar(&factor.0).then(|| factor_key(factor)));
+            }
+        }
+    }
+
+    fn mul(mut self, other: &Term) -> Self {
+        self.coefficient = self.coefficient * other.coefficient.clone();
+        for (base, exponent) in &other.factors {
+            self.mul_factor(base.clone(), exponent.clone());
+        }
+        self.tidy()
+    }
+
+    // Whether the factors all commute, at most one of them may be a matrix.
+    fn commutes(&self) -> bool {
+        self.factors.iter().filter(|(base, _)| !is_scalar(base)).count() <= 1
+    }
+
+    // Integer powers distribute over every factor, which needs them to commute.
+    fn pow(&self, n: &Rational) -> Self {
+        let mut term = Self::constant(self.coefficient.clone().pow(n.clone()));
+        let n = coefficient_expr(n);
+        for (base, exponent) in &self.factors {
+            term.mul_factor(base.clone(), Expr::binary(BinaryOp::Mul, exponent.clone(), n.clone()).simplify());
+        }
+        term.tidy()
+    }
+
+    fn neg(mut self) -> Self {
+        self.coefficient = -self.coefficient;
+        self
+    }
+
+    // sin(u)^n/cos(u)^n becomes tan(u)^n.
+    fn tidy(mut self) -> Self {
+        let tangent = self.factors.iter().enumerate().find_map(|(i, (base, exponent))| match base {
+            Expr::Call(name, args) if name == "sin" => {
+                let cos = (Expr::call("cos", args.clone()), Expr::unary(UnaryOp::Neg, exponent.clone()).simplify());
+                // Either side of a matrix factor has to stay where it is
+                let found = match is_scalar(base) {
+                    true => self.factors.contains(&cos),
+                    false => self.factors.get(i + 1) == Some(&cos),
+                };
+                found.then(|| (base.clone(), cos, args.clone(), exponent.clone()))
+            }
+            _ => None,
+        });
+
+        if let Some((sin, cos, args, exponent)) = tangent {
+            self.factors.retain(|factor| factor.0 != sin && *factor != cos);
+            self.mul_factor(Expr::call("tan", args), exponent);
+        }
+        self
+    }
+
+    fn degree(&self) -> f64 {
+        self.factors.iter()
+            .map(|(_, exponent)| constant_value(exponent).map_or(1.0, |n| n.to_f64()))
+            .sum()
+    }
+}
+
+
+fn atom(expr: Expr) -> Vec<Term> {
+    vec![Term::factor(expr, Expr::literal(1.0))]
+}
+
+fn negate(terms: Vec<Term>) -> Vec<Term> {
+    terms.into_iter().map(Term::neg).collect()
+}
+
+// Adds up like terms, drops the zero ones and puts the rest in canonical order.
+fn merge(terms: Vec<Term>) -> Vec<Term> {
+    let mut merged: Vec<Term> = Vec::new();
+    for term in terms {
+        match merged.iter_mut().find(|other| other.factors == term.factors) {
+            Some(other) => other.coefficient = other.coefficient.clone() + term.coefficient,
+            None => merged.push(term),
+        }
+    }
+    merged.retain(|term| term.coefficient != constant(0.0));
+
+    if let Some(merged) = pythagorean(&merged) {
+        return merge(merged);
+    }
+
+    merged.sort_by(|a, b| {
+        b.degree().partial_cmp(&a.degree())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| {
+                let key = |term: &Term| term.factors.iter().map(factor_key).collect::<Vec<(u8, String)>>();
+                key(a).cmp(&key(b))
+            })
+    });
+    merged
+}
+
+// c*sin(u)^2 + c*cos(u)^2 becomes c, also when both terms share other factors.
+fn pythagorean(terms: &[Term]) -> Option<Vec<Term>> {
+    let square = Expr::literal(2.0);
+    for (i, term) in terms.iter().enumerate() {
+        for (k, (base, exponent)) in term.factors.iter().enumerate() {
+            let Expr::Call(name, args) = base else { continue };
+            if name != "sin" || *exponent != square {
+                continue;
+            }
+
+            let mut rest = term.factors.clone();
+            rest.remove(k);
+            let mut cos = Term{coefficient: term.coefficient.clone(), factors: rest.clone()};
+            cos.mul_factor(Expr::call("cos", args.clone()), square.clone());
+
+            if let Some(j) = terms.iter().position(|other| other.coefficient == cos.coefficient && other.factors == cos.factors) {
+                let mut result: Vec<Term> = terms.iter()
+                    .enumerate()
+                    .filter(|(n, _)| *n != i && *n != j)
+                    .map(|(_, term)| term.clone())
+                    .collect();
+                result.push(Term{coefficient: term.coefficient.clone(), factors: rest});
+                return Some(result);
+            }
+        }
+    }
+    None
+}
+
+fn multiply(lhs: Vec<Term>, rhs: Vec<Term>) -> Vec<Term> {
+    // Distribute when either side is a single term, a product of two sums is kept factored
+    if lhs.len() <= 1 || rhs.len() <= 1 {
+        let terms = lhs.iter()
+            .flat_map(|a| rhs.iter().map(|b| a.clone().mul(b)))
+            .collect();
+        return merge(terms);
+    }
+    let product = Term::factor(rebuild(&lhs), Expr::literal(1.0)).mul(&Term::factor(rebuild(&rhs), Expr::literal(1.0)));
+    vec![product]
+}
+
+fn divide(lhs: Vec<Term>, rhs: Vec<Term>) -> Vec<Term> {
+    match rhs.as_slice() {
+        [term] if term.commutes() => multiply(lhs, vec![term.pow(&constant(-1.0))]),
+        _ => multiply(lhs, vec![Term::factor(rebuild(&rhs), Expr::literal(-1.0))]),
+    }
+}
+
+fn power(base: Vec<Term>, exponent: Expr) -> Vec<Term> {
+    let n = constant_value(&exponent);
+    match (base.as_slice(), n) {
+        (_, Some(n)) if n == constant(0.0) => vec![Term::constant(constant(1.0))],
+        (_, Some(n)) if n == constant(1.0) => base,
+        (_, Some(n)) if base.is_empty() && n > constant(0.0) => Vec::new(),
+        // The coefficient's power is only folded when exact, so 2^0.5 and powers too large to hold are kept rather than folding to inf or 0
+        ([term], Some(n)) if !term.coefficient.clone().pow(n.clone()).is_exact() => vec![Term::factor(rebuild(&base), exponent)],
+        ([term], Some(n)) if is_integer(&n) && term.commutes() => vec![term.pow(&n)],
+        ([term], Some(n)) if term.factors.is_empty() => vec![Term::constant(term.coefficient.clone().pow(n))],
+        // x^a^b only combines into x^(a*b) when the inner exponent is 1, (x^2)^0.5 is |x|
+        ([term], _) if term.coefficient == constant(1.0) && term.factors.len() == 1 && term.factors[0].1 == Expr::literal(1.0) => {
+            vec![Term::factor(term.factors[0].0.clone(), exponent)]
+        }
+        _ => vec![Term::factor(rebuild(&base), exponent)],
+    }
+}
+
+// Returns -u when u simplifies to a single negative term.
+fn negative(expr: &Expr) -> Option<Expr> {
+    match flatten(expr).as_slice() {
+        [term] if term.coefficient < constant(0.0) => Some(rebuild(&[term.clone().neg()])),
+        _ => None,
+    }
+}
+
+// Trig and log identities, applied to calls whose arguments are already simplified.
+fn call(name: &str, args: Vec<Expr>) -> Expr {
+    let is = |expr: &Expr, value: f64| constant_value(expr) == Some(constant(value));
+
+    match (name, args.as_slice()) {
+        ("sin" | "tan" | "sqrt", [x]) if is(x, 0.0) => Expr::literal(0.0),
+        ("cos" | "exp", [x]) if is(x, 0.0) => Expr::literal(1.0),
+        ("ln" | "log", [x]) | ("log", [_, x]) if is(x, 1.0) => Expr::literal(0.0),
+        ("log", [base, x]) if base == x => Expr::literal(1.0),
+        ("ln", [Expr::Call(inner, u)]) if inner == "exp" && u.len() == 1 => u[0].clone(),
+        ("exp", [Expr::Call(inner, u)]) if inner == "ln" && u.len() == 1 => u[0].clone(),
+        ("sqrt", [Expr::Binary(BinaryOp::Pow, u, exponent)]) if is(exponent, 2.0) => Expr::call("abs", vec![*u.clone()]),
+        ("sqrt", [x]) if constant_value(x).is_some_and(|x| x.clone().sqrt().is_exact()) => {
+            coefficient_expr(&constant_value(x).unwrap().sqrt())
+        }
+        // Odd functions take the sign out, even ones drop it
+        ("sin" | "tan", [x]) if negative(x).is_some() => {
+            Expr::unary(UnaryOp::Neg, Expr::call(name, vec![negative(x).unwrap()]))
+        }
+        ("cos" | "abs", [x]) if negative(x).is_some() => Expr::call(name, vec![negative(x).unwrap()]),
+        _ => Expr::call(name, args),
+    }
+}
+
+// Breaks an expression into simplified terms.
+fn flatten(expr: &Expr) -> Vec<Term> {
+    match expr {
+        Expr::Literal(x) => merge(vec![Term::constant(constant(*x))]),
+        Expr::Integer(x) => merge(vec![Term::constant(Rational::Exact(BigRational::from_integer(x.clone())))]),
+        Expr::Decimal(text) => merge(vec![Term::constant(Rational::from_literal(text))]),
+        Expr::Var(_) => atom(expr.clone()),
+        Expr::Unary(UnaryOp::Neg, operand) => negate(flatten(operand)),
+        Expr::Unary(UnaryOp::Not, operand) => atom(Expr::unary(UnaryOp::Not, operand.simplify())),
+        Expr::Postfix(PostfixOp::Percent, operand) => multiply(flatten(operand), vec![Term::constant(constant(1.0) / constant(100.0))]),
+        Expr::Postfix(PostfixOp::Factorial, operand) => atom(Expr::postfix(PostfixOp::Factorial, operand.simplify())),
+        Expr::Binary(op, lhs, rhs) => match op {
+            BinaryOp::Add => merge([flatten(lhs), flatten(rhs)].concat()),
+            BinaryOp::Sub => merge([flatten(lhs), negate(flatten(rhs))].concat()),
+            BinaryOp::Mul => multiply(flatten(lhs), flatten(rhs)),
+            BinaryOp::Div => divide(flatten(lhs), flatten(rhs)),
+            BinaryOp::Pow => power(flatten(lhs), rhs.simplify()),
+            _ => atom(Expr::binary(op.clone(), lhs.simplify(), rhs.simplify())),
+        },
+        Expr::Call(name, args) => match call(name, args.iter().map(Expr::simplify).collect()) {
+            // A square root is a power of 1/2, so that sqrt(x)*sqrt(x) can combine into x
+            Expr::Call(name, args) if name == "sqrt" && args.len() == 1 => {
+                power(flatten(&args[0]), coefficient_expr(&(constant(1.0) / constant(2.0))))
+            }
+            call @ Expr::Call(..) => atom(call),
+            other => flatten(&other),
+        },
+        Expr::List(items) => atom(Expr::list(items.iter().map(Expr::simplify).collect())),
+        Expr::Index(target, indices) => atom(Expr::index(target.simplify(), indices.iter().map(Expr::simplify).collect())),
+        Expr::Piecewise(clauses, otherwise) => atom(map_clauses(clauses, otherwise, Expr::simplify, Expr::simplify)),
+        Expr::Assign(name, value) => atom(Expr::assign(name, value.simplify())),
+        Expr::Define(name, params, body) => atom(Expr::Define(name.clone(), params.clone(), Box::new(body.simplify()))),
+        Expr::At(_, expr) => flatten(expr),
+    }
+}
+
+
+// Integers print as literals, other fractions as "p/q". Integers too large for an f64 stay exact.
+fn coefficient_expr(x: &Rational) -> Expr {
+    match x {
+        Rational::Exact(x) if !x.is_integer() => Expr::binary(
+            BinaryOp::Div,
+            Expr::integer(x.numer().clone()),
+            Expr::integer(x.denom().clone()),
+        ),
+        Rational::Exact(x) => Expr::integer(x.to_integer()),
+        x => Expr::literal(x.to_f64()),
+    }
+}
+
+fn product_of(factors: Vec<Expr>) -> Option<Expr> {
+    factors.into_iter().reduce(|product, factor| Expr::binary(BinaryOp::Mul, product, factor))
+}
+
+fn term_expr(term: &Term) -> Expr {
+    let negative = term.coefficient < constant(0.0);
+    let magnitude = term.coefficient.clone().abs();
+
+    let mut numerator = Vec::new();
+    let mut denominator = Vec::new();
+    match (&magnitude, coefficient_expr(&magnitude)) {
+        (_, Expr::Binary(BinaryOp::Div, p, q)) => {
+            if *p != Expr::literal(1.0) {
+                numerator.push(*p);
+            }
+            denominator.push(*q);
+        }
+        (m, _) if *m == constant(1.0) => (),
+        (_, m) => numerator.push(m),
+    }
+
+    for (base, exponent) in &term.factors {
+        let power = |exponent: Expr| match exponent {
+            Expr::Literal(1.0) => base.clone(),
+            exponent if constant_value(&exponent) == Some(constant(0.5)) => Expr::call("sqrt", vec![base.clone()]),
+            exponent => Expr::binary(BinaryOp::Pow, base.clone(), exponent),
+        };
+        match constant_value(exponent) {
+            Some(n) if n < constant(0.0) => denominator.push(power(coefficient_expr(&-n))),
+            _ => numerator.push(power(exponent.clone())),
+        }
+    }
+
+    // Only numbers that are not the whole term are kept in front, "-2*x" but "-(1/x)" stays "-1/x"
+    let numerator = match (negative, numerator.first()) {
+        (true, Some(Expr::Literal(x))) => {
+            numerator[0] = Expr::literal(-x);
+            product_of(numerator).unwrap()
+        }
+        (true, _) => match product_of(numerator) {
+            Some(product) => Expr::unary(UnaryOp::Neg, product),
+            None => Expr::literal(-1.0),
+        },
+        (false, _) => product_of(numerator).unwrap_or(Expr::literal(1.0)),
+    };
+
+    match product_of(denominator) {
+        Some(denominator) => Expr::binary(BinaryOp::Div, numerator, denominator),
+        None => numerator,
+    }
+}
+
+fn rebuild(terms: &[Term]) -> Expr {
+    let Some((first, rest)) = terms.split_first() else {
+        return Expr::literal(0.0);
+    };
+
+    rest.iter().fold(term_expr(first), |sum, term| match term.coefficient < constant(0.0) {
+        true => Expr::binary(BinaryOp::Sub, sum, term_expr(&term.clone().neg())),
+        false => Expr::binary(BinaryOp::Add, sum, term_expr(term)),
+    })
+}
+
+
+impl Expr {
+    // Canonical form: like terms merged, powers of equal bases combined, constants folded exactly.
+    pub fn simplify(&self) -> Expr {
+        rebuild(&flatten(self))
+    }
+
+    // Simplifying recurses into every operand, long sums included, so deeper expressions are refused.
+    pub fn try_simplify(&self) -> Result<Expr, EvalError> {
+        match self.height() > MAX_DEPTH {
+            true => Err(EvalError::TooDeep("simplify".to_string())),
+            false => Ok(self.simplify()),
+        }
+    }
+}
+
+
+#[test]
+fn test_simplify_0() {
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    let simplify = |input: &str| parse(tokenize(input).unwrap()).unwrap().simplify().to_string();
+    let cases = [
+        ("x*1 + 0", "x"),
+        ("x - x", "0"),
+        ("2*x + 3*x + 2*y - y", "5*x + y"),
+        ("x*x^2/x", "x^2"),
+        ("2*3 + 1/4 + 1/3", "79/12"),
+        ("x/3 + 0.1*x", "13*x/30"),
+        ("(x + 1)*(x + 1) - 2*x", "(x + 1)^2 - 2*x"),
+        ("x*(x - 1) + x", "x^2"),
+        ("(x^2)^3*y^-1", "x^6/y"),
+        ("-(-y)", "y"),
+        ("(2*x)^3", "8*x^3"),
+        ("(2*x)^1000000", "(2*x)^1000000"),
+        ("2^-1000000*y", "y/2^1000000"),
+    ];
+    for (input, expected) in cases {
+        assert!(simplify(input) == expected, "{input} simplified to {}, expected {expected}", simplify(input));
+    }
+}
+
+#[test]
+fn test_simplify_1() {
+    use crate::{parser::parse, tokenizer::tokenize};
+
+    let simplify = |input: &str| parse(tokenize(input).unwrap()).unwrap().simplify().to_string();
+    let cases = [
+        ("sin(x)^2 + cos(x)^2", "1"),
+        ("3*y*sin(2*x)^2 + 3*y*cos(2*x)^2", "3*y"),
+        ("sin(x)/cos(x)", "tan(x)"),
+        ("ln(exp(x + 1))", "x + 1"),
+        ("exp(ln(2*x))*cos(0)", "2*x"),
+        ("sin(-x) + cos(-x)", "cos(x) - sin(x)"),
+        ("log(7, 7) + ln(1) + sqrt(9/4)", "5/2"),
+        ("sqrt(x^2)", "abs(x)"),
+        ("sqrt(x)*sqrt(x)/(2*sqrt(x))", "sqrt(x)/2"),
+    ];
+    for (input, expected) in cases {
+        assert!(simplify(input) == expected, "{input} simplified to {}, expected {expected}", simplify(input));
+    }
+}
+
+#[test]
+fn test_simplify_2() {
+    use crate::{engine::Engine, evaluator::EvalOutput, parser::parse, tokenizer::tokenize};
+
+    // Anything with a variable may be a matrix, so products keep their order
+    let simplify = |input: &str| parse(tokenize(input).unwrap()).unwrap().simplify().to_string();
+    let cases = [
+        ("a*b - b*a", "a*b - b*a"),
+        ("x*y*x", "x*y*x"),
+        ("x*x*y*3*y", "3*x^2*y^2"),
+        ("(a*b)^2/(a*b)", "a*b"),
+        ("(x*y)^-1", "1/(x*y)"),
+        ("2*y*sqrt(2)*y", "2*y^2*sqrt(2)"),
+    ];
+    for (input, expected) in cases {
+        assert!(simplify(input) == expected, "{input} simplified to {}, expected {expected}", simplify(input));
+    }
+
+    let mut engine: Engine = Engine::new();
+    engine.eval("A = [[1, 2], [3, 4]]").unwrap();
+    engine.eval("B = [[0, 1], [1, 0]]").unwrap();
+    let Ok(EvalOutput::Expression(simplified)) = engine.eval("simplify(A*B - B*A)") else { panic!("Expected an expression") };
+    assert!(engine.value(&simplified.to_string()).unwrap().to_string() == "[[-1, -3], [3, 1]]");
+}
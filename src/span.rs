@@ -0,0 +1,76 @@
+use std::fmt::Display;
+
+use crate::tokens::Token;
+
+
+// Range of character columns in the input line, the end is exclusive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span{start, end}
+    }
+
+    // Smallest span covering both.
+    pub fn to(self, other: Span) -> Self {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    // Marks the span under the input line, e.g. "    ^~~".
+    pub fn underline(&self) -> String {
+        let width = self.end.saturating_sub(self.start).max(1);
+        format!("{}^{}", " ".repeat(self.start), "~".repeat(width - 1))
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Span) -> Self {
+        Spanned{value, span}
+    }
+}
+
+// Tokens built by hand have no position in any input.
+impl From<Token> for Spanned<Token> {
+    fn from(value: Token) -> Self {
+        Spanned::new(value, Span::default())
+    }
+}
+
+// Lets tokenizer output be compared against plain tokens.
+impl PartialEq<Token> for Spanned<Token> {
+    fn eq(&self, other: &Token) -> bool {
+        self.value == *other
+    }
+}
+
+impl<T: Display> Display for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+
+#[test]
+fn test_span_0() {
+    let span = Span::new(4, 7).to(Span::new(2, 3));
+    assert!(span == Span::new(2, 7));
+    assert!(span.underline() == "  ^~~~~", "Got \"{}\"", span.underline());
+    assert!(Span::new(3, 3).underline() == "   ^");
+}
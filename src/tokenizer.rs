@@ -1,33 +1,60 @@
 use std::{fmt::Display, str::Chars};
-use crate::tokens::{BinaryOp, Function, Glyph, Token, UnaryOp, Value};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+use crate::{number::literal_parts, span::{Span, Spanned}, tokens::{exact_f64, BinaryOp, Function, Glyph, PostfixOp, Token, UnaryOp, Value}};
 
 macro_rules! symbols {
     () => {
         '+' | '-' | '*' | '/' | '^' |
-        '(' | ')' | ',' | '=' 
+        '(' | ')' | '[' | ']' | ',' | '=' |
+        '<' | '>' | '!' | '&' | '|' |
+        '{' | '}' | ';' | '%'
     };
 }
 
 
 #[derive(Debug)]
 pub enum TokenizerError {
-    EmptyToken,
-    IncorrectCharacter(String),
+    EmptyToken(Span),
+    IncorrectCharacter(String, Span),
+    InvalidNumber(String, Span),
+}
+
+impl TokenizerError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::EmptyToken(_) => "E0001",
+            Self::IncorrectCharacter(..) => "E0002",
+            Self::InvalidNumber(..) => "E0003",
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::EmptyToken(span) | Self::IncorrectCharacter(_, span) | Self::InvalidNumber(_, span) => *span,
+        }
+    }
 }
 
 impl Display for TokenizerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "TokenizerError -> ")?;
         match self {
-            Self::EmptyToken => write!(f, "Empty Token"),
-            Self::IncorrectCharacter(c) => write!(f, "Incorrect Character: \"{c}\""),
+            Self::EmptyToken(_) => write!(f, "Empty Token"),
+            Self::IncorrectCharacter(c, _) => write!(f, "Incorrect Character: \"{c}\""),
+            Self::InvalidNumber(number, _) => write!(f, "Invalid Number: \"{number}\""),
         }
     }
 }
 
+impl std::error::Error for TokenizerError {}
+
 
 pub struct LexingReader<'a> {
     iterator: Chars<'a>,
+    position: usize,
     prev_char: Option<char>, 
     current_char: Option<char>,
     next_char: Option<char>,
@@ -40,7 +67,12 @@ impl <'a> LexingReader<'a> {
         let current_char = iterator.next();
         let next_char = iterator.next();
 
-        Self {iterator, prev_char, current_char, next_char}
+        Self {iterator, position: 0, prev_char, current_char, next_char}
+    }
+
+    // Column of the current character.
+    pub fn position(&self) -> usize {
+        self.position
     }
 
     pub fn prev_char(&self) -> Option<char> {
@@ -55,8 +87,19 @@ impl <'a> LexingReader<'a> {
         self.next_char
     }
 
+    // One more character of lookahead, for "2e-3" against "2e - 3".
+    pub fn second_next_char(&self) -> Option<char> {
+        self.iterator.clone().next()
+    }
+
+    // Spaces are skipped over, so "[1, -2]" still sees the "," before the "-"
     pub fn advance(&mut self) {
-        self.prev_char = self.current_char;
+        if self.current_char != Some(' ') {
+            self.prev_char = self.current_char;
+        }
+        if self.current_char.is_some() {
+            self.position += 1;
+        }
         self.current_char = self.next_char;
         self.next_char = self.iterator.next();
     }
@@ -78,24 +121,57 @@ impl <'a> Lexer<'a> for SymbolLexer {
     fn read_token(self, reader: &mut LexingReader) -> Result<Token, TokenizerError> {
         assert!(!reader.finished(), "Cannot tokenize: empty reader");
 
-        let c = reader.current_char().ok_or(TokenizerError::EmptyToken)?;
+        let span = Span::new(reader.position(), reader.position() + 1);
+        let c = reader.current_char().ok_or(TokenizerError::EmptyToken(span))?;
+        // Two character operators, the second character is consumed along with the first
+        let pair = |second: char, token: Token| match reader.next_char() == Some(second) {
+            true => Some(token),
+            false => None,
+        };
+        if let Some(token) = match c {
+            '<' => pair('=', BinaryOp::Le.into()),
+            '>' => pair('=', BinaryOp::Ge.into()),
+            '=' => pair('=', BinaryOp::Eq.into()),
+            '!' => pair('=', BinaryOp::Ne.into()),
+            '&' => pair('&', BinaryOp::And.into()),
+            '|' => pair('|', BinaryOp::Or.into()),
+            _ => None,
+        } {
+            reader.advance();
+            reader.advance();
+            return Ok(token);
+        }
+
         let result = match c {
             '+' => Ok(BinaryOp::Add.into()),
             '-' => {
                 // This is done to differentiate between binary "-" and unary "-"
                 match reader.prev_char {
-                    None | Some( '(' | '+' | '-' | '*' | '/' | '^' | ',' ) => Ok(UnaryOp::Neg.into()),
+                    None | Some( '(' | '[' | '{' | ';' | '+' | '-' | '*' | '/' | '%' | '^' | ',' | '=' | '<' | '>' | '!' | '&' | '|' ) => {
+                        Ok(UnaryOp::Neg.into())
+                    }
                     _ => Ok(BinaryOp::Sub.into()),
                 }
             }
             '*' => Ok(BinaryOp::Mul.into()),
             '/' => Ok(BinaryOp::Div.into()),
+            // Breaking change: "%" was the remainder before it became a percentage. "7 % 3" is now an error like "7 3"
+            // and "7 % (3)" is 7% times 3, the remainder is written "7 mod 3" or "mod(7, 3)".
+            '%' => Ok(PostfixOp::Percent.into()),
             '^' => Ok(BinaryOp::Pow.into()),
+            '<' => Ok(BinaryOp::Lt.into()),
+            '>' => Ok(BinaryOp::Gt.into()),
+            '!' => Ok(UnaryOp::Not.into()),
             '=' => Ok(Function::Assign.into()),
             '(' => Ok(Glyph::LBracket.into()),
             ')' => Ok(Glyph::RBracket.into()),
+            '[' => Ok(Glyph::LSquare.into()),
+            ']' => Ok(Glyph::RSquare.into()),
             ',' => Ok(Glyph::Comma.into()),
-            _ => Err(TokenizerError::IncorrectCharacter(String::from(c)))
+            '{' => Ok(Glyph::LBrace.into()),
+            '}' => Ok(Glyph::RBrace.into()),
+            ';' => Ok(Glyph::Semicolon.into()),
+            _ => Err(TokenizerError::IncorrectCharacter(String::from(c), span))
         };
         
         reader.advance();
@@ -107,33 +183,124 @@ impl <'a> Lexer<'a> for SymbolLexer {
 struct NumberLexer;
 
 impl <'a> Lexer<'a> for NumberLexer {
-    fn read_token(self, reader: &mut LexingReader) -> Result<Token, TokenizerError> {       
+    fn read_token(self, reader: &mut LexingReader) -> Result<Token, TokenizerError> {
         assert!(!reader.finished(), "Cannot tokenize: empty reader");
-        
+
+        let start = reader.position();
+        let radix = match (reader.current_char(), reader.next_char()) {
+            (Some('0'), Some('x' | 'X')) => Some(16),
+            (Some('0'), Some('o' | 'O')) => Some(8),
+            (Some('0'), Some('b' | 'B')) => Some(2),
+            _ => None,
+        };
+
         let mut buffer = String::new();
-        let mut has_dot = false; 
-        while let Some(c) = reader.current_char() {
-            if c.is_numeric() {
-                buffer.push(c);
-                reader.advance();
-            }
-            else if c == '.' {
-                if has_dot {
-                    return Ok(Value::Scalar(buffer.parse().unwrap()).into());
+        match radix {
+            // Everything alphanumeric belongs to the literal, so "0b102" is an error rather than "0b10" and "2"
+            Some(_) => {
+                let mut take = |reader: &mut LexingReader| {
+                    buffer.extend(reader.current_char());
+                    reader.advance();
+                };
+                take(reader);
+                take(reader);
+                while reader.current_char().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    take(reader);
                 }
-                else {
+            }
+            None => {
+                while let Some(c) = reader.current_char().filter(|c| c.is_ascii_digit() || *c == '_' || *c == '.') {
                     buffer.push(c);
-                    has_dot = true;
                     reader.advance();
                 }
-                
+                // An exponent needs digits after it, so "2e" and "2exp(x)" stay products with "e" and "exp"
+                let sign = matches!(reader.next_char(), Some('+' | '-'));
+                let exponent_digit = match sign {
+                    true => reader.second_next_char(),
+                    false => reader.next_char(),
+                };
+                if matches!(reader.current_char(), Some('e' | 'E')) && exponent_digit.is_some_and(|c| c.is_ascii_digit()) {
+                    for _ in 0..if sign { 2 } else { 1 } {
+                        buffer.extend(reader.current_char());
+                        reader.advance();
+                    }
+                    while let Some(c) = reader.current_char().filter(|c| c.is_ascii_digit() || *c == '_') {
+                        buffer.push(c);
+                        reader.advance();
+                    }
+                }
             }
-            else {
-                return Ok(Value::Scalar(buffer.parse().unwrap()).into());
-            };
         }
 
-        Ok(Value::Scalar(buffer.parse().unwrap()).into())
+        let span = Span::new(start, reader.position());
+        number(&buffer, radix).ok_or(TokenizerError::InvalidNumber(buffer, span))
+    }
+}
+
+
+// Separators go between two digits, "1_000" but not "1__000", "_1" or "1_".
+fn separated(digits: &str, is_digit: impl Fn(char) -> bool) -> bool {
+    let chars: Vec<char> = digits.chars().collect();
+    !chars.is_empty() && chars.iter().enumerate().all(|(i, &c)| match c {
+        '_' => i > 0 && i + 1 < chars.len() && is_digit(chars[i - 1]) && is_digit(chars[i + 1]),
+        c => is_digit(c),
+    })
+}
+
+// Converts the text of a literal, None when it is malformed. Prefixed literals are always whole numbers.
+fn number(buffer: &str, radix: Option<u32>) -> Option<Token> {
+    if let Some(radix) = radix {
+        let digits = &buffer[2..];
+        if !separated(digits, |c| c.is_digit(radix)) {
+            return None;
+        }
+        return BigInt::parse_bytes(digits.replace('_', "").as_bytes(), radix).map(integer);
+    }
+
+    let (mantissa, exponent) = match buffer.find(['e', 'E']) {
+        Some(i) => (&buffer[..i], Some(buffer[i + 1..].trim_start_matches(['+', '-']))),
+        None => (buffer, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+    // Either side of the dot may be left out, "5." and ".5", but not both
+    let valid = (separated(whole, |c| c.is_ascii_digit()) || whole.is_empty() && fraction.is_some_and(|f| !f.is_empty()))
+        && fraction.is_none_or(|fraction| fraction.is_empty() || separated(fraction, |c| c.is_ascii_digit()))
+        && exponent.is_none_or(|exponent| separated(exponent, |c| c.is_ascii_digit()));
+    if !valid {
+        return None;
+    }
+
+    let text = buffer.replace('_', "");
+    match fraction.is_none() && exponent.is_none() {
+        true => text.parse().ok().map(integer),
+        false => text.parse().ok().map(|x| decimal(x, text)),
+    }
+}
+
+// Decimal literals an f64 holds exactly are scalars, "0.1" and the like keep their digits for the exact number types.
+fn decimal(x: f64, text: String) -> Token {
+    let exact = literal_parts(&text).is_some_and(|(mantissa, exponent)| {
+        // Past these exponents an f64 is either out of range or holds no exact decimal
+        (-1100..=310).contains(&exponent) && BigRational::from_float(x).is_some_and(|x| {
+            let power = BigRational::from_integer(num_traits::pow(BigInt::from(10), exponent.unsigned_abs() as usize));
+            let value = BigRational::from_integer(mantissa);
+            x == if exponent < 0 { value / power } else { value * power }
+        })
+    });
+    match exact {
+        true => Value::Scalar(x).into(),
+        false => Value::Decimal(text).into(),
+    }
+}
+
+// Whole numbers that an f64 holds exactly are scalars, larger ones are kept as integers.
+fn integer(x: BigInt) -> Token {
+    match exact_f64(&x) {
+        Some(x) => Value::Scalar(x).into(),
+        None => Value::Integer(x).into(),
     }
 }
 
@@ -146,7 +313,8 @@ impl <'a> Lexer<'a> for CharacterLexer {
         
         let mut buffer = String::new();
         while let Some(c) = reader.current_char() {
-            if c.is_alphabetic() {
+            // Names start with a letter, but may contain digits after it, e.g. "atan2"
+            if c.is_alphanumeric() {
                 buffer.push(c);
                 reader.advance();
             }
@@ -154,27 +322,68 @@ impl <'a> Lexer<'a> for CharacterLexer {
                 return Ok(Function::NamedFunc(buffer).into());
             }
             else {
-                return Ok(Value::Var(buffer).into());
+                return Ok(word(buffer));
             }
         };
 
-        Ok(Value::Var(buffer).into())
+        Ok(word(buffer))
+    }
+}
+
+// "if", "otherwise", "mod" and "div" are keywords, unless called as a function, "if(c, a, b)" or "mod(a, b)".
+fn word(name: String) -> Token {
+    match name.as_str() {
+        "mod" => BinaryOp::Mod.into(),
+        "div" => BinaryOp::FloorDiv.into(),
+        "if" => Glyph::If.into(),
+        "otherwise" => Glyph::Otherwise.into(),
+        _ => Value::Var(name).into(),
+    }
+}
+
+// The reader only sees the letter before a "-", so "a mod -b", "a div -b" and "{ 1 if -x < 0 }" are fixed up here.
+fn sign_after_keyword(token: Token, prev: Option<&Spanned<Token>>) -> Token {
+    let keyword = prev.is_some_and(|prev| {
+        matches!(prev.value, Token::Func(Function::BinaryOp(BinaryOp::Mod | BinaryOp::FloorDiv)) | Token::Glyph(Glyph::If))
+    });
+    match token {
+        Token::Func(Function::BinaryOp(BinaryOp::Sub)) if keyword => UnaryOp::Neg.into(),
+        token => token,
+    }
+}
+
+// "!" after an operand is a factorial, "%" is always a percentage and the remainder is written "mod".
+// The minus in "3! - 1" and "20%-5" was lexed as a sign after the symbol, it is turned back into a subtraction here.
+fn resolve_postfix(tokens: &mut [Spanned<Token>]) {
+    for i in 0..tokens.len() {
+        let after_operand = i > 0 && matches!(tokens[i - 1].value,
+            Token::Val(_) | Token::Func(Function::PostfixOp(_)) |
+            Token::Glyph(Glyph::RBracket) | Token::Glyph(Glyph::RSquare) | Token::Glyph(Glyph::RBrace)
+        );
+
+        let token = &mut tokens[i].value;
+        match token {
+            Token::Func(Function::UnaryOp(UnaryOp::Not)) if after_operand => *token = PostfixOp::Factorial.into(),
+            Token::Func(Function::UnaryOp(UnaryOp::Neg)) if after_operand => *token = BinaryOp::Sub.into(),
+            _ => (),
+        }
     }
 }
 
 
-pub fn tokenize<'a>(s: &'a str) -> Result<Vec<Token>, TokenizerError> { 
+pub fn tokenize(s: &str) -> Result<Vec<Spanned<Token>>, TokenizerError> { 
     let mut reader = LexingReader::new(s);
     let mut tokens = Vec::with_capacity(s.len() + 2);
-    tokens.push(Token::Start);
+    tokens.push(Spanned::new(Token::Start, Span::new(0, 0)));
 
     while let Some(c) = reader.current_char() {
         let token: Token;
+        let start = reader.position();
         
         if let symbols!() = c {
             token = SymbolLexer.read_token(&mut reader)?;
         }
-        else if c.is_numeric() {
+        else if c.is_ascii_digit() || c == '.' {
             token = NumberLexer.read_token(&mut reader)?;
         }
         else if c.is_alphabetic() {
@@ -185,27 +394,32 @@ pub fn tokenize<'a>(s: &'a str) -> Result<Vec<Token>, TokenizerError> {
             continue;
         }
         else {
-            return Err(TokenizerError::IncorrectCharacter(String::from(c)));
+            return Err(TokenizerError::IncorrectCharacter(String::from(c), Span::new(start, start + 1)));
         }
 
-        tokens.push(token);
+        let token = sign_after_keyword(token, tokens.last());
+        tokens.push(Spanned::new(token, Span::new(start, reader.position())));
     };
     
-    tokens.push(Token::End);
+    // End sits one column past the input, so errors about a missing operand point after it
+    let end = reader.position();
+    tokens.push(Spanned::new(Token::End, Span::new(end, end + 1)));
+    resolve_postfix(&mut tokens);
     Ok(tokens)
 }
 
-pub fn tokenize_unpadded<'a>(s: &'a str) -> Result<Vec<Token>, TokenizerError> { 
+pub fn tokenize_unpadded(s: &str) -> Result<Vec<Spanned<Token>>, TokenizerError> { 
     let mut reader = LexingReader::new(s);
     let mut tokens = Vec::with_capacity(s.len() + 2);
 
     while let Some(c) = reader.current_char() {
         let token: Token;
+        let start = reader.position();
         
         if let symbols!() = c {
             token = SymbolLexer.read_token(&mut reader)?;
         }
-        else if c.is_numeric() {
+        else if c.is_ascii_digit() || c == '.' {
             token = NumberLexer.read_token(&mut reader)?;
         }
         else if c.is_alphabetic() {
@@ -216,12 +430,14 @@ pub fn tokenize_unpadded<'a>(s: &'a str) -> Result<Vec<Token>, TokenizerError> {
             continue;
         }
         else {
-            return Err(TokenizerError::IncorrectCharacter(String::from(c)));
+            return Err(TokenizerError::IncorrectCharacter(String::from(c), Span::new(start, start + 1)));
         }
 
-        tokens.push(token);
+        let token = sign_after_keyword(token, tokens.last());
+        tokens.push(Spanned::new(token, Span::new(start, reader.position())));
     };
     
+    resolve_postfix(&mut tokens);
     Ok(tokens)
 }
 
@@ -249,4 +465,152 @@ fn test_tokenzie_1() {
         Token::End,
     ];
     assert!(tokenize(input).unwrap() == output, ",+3 *sin(x) Failed");
-}
\ No newline at end of file
+}
+#[test]
+fn test_tokenize_2() {
+    let input = "atan2(y, x2)";
+    let output: Vec<Token> = vec![
+        Token::Start,
+        Function::NamedFunc("atan2".to_string()).into(),
+        Glyph::LBracket.into(),
+        Value::Var("y".to_string()).into(),
+        Glyph::Comma.into(),
+        Value::Var("x2".to_string()).into(),
+        Glyph::RBracket.into(),
+        Token::End,
+    ];
+    assert!(tokenize(input).unwrap() == output, "atan2(y, x2) Failed");
+}
+
+#[test]
+fn test_tokenize_3() {
+    let spans: Vec<Span> = tokenize("sin(x1) + 2.5").unwrap().iter().map(|t| t.span).collect();
+    let expected = [(0, 0), (0, 3), (3, 4), (4, 6), (6, 7), (8, 9), (10, 13), (13, 14)];
+    assert!(spans == expected.map(|(start, end)| Span::new(start, end)), "Got {spans:?}");
+
+    assert!(matches!(tokenize("2 + $"), Err(TokenizerError::IncorrectCharacter(_, span)) if span == Span::new(4, 5)));
+}
+
+#[test]
+fn test_tokenize_4() {
+    let input = "x<=-1 || !y != z";
+    let output: Vec<Token> = vec![
+        Token::Start,
+        Value::Var("x".to_string()).into(),
+        BinaryOp::Le.into(),
+        UnaryOp::Neg.into(),
+        Value::Scalar(1.0).into(),
+        BinaryOp::Or.into(),
+        UnaryOp::Not.into(),
+        Value::Var("y".to_string()).into(),
+        BinaryOp::Ne.into(),
+        Value::Var("z".to_string()).into(),
+        Token::End,
+    ];
+    assert!(tokenize(input).unwrap() == output, "Got {:?}", tokenize(input));
+    assert!(matches!(tokenize("a & b"), Err(TokenizerError::IncorrectCharacter(_, span)) if span == Span::new(2, 3)));
+}
+
+#[test]
+fn test_tokenize_5() {
+    let input = "{x if(a, b, c);-1 otherwise}";
+    let output: Vec<Token> = vec![
+        Token::Start,
+        Glyph::LBrace.into(),
+        Value::Var("x".to_string()).into(),
+        Function::NamedFunc("if".to_string()).into(),
+        Glyph::LBracket.into(),
+        Value::Var("a".to_string()).into(),
+        Glyph::Comma.into(),
+        Value::Var("b".to_string()).into(),
+        Glyph::Comma.into(),
+        Value::Var("c".to_string()).into(),
+        Glyph::RBracket.into(),
+        Glyph::Semicolon.into(),
+        UnaryOp::Neg.into(),
+        Value::Scalar(1.0).into(),
+        Glyph::Otherwise.into(),
+        Glyph::RBrace.into(),
+        Token::End,
+    ];
+    assert!(tokenize(input).unwrap() == output, "Got {:?}", tokenize(input));
+    assert!(tokenize("x if y").unwrap()[2].value == Glyph::If.into());
+}
+
+#[test]
+fn test_tokenize_6() {
+    let input = "a%-3 mod 9007199254740993";
+    let output: Vec<Token> = vec![
+        Token::Start,
+        Value::Var("a".to_string()).into(),
+        PostfixOp::Percent.into(),
+        BinaryOp::Sub.into(),
+        Value::Scalar(3.0).into(),
+        BinaryOp::Mod.into(),
+        Value::Integer("9007199254740993".parse().unwrap()).into(),
+        Token::End,
+    ];
+    assert!(tokenize(input).unwrap() == output, "Got {:?}", tokenize(input));
+    // Below 2^53 every whole number is exact as an f64
+    assert!(tokenize("9007199254740991").unwrap()[1].value == Value::Scalar(9007199254740991.0).into());
+    assert!(tokenize("x if -1").unwrap()[3].value == UnaryOp::Neg.into());
+    assert!(tokenize("modulo").unwrap()[1].value == Value::Var("modulo".to_string()).into());
+    assert!(tokenize("7 div -2").unwrap()[2].value == BinaryOp::FloorDiv.into() && tokenize("7 div -2").unwrap()[3].value == UnaryOp::Neg.into());
+    assert!(tokenize("div(7, 2)").unwrap()[1].value == Function::NamedFunc("div".to_string()).into());
+}
+
+#[test]
+fn test_tokenize_7() {
+    let input = "3!-!x%";
+    let output: Vec<Token> = vec![
+        Token::Start,
+        Value::Scalar(3.0).into(),
+        PostfixOp::Factorial.into(),
+        BinaryOp::Sub.into(),
+        UnaryOp::Not.into(),
+        Value::Var("x".to_string()).into(),
+        PostfixOp::Percent.into(),
+        Token::End,
+    ];
+    assert!(tokenize(input).unwrap() == output, "Got {:?}", tokenize(input));
+
+    let values = |input: &str| tokenize(input).unwrap().into_iter().map(|t| t.value).collect::<Vec<Token>>();
+    assert!(values("20%*150")[2] == PostfixOp::Percent.into() && values("(x)%")[4] == PostfixOp::Percent.into());
+    // Whitespace never changes what "%" means
+    for input in ["20% - 5", "20%-5", "20% -5", "20%- 5"] {
+        assert!(values(input)[2] == PostfixOp::Percent.into() && values(input)[3] == BinaryOp::Sub.into(), "{input} was not a subtraction");
+    }
+    assert!(values("50%x")[2] == PostfixOp::Percent.into() && values("7 % (2)")[2] == PostfixOp::Percent.into());
+    assert!(values("7 % 3")[2] == PostfixOp::Percent.into() && values("7 mod 3")[2] == BinaryOp::Mod.into());
+    assert!(values("f(x)! != 2")[5] == PostfixOp::Factorial.into() && values("f(x)! != 2")[6] == BinaryOp::Ne.into());
+}
+
+#[test]
+fn test_tokenize_8() {
+    let value = |input: &str| tokenize(input).unwrap()[1].value.clone();
+    let scalar = |x: f64| Token::from(Value::Scalar(x));
+    let decimal = |text: &str| Token::from(Value::Decimal(text.to_string()));
+
+    assert!(value("6.02e23") == decimal("6.02e23") && value("1.5E-9") == decimal("1.5E-9") && value("2e+3") == scalar(2000.0));
+    // Only literals an f64 holds exactly become scalars, the rest keep their digits
+    assert!(value("0.1") == decimal("0.1") && value("1e30") == decimal("1e30") && value("1_000.000_1") == decimal("1000.0001"));
+    assert!(value("0.375") == scalar(0.375) && value("1e22") == scalar(1e22) && value("2.5e-3_00") == decimal("2.5e-300"));
+    assert!(value("0xFF") == scalar(255.0) && value("0b1011") == scalar(11.0) && value("0o17") == scalar(15.0));
+    assert!(value("1_000_000") == scalar(1e6) && value("0xFF_FF") == scalar(65535.0) && value("1_000.5e1_0") == scalar(1000.5e10));
+    assert!(value(".5") == scalar(0.5) && value("5.") == scalar(5.0));
+    assert!(value("0x1_0000_0000_0000_0000") == Value::Integer("18446744073709551616".parse().unwrap()).into());
+    // Without digits after it, "e" is the constant, so "2e" is still an implicit product
+    assert!(tokenize("2e").unwrap()[2].value == Value::Var("e".to_string()).into());
+    assert!(tokenize("2e-x").unwrap()[3].value == BinaryOp::Sub.into());
+
+    for (input, span) in [
+        (".", (0, 1)), ("1.2.3", (0, 5)), ("1__0", (0, 4)), ("1_", (0, 2)), ("2 + 3_.5", (4, 8)), ("1e5_", (0, 4)),
+        ("0x", (0, 2)), ("0xFG", (0, 4)), ("0b102", (0, 5)), ("0o8", (0, 3)), ("0x_1", (0, 4)),
+    ] {
+        assert!(
+            matches!(tokenize(input), Err(TokenizerError::InvalidNumber(_, s)) if s == Span::new(span.0, span.1)),
+            "Got {:?} for {input}", tokenize(input),
+        );
+    }
+    assert!(matches!(tokenize("\u{0663}"), Err(TokenizerError::IncorrectCharacter(..))));
+}
@@ -1,5 +1,10 @@
 use std::fmt::Display;
 
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use crate::{evaluator::EvalError, number::Number, span::{Span, Spanned}};
+
 
 #[derive(Debug, Clone)]
 pub struct ExpressionBuilder {
@@ -25,13 +30,23 @@ impl Display for ExpressionBuilder {
     }
 }
 
+impl Default for ExpressionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ExpressionBuilder {
     pub fn new() -> Self {
         ExpressionBuilder{vec: Vec::new()}
     }
 
-    pub fn collect(self) -> Vec<Token> {
-        self.vec
+    // Each token spans the column of its index, as if every token were one character wide.
+    pub fn collect(self) -> Vec<Spanned<Token>> {
+        self.vec.into_iter()
+            .enumerate()
+            .map(|(i, token)| Spanned::new(token, Span::new(i, i + 1)))
+            .collect()
     }
 
     pub fn start(mut self) -> Self {
@@ -79,6 +94,7 @@ impl ExpressionBuilder {
         self
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn neg(mut self) -> Self {
         self.vec.push(Token::Func(Function::UnaryOp(UnaryOp::Neg)));
         self
@@ -89,7 +105,7 @@ impl ExpressionBuilder {
         self
     }
 
-    pub fn scalar(mut self, scalar: f32) -> Self {
+    pub fn scalar(mut self, scalar: f64) -> Self {
         self.vec.push(Token::Val(Value::Scalar(scalar)));
         self
     }
@@ -109,6 +125,16 @@ impl ExpressionBuilder {
         self
     }
 
+    pub fn lsquare(mut self) -> Self {
+        self.vec.push(Token::Glyph(Glyph::LSquare));
+        self
+    }
+
+    pub fn rsquare(mut self) -> Self {
+        self.vec.push(Token::Glyph(Glyph::RSquare));
+        self
+    }
+
     pub fn comma(mut self) -> Self {
         self.vec.push(Token::Glyph(Glyph::Comma));
         self
@@ -145,6 +171,7 @@ pub enum Function {
     Assign,
     BinaryOp(BinaryOp),
     UnaryOp(UnaryOp),
+    PostfixOp(PostfixOp),
     NamedFunc(String),
 }
 
@@ -154,32 +181,56 @@ impl Display for Function {
             Self::Assign => write!(f, "Assign"),
             Self::BinaryOp(op) => op.fmt(f),
             Self::UnaryOp(op) => op.fmt(f),
+            Self::PostfixOp(op) => op.fmt(f),
             Self::NamedFunc(name) => write!(f, "NamedFunc({})", name) 
         }
     }
 }
 
-impl Into<Token> for Function {
-    fn into(self) -> Token {
-        Token::Func(self)
+impl From<Function> for Token {
+    fn from(value: Function) -> Self {
+        Token::Func(value)
     }
 }
 
+// Which side operators of equal precedence group to, "a - b - c" is "(a - b) - c" but "a^b^c" is "a^(b^c)".
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
 impl Function {
+    // Unary minus sits between "*" and "^", so "-2^2" is "-(2^2)" and "-2*3" is "(-2)*3".
+    // Comparisons are below arithmetic and above "&&", which is above "||".
+    // Postfix operators bind tightest, "-3!" is "-(3!)" and "2^3!" is "2^(3!)".
     pub const fn presedence(&self) -> i32 {
         match self {
             Self::Assign => -1,
             Self::BinaryOp(op) => {
                 match op {
-                    BinaryOp::Add => 0,
-                    BinaryOp::Sub => 0,
-                    BinaryOp::Mul => 1,
-                    BinaryOp::Div => 1,
-                    BinaryOp::Pow => 2,
+                    BinaryOp::Or => 0,
+                    BinaryOp::And => 1,
+                    BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::Ne => 2,
+                    BinaryOp::Add => 3,
+                    BinaryOp::Sub => 3,
+                    BinaryOp::Mul => 4,
+                    BinaryOp::Div => 4,
+                    BinaryOp::Mod => 4,
+                    BinaryOp::FloorDiv => 4,
+                    BinaryOp::Pow => 6,
                 }
             }
-            Self::UnaryOp(UnaryOp::Neg) => 1,
-            Self::NamedFunc(_) => 3,
+            Self::UnaryOp(UnaryOp::Neg | UnaryOp::Not) => 5,
+            Self::NamedFunc(_) => 7,
+            Self::PostfixOp(_) => 8,
+        }
+    }
+
+    pub const fn associativity(&self) -> Associativity {
+        match self {
+            Self::Assign | Self::BinaryOp(BinaryOp::Pow) | Self::UnaryOp(_) => Associativity::Right,
+            Self::BinaryOp(_) | Self::PostfixOp(_) | Self::NamedFunc(_) => Associativity::Left,
         }
     }
 }
@@ -192,7 +243,76 @@ pub enum BinaryOp {
     Sub,
     Mul,
     Div,
+    Mod,
+    FloorDiv,
     Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub const fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "mod",
+            Self::FloorDiv => "div",
+            Self::Pow => "^",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::And => "&&",
+            Self::Or => "||",
+        }
+    }
+
+    pub const fn is_comparison(&self) -> bool {
+        matches!(self, Self::Lt | Self::Le | Self::Gt | Self::Ge | Self::Eq | Self::Ne)
+    }
+
+    // The operator on two scalars, "&&" and "||" look at both sides here, short-circuiting is up to the caller.
+    pub fn apply<N: Number>(&self, a: N, b: N) -> N {
+        match self {
+            Self::Add => a + b,
+            Self::Sub => a - b,
+            Self::Mul => a * b,
+            Self::Div => a / b,
+            Self::Mod => a.modulo(b),
+            Self::FloorDiv => a.div_floor(b),
+            Self::Pow => a.pow(b),
+            Self::Lt => N::from_bool(a < b),
+            Self::Le => N::from_bool(a <= b),
+            Self::Gt => N::from_bool(a > b),
+            Self::Ge => N::from_bool(a >= b),
+            Self::Eq => N::from_bool(a == b),
+            Self::Ne => N::from_bool(a != b),
+            Self::And => N::from_bool(a.is_true() && b.is_true()),
+            Self::Or => N::from_bool(a.is_true() || b.is_true()),
+        }
+    }
+
+    // Like "apply", but a remainder or floor division by zero is an error rather than NaN or infinity,
+    // and so is ordering a value that is not real.
+    pub fn try_apply<N: Number>(&self, a: N, b: N) -> Result<N, EvalError> {
+        let invalid = |reason: String| EvalError::InvalidArgument(self.symbol().to_string(), reason);
+        match self {
+            Self::Mod | Self::FloorDiv if b == N::from_f64(0.0) => Err(invalid("the divisor is 0".to_string())),
+            Self::Lt | Self::Le | Self::Gt | Self::Ge if !a.is_real() => Err(invalid(format!("{a} is not real"))),
+            Self::Lt | Self::Le | Self::Gt | Self::Ge if !b.is_real() => Err(invalid(format!("{b} is not real"))),
+            op => Ok(op.apply(a, b)),
+        }
+    }
 }
 
 impl Display for BinaryOp {
@@ -202,20 +322,30 @@ impl Display for BinaryOp {
             Self::Sub => write!(f, "Sub"),
             Self::Mul => write!(f, "Mul"),
             Self::Div => write!(f, "Div"),
+            Self::Mod => write!(f, "Mod"),
+            Self::FloorDiv => write!(f, "FloorDiv"),
             Self::Pow => write!(f, "Pow"),
+            Self::Lt => write!(f, "Lt"),
+            Self::Le => write!(f, "Le"),
+            Self::Gt => write!(f, "Gt"),
+            Self::Ge => write!(f, "Ge"),
+            Self::Eq => write!(f, "Eq"),
+            Self::Ne => write!(f, "Ne"),
+            Self::And => write!(f, "And"),
+            Self::Or => write!(f, "Or"),
         }
     }
 }
 
-impl Into<Function> for BinaryOp {
-    fn into(self) -> Function {
-        Function::BinaryOp(self)
+impl From<BinaryOp> for Function {
+    fn from(value: BinaryOp) -> Self {
+        Function::BinaryOp(value)
     }
 }
 
-impl Into<Token> for BinaryOp {
-    fn into(self) -> Token {
-        Token::Func(Function::BinaryOp(self))
+impl From<BinaryOp> for Token {
+    fn from(value: BinaryOp) -> Self {
+        Token::Func(Function::BinaryOp(value))
     }
 }
 
@@ -224,33 +354,95 @@ impl Into<Token> for BinaryOp {
 #[derive(Debug, PartialEq, Clone)]
 pub enum UnaryOp {
     Neg,
+    Not,
 }
 
 impl Display for UnaryOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Neg => write!(f, "Neg"),
+            Self::Not => write!(f, "Not"),
         }
     }
 }
 
-impl Into<Function> for UnaryOp {
-    fn into(self) -> Function {
-        Function::UnaryOp(self)
+impl From<UnaryOp> for Function {
+    fn from(value: UnaryOp) -> Self {
+        Function::UnaryOp(value)
+    }
+}
+
+impl From<UnaryOp> for Token {
+    fn from(value: UnaryOp) -> Self {
+        Token::Func(Function::UnaryOp(value))
     }
 }
 
-impl Into<Token> for UnaryOp {
-    fn into(self) -> Token {
-        Token::Func(Function::UnaryOp(self))
+
+
+// Operators written after their operand, "5!" and "20%".
+// "%" is only ever a percentage, the remainder is BinaryOp::Mod, written "mod".
+#[derive(Debug, PartialEq, Clone)]
+pub enum PostfixOp {
+    Factorial,
+    Percent,
+}
+
+impl PostfixOp {
+    pub const fn symbol(&self) -> &'static str {
+        match self {
+            Self::Factorial => "!",
+            Self::Percent => "%",
+        }
+    }
+
+    pub fn apply<N: Number>(&self, x: N) -> N {
+        match self {
+            Self::Factorial => x.factorial(),
+            Self::Percent => x / N::from_f64(100.0),
+        }
     }
 }
 
+impl Display for PostfixOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Factorial => write!(f, "Factorial"),
+            Self::Percent => write!(f, "Percent"),
+        }
+    }
+}
+
+impl From<PostfixOp> for Function {
+    fn from(value: PostfixOp) -> Self {
+        Function::PostfixOp(value)
+    }
+}
+
+impl From<PostfixOp> for Token {
+    fn from(value: PostfixOp) -> Self {
+        Token::Func(Function::PostfixOp(value))
+    }
+}
+
+
+
+// Whole numbers from 2^53 on may lose digits as an f64, so they are kept as integers.
+pub(crate) fn exact_f64(x: &BigInt) -> Option<f64> {
+    match x.bits() <= 53 {
+        true => x.to_f64(),
+        false => None,
+    }
+}
 
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
-    Scalar(f32),
+    Scalar(f64),
+    // Whole number too large for an f64 to hold exactly
+    Integer(BigInt),
+    // Decimal literal an f64 does not hold exactly, kept as written without separators
+    Decimal(String),
     Var(String),
 }
 
@@ -258,14 +450,16 @@ impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Scalar(x) => write!(f, "Const({})", x),
+            Self::Integer(x) => write!(f, "Const({})", x),
+            Self::Decimal(x) => write!(f, "Const({})", x),
             Self::Var(name) => write!(f, "Var({})", name),
         }
     }
 }
 
-impl Into<Token> for Value {
-    fn into(self) -> Token {
-        Token::Val(self)
+impl From<Value> for Token {
+    fn from(value: Value) -> Self {
+        Token::Val(value)
     }
 }
 
@@ -275,7 +469,15 @@ impl Into<Token> for Value {
 pub enum Glyph {
     LBracket,
     RBracket,
+    LSquare,
+    RSquare,
     Comma,
+    LBrace,
+    RBrace,
+    Semicolon,
+    // Keywords of a piecewise block, "{ a if c; b otherwise }"
+    If,
+    Otherwise,
 }
 
 impl Display for Glyph {
@@ -283,13 +485,20 @@ impl Display for Glyph {
         match self {
             Self::LBracket => write!(f, "LBracket"),
             Self::RBracket => write!(f, "RBracket"),
+            Self::LSquare => write!(f, "LSquare"),
+            Self::RSquare => write!(f, "RSquare"),
             Self::Comma => write!(f, "Comma"),
+            Self::LBrace => write!(f, "LBrace"),
+            Self::RBrace => write!(f, "RBrace"),
+            Self::Semicolon => write!(f, "Semicolon"),
+            Self::If => write!(f, "If"),
+            Self::Otherwise => write!(f, "Otherwise"),
         }
     }
 }
 
-impl Into<Token> for Glyph {
-    fn into(self) -> Token {
-        Token::Glyph(self)
+impl From<Glyph> for Token {
+    fn from(value: Glyph) -> Self {
+        Token::Glyph(value)
     }
 }
\ No newline at end of file
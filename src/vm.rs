@@ -0,0 +1,378 @@
+use std::sync::Arc;
+
+use crate::{
+    app_context::Context,
+    ast::Expr,
+    compile::{Node, SlotExpr},
+    evaluator::EvalError,
+    function::Function,
+    number::{Number, Settings},
+    tokens::{BinaryOp, PostfixOp},
+};
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instr<N: Number> {
+    Push(N),
+    Load(usize),
+    // Copies the top of the stack into a slot, leaving it in place
+    Store(usize),
+    Neg,
+    Factorial,
+    Percent,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    FloorDiv,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Not,
+    // Turns the top of the stack into 1 or 0
+    Truth,
+    // Pops the condition and jumps to the instruction index when it is false
+    JumpIfFalse(usize),
+    Jump(usize),
+    // Fails with the piecewise expression none of whose clauses held
+    NoMatch(String),
+    // Function index and argument count, the arguments are the topmost values
+    Call(usize, usize),
+}
+
+
+// Stack code for one expression, lowered from a slot-compiled tree.
+pub struct Program<N: Number = f64> {
+    slots: Vec<String>,
+    pub(crate) code: Vec<Instr<N>>,
+    pub(crate) funcs: Vec<Arc<dyn Function<N>>>,
+    pub(crate) stack_size: usize,
+    // Whether the code has jumps, so rows can take different paths through it
+    pub(crate) branches: bool,
+    // Those of the context it was compiled against, installed while it runs
+    pub(crate) settings: Settings,
+}
+
+impl<N: Number> Program<N> {
+    // An assignment to one of the slots compiles to a store into it.
+    pub fn compile(expr: &Expr, slots: &[&str], context: &Context<N>) -> Result<Self, EvalError> {
+        let (target, value) = match expr.inner() {
+            Expr::Assign(name, value) => (Some(name), value.as_ref()),
+            _ => (None, expr),
+        };
+        let tree = SlotExpr::compile(value, slots, context)?;
+
+        let mut program = Program{slots: tree.slots().to_vec(), code: Vec::new(), funcs: Vec::new(), stack_size: 0, branches: false, settings: tree.settings()};
+        program.lower(tree.root(), 0);
+        if let Some(name) = target {
+            let slot = tree.slot(name).ok_or_else(|| EvalError::NotCompilable(expr.to_string()))?;
+            program.code.push(Instr::Store(slot));
+        }
+        Ok(program)
+    }
+
+    // "depth" is the number of values already on the stack when the node runs.
+    fn lower(&mut self, node: &Node<N>, depth: usize) {
+        self.stack_size = self.stack_size.max(depth + 1);
+        match node {
+            Node::Const(x) => self.code.push(Instr::Push(x.clone())),
+            Node::Slot(i) => self.code.push(Instr::Load(*i)),
+            Node::Neg(operand) => {
+                self.lower(operand, depth);
+                self.code.push(Instr::Neg);
+            }
+            Node::Not(operand) => {
+                self.lower(operand, depth);
+                self.code.push(Instr::Not);
+            }
+            Node::Postfix(op, operand) => {
+                self.lower(operand, depth);
+                self.code.push(match op {
+                    PostfixOp::Factorial => Instr::Factorial,
+                    PostfixOp::Percent => Instr::Percent,
+                });
+            }
+            // The chain of left operands is lowered in a loop, each operation after the code of the one before
+            Node::Binary(..) | Node::And(..) | Node::Or(..) => {
+                let (first, chain) = node.left_chain();
+                self.lower(first, depth);
+                for node in chain.into_iter().rev() {
+                    self.lower_operation(node, depth);
+                }
+            }
+            // Each branch leaves its one value where the other would have
+            Node::If(condition, then, otherwise) => {
+                self.lower(condition, depth);
+                let jump_else = self.jump(Instr::JumpIfFalse(0));
+                self.lower(then, depth);
+                let jump_end = self.jump(Instr::Jump(0));
+                self.patch(jump_else);
+                self.lower(otherwise, depth);
+                self.patch(jump_end);
+            }
+            Node::NoMatch(expr) => self.code.push(Instr::NoMatch(expr.clone())),
+            Node::Call(func, args) => {
+                for (i, arg) in args.iter().enumerate() {
+                    self.lower(arg, depth + i);
+                }
+                let index = match self.funcs.iter().position(|known| Arc::ptr_eq(known, func)) {
+                    Some(index) => index,
+                    None => {
+                        self.funcs.push(func.clone());
+                        self.funcs.len() - 1
+                    }
+                };
+                self.code.push(Instr::Call(index, args.len()));
+            }
+        }
+    }
+
+    // Code for an operation whose left operand is already on the stack.
+    fn lower_operation(&mut self, node: &Node<N>, depth: usize) {
+        match node {
+            Node::Binary(op, _, rhs) => {
+                self.lower(rhs, depth + 1);
+                self.code.push(match op {
+                    BinaryOp::Add => Instr::Add,
+                    BinaryOp::Sub => Instr::Sub,
+                    BinaryOp::Mul => Instr::Mul,
+                    BinaryOp::Div => Instr::Div,
+                    BinaryOp::Mod => Instr::Mod,
+                    BinaryOp::FloorDiv => Instr::FloorDiv,
+                    BinaryOp::Pow => Instr::Pow,
+                    BinaryOp::Lt => Instr::Lt,
+                    BinaryOp::Le => Instr::Le,
+                    BinaryOp::Gt => Instr::Gt,
+                    BinaryOp::Ge => Instr::Ge,
+                    BinaryOp::Eq => Instr::Eq,
+                    BinaryOp::Ne => Instr::Ne,
+                    BinaryOp::And | BinaryOp::Or => unreachable!("Logic operators compile to their own nodes"),
+                });
+            }
+            Node::And(_, rhs) => {
+                let jump_false = self.jump(Instr::JumpIfFalse(0));
+                self.lower(rhs, depth);
+                self.code.push(Instr::Truth);
+                let jump_end = self.jump(Instr::Jump(0));
+                self.patch(jump_false);
+                self.code.push(Instr::Push(N::from_bool(false)));
+                self.patch(jump_end);
+            }
+            Node::Or(_, rhs) => {
+                let jump_rhs = self.jump(Instr::JumpIfFalse(0));
+                self.code.push(Instr::Push(N::from_bool(true)));
+                let jump_end = self.jump(Instr::Jump(0));
+                self.patch(jump_rhs);
+                self.lower(rhs, depth);
+                self.code.push(Instr::Truth);
+                self.patch(jump_end);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Pushes a jump with its target left to "patch", returning where it is.
+    fn jump(&mut self, instr: Instr<N>) -> usize {
+        self.branches = true;
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+
+    // Points the jump at the next instruction to be pushed.
+    fn patch(&mut self, at: usize) {
+        let target = self.code.len();
+        match &mut self.code[at] {
+            Instr::JumpIfFalse(to) | Instr::Jump(to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn slots(&self) -> &[String] {
+        &self.slots
+    }
+
+    pub fn code(&self) -> &[Instr<N>] {
+        &self.code
+    }
+
+    // Runs on a fresh stack, use a Vm to reuse one between calls.
+    pub fn eval(&self, bindings: &mut [N]) -> Result<N, EvalError> {
+        Vm::new().run(self, bindings)
+    }
+}
+
+
+// Value stack kept between runs, so evaluating does not allocate once it has grown.
+pub struct Vm<N: Number = f64> {
+    stack: Vec<N>,
+}
+
+impl<N: Number> Default for Vm<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: Number> Vm<N> {
+    pub fn new() -> Self {
+        Vm{stack: Vec::new()}
+    }
+
+    // Bindings are given in slot order, passing fewer than there are slots is a bug in the caller and panics.
+    pub fn run(&mut self, program: &Program<N>, bindings: &mut [N]) -> Result<N, EvalError> {
+        N::scoped(&program.settings, || self.execute(program, bindings))
+    }
+
+    fn execute(&mut self, program: &Program<N>, bindings: &mut [N]) -> Result<N, EvalError> {
+        assert!(bindings.len() >= program.slots.len(), "Expected {} bindings, got {}", program.slots.len(), bindings.len());
+        let stack = &mut self.stack;
+        stack.clear();
+        stack.reserve(program.stack_size);
+
+        let mut pc = 0;
+        while let Some(instr) = program.code.get(pc) {
+            pc += 1;
+            match instr {
+                Instr::Push(x) => stack.push(x.clone()),
+                Instr::Load(i) => stack.push(bindings[*i].clone()),
+                Instr::Store(i) => bindings[*i] = stack.last().unwrap().clone(),
+                Instr::Neg => {
+                    let x = stack.pop().unwrap();
+                    stack.push(-x);
+                }
+                Instr::Factorial => Self::postfix(stack, PostfixOp::Factorial),
+                Instr::Percent => Self::postfix(stack, PostfixOp::Percent),
+                Instr::Add => Self::binary(stack, BinaryOp::Add)?,
+                Instr::Sub => Self::binary(stack, BinaryOp::Sub)?,
+                Instr::Mul => Self::binary(stack, BinaryOp::Mul)?,
+                Instr::Div => Self::binary(stack, BinaryOp::Div)?,
+                Instr::Mod => Self::binary(stack, BinaryOp::Mod)?,
+                Instr::FloorDiv => Self::binary(stack, BinaryOp::FloorDiv)?,
+                Instr::Pow => Self::binary(stack, BinaryOp::Pow)?,
+                Instr::Lt => Self::binary(stack, BinaryOp::Lt)?,
+                Instr::Le => Self::binary(stack, BinaryOp::Le)?,
+                Instr::Gt => Self::binary(stack, BinaryOp::Gt)?,
+                Instr::Ge => Self::binary(stack, BinaryOp::Ge)?,
+                Instr::Eq => Self::binary(stack, BinaryOp::Eq)?,
+                Instr::Ne => Self::binary(stack, BinaryOp::Ne)?,
+                Instr::Not => {
+                    let x = stack.pop().unwrap();
+                    stack.push(N::from_bool(!x.is_true()));
+                }
+                Instr::Truth => {
+                    let x = stack.pop().unwrap();
+                    stack.push(N::from_bool(x.is_true()));
+                }
+                Instr::JumpIfFalse(target) => {
+                    if !stack.pop().unwrap().is_true() {
+                        pc = *target;
+                    }
+                }
+                Instr::Jump(target) => pc = *target,
+                Instr::NoMatch(expr) => return Err(EvalError::NoMatchingClause(expr.clone())),
+                Instr::Call(func, count) => {
+                    let base = stack.len() - count;
+                    let result = program.funcs[*func].call_scalar(&stack[base..])?;
+                    stack.truncate(base);
+                    stack.push(result);
+                }
+            }
+        }
+
+        Ok(stack.pop().unwrap())
+    }
+
+    fn binary(stack: &mut Vec<N>, op: BinaryOp) -> Result<(), EvalError> {
+        let (b, a) = (stack.pop().unwrap(), stack.pop().unwrap());
+        stack.push(op.try_apply(a, b)?);
+        Ok(())
+    }
+
+    fn postfix(stack: &mut Vec<N>, op: PostfixOp) {
+        let x = stack.pop().unwrap();
+        stack.push(op.apply(x));
+    }
+}
+
+
+#[test]
+fn test_vm_0() {
+    use crate::engine::Engine;
+
+    let mut engine: Engine = Engine::new();
+    engine.eval("f(t) = t^2 + 1").unwrap();
+
+    let program = engine.compile_program("2*x + 1", &["x"]).unwrap();
+    assert!(program.code() == [Instr::Push(2.0), Instr::Load(0), Instr::Mul, Instr::Push(1.0), Instr::Add]);
+
+    let tree = engine.compile_slots("f(x)*max(x, y, 0, 1) + -y/2", &["x", "y"]).unwrap();
+    let program = engine.compile_program("f(x)*max(x, y, 0, 1) + -y/2", &["x", "y"]).unwrap();
+    let mut vm = Vm::new();
+    for (x, y) in [(0.0, 0.0), (1.5, -2.0), (-3.0, 4.0)] {
+        assert!(vm.run(&program, &mut [x, y]).ok() == tree.eval(&[x, y]).ok(), "Wrong result at {x}, {y}");
+    }
+
+    let program = engine.compile_program("y = sin(x)", &["x", "y"]).unwrap();
+    let mut bindings = [0.5, 0.0];
+    assert!(program.eval(&mut bindings).is_ok_and(|y| y == 0.5f64.sin()) && bindings[1] == 0.5f64.sin());
+    assert!(matches!(engine.compile_program("z = x", &["x"]).map_err(|e| e.code()), Err("E0215")));
+}
+
+#[test]
+fn test_vm_1() {
+    use crate::engine::Engine;
+
+    let mut engine: Engine = Engine::new();
+    engine.eval("price(q) = if(q < 10, 5*q, if(q < 100, 4.5*q, 4*q))").unwrap();
+    engine.eval("tariff(q) = { 2*q if q < 20; 40 + q if q < 200; 240 otherwise }").unwrap();
+    let formulas = [
+        "price(q)", "q > 3 && q <= 50 || !(q != 0)", "q < 0 || sqrt(q) > 3", "if(1 < 2, q, ln(q))",
+        "tariff(q) - { 1 if 0; q otherwise }", "q mod 7 - div(q, 4) + (q mod -3) - q div -6", "(q mod 12)! - q*15% + (q/7)!",
+    ];
+
+    for formula in formulas {
+        let tree = engine.compile_slots(formula, &["q"]).unwrap();
+        let program = engine.compile_program(formula, &["q"]).unwrap();
+        let rows: Vec<f64> = (-5..300).map(f64::from).collect();
+        let mut out = vec![0.0; rows.len()];
+        program.eval_columns(&[&rows], &mut out).unwrap();
+
+        for (q, out) in rows.iter().zip(&out) {
+            engine.set_var("q", *q);
+            let expected = engine.value(formula).unwrap().scalar().unwrap();
+            assert!(tree.eval(&[*q]).is_ok_and(|result| result == expected), "{formula} differs at {q}");
+            assert!(*out == expected, "{formula} differs in a batch at {q}");
+        }
+    }
+    // A constant condition leaves only the branch taken
+    let program = engine.compile_program("if(1 < 2, q, ln(q))", &["q"]).unwrap();
+    assert!(program.code() == [Instr::Load(0)]);
+
+    // Constant calls that fail are only an error once their branch is taken
+    let formula = "if(q > 0, q, modpow(2, 1, 0))";
+    let (tree, program) = (engine.compile_slots(formula, &["q"]).unwrap(), engine.compile_program(formula, &["q"]).unwrap());
+    assert!(tree.eval(&[1.0]).is_ok_and(|x| x == 1.0) && program.eval(&mut [1.0]).is_ok_and(|x| x == 1.0));
+    assert!(matches!(tree.eval(&[-1.0]), Err(EvalError::InvalidArgument(..))));
+    assert!(matches!(program.eval(&mut [-1.0]), Err(EvalError::InvalidArgument(..))));
+
+    let formula = "{ q if q > 0; gcd(1.5, 2) otherwise }";
+    let (tree, program) = (engine.compile_slots(formula, &["q"]).unwrap(), engine.compile_program(formula, &["q"]).unwrap());
+    assert!(tree.eval(&[1.0]).is_ok_and(|x| x == 1.0) && program.eval(&mut [1.0]).is_ok_and(|x| x == 1.0));
+    assert!(matches!(program.eval_columns(&[&[2.0, 0.0]], &mut [0.0; 2]), Err(EvalError::ExpectedInteger(..))));
+
+    // A zero divisor fails on every path, even when it is a constant
+    for formula in ["q mod (q - 1)", "q div 0"] {
+        let (tree, program) = (engine.compile_slots(formula, &["q"]).unwrap(), engine.compile_program(formula, &["q"]).unwrap());
+        assert!(matches!(tree.eval(&[1.0]), Err(EvalError::InvalidArgument(..))));
+        assert!(matches!(program.eval_columns(&[&[3.0, 1.0]], &mut [0.0; 2]), Err(EvalError::InvalidArgument(..))), "{formula}");
+    }
+
+    let program = engine.compile_program("{ 1 if q > 0; -1 if q < 0 }", &["q"]).unwrap();
+    assert!(matches!(program.eval(&mut [0.0]), Err(EvalError::NoMatchingClause(_))));
+    assert!(matches!(program.eval_columns(&[&[1.0, 0.0]], &mut [0.0; 2]), Err(EvalError::NoMatchingClause(_))));
+}